@@ -0,0 +1,111 @@
+// Measures the memory taken by the disputable transaction history on a synthetic workload
+// of deposits, compared with keeping a full `Transaction` per deposit in a `BTreeMap` per
+// client (what `Client` used to do). Run with `cargo bench --bench history_memory`, and
+// set `HISTORY_DEPOSITS` to change the default of 50M deposits.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use payment_engine::engine::history::HistoryConfig;
+use payment_engine::engine::{Engine, EngineConfig, PaymentEngine};
+use payment_engine::parse::Amount;
+use payment_engine::{Transaction, TransactionType};
+
+const CLIENTS: u32 = 1000;
+
+// Counts the bytes currently allocated, so a structure's footprint is the difference
+// before and after building it
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn deposits(count: u32) -> impl Iterator<Item = Transaction> {
+    (1..=count).map(|tx| {
+        let amount = Amount::from(i64::from(tx % 100_000) + 1);
+        Transaction::new(
+            tx,
+            (tx % CLIENTS) as u16,
+            TransactionType::Deposit,
+            Some(amount),
+        )
+    })
+}
+
+fn report(name: &str, deposits: u32, before: usize, started: Instant) {
+    let bytes = ALLOCATED.load(Ordering::Relaxed).saturating_sub(before);
+    println!(
+        "{name:<32} {:>8.1} MiB {:>6.1} bytes/deposit {:>8.2?}",
+        bytes as f64 / f64::from(1 << 20),
+        bytes as f64 / f64::from(deposits),
+        started.elapsed()
+    );
+}
+
+fn engine(deposits: u32, history: HistoryConfig, name: &str) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let started = Instant::now();
+    let mut engine = Engine::with_config(EngineConfig {
+        history,
+        ..Default::default()
+    });
+    for transaction in self::deposits(deposits) {
+        engine.process(transaction).unwrap();
+    }
+    // The stream engine's workers may still be catching up, so this is only exact for the
+    // serial one
+    report(name, deposits, before, started);
+}
+
+fn main() {
+    let deposits = std::env::var("HISTORY_DEPOSITS")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(50_000_000);
+    println!("{deposits} deposits across {CLIENTS} clients");
+
+    {
+        let before = ALLOCATED.load(Ordering::Relaxed);
+        let started = Instant::now();
+        let mut clients = HashMap::<u16, BTreeMap<u32, Transaction>>::new();
+        for transaction in self::deposits(deposits) {
+            clients
+                .entry(transaction.client_id)
+                .or_default()
+                .insert(transaction.id, transaction);
+        }
+        report("BTreeMap<tx, Transaction>", deposits, before, started);
+    }
+
+    engine(deposits, HistoryConfig::default(), "engine");
+    engine(
+        deposits,
+        HistoryConfig {
+            memory_budget: Some(64 << 20),
+            spill_dir: None,
+        },
+        "engine, --history-memory 64",
+    );
+}
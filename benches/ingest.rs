@@ -0,0 +1,66 @@
+// Compares the serde CSV path with `FastCsvReader` on the same in-memory input.
+// Run with `cargo bench --bench ingest`.
+
+use std::fmt::Write;
+use std::hint::black_box;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use payment_engine::Transaction;
+use payment_engine::fast_csv::FastCsvReader;
+
+const ROWS: u32 = 100_000;
+
+fn input() -> String {
+    let mut csv = String::from("type, client, tx, amount\n");
+    for tx in 1..=ROWS {
+        let client = tx % 1000;
+        let _ = match tx % 10 {
+            0 => writeln!(csv, "dispute, {client}, {}, ", tx - 1),
+            1..=6 => writeln!(
+                csv,
+                "deposit, {client}, {tx}, {}.{:04}",
+                tx % 500,
+                tx % 10_000
+            ),
+            _ => writeln!(
+                csv,
+                "withdrawal, {client}, {tx}, {}.{:02}",
+                tx % 50,
+                tx % 100
+            ),
+        };
+    }
+    csv
+}
+
+fn reader(input: &str) -> csv::Reader<&[u8]> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input.as_bytes())
+}
+
+fn ingest(c: &mut Criterion) {
+    let input = input();
+    let mut group = c.benchmark_group("ingest");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+
+    group.bench_function("serde", |b| {
+        b.iter(|| {
+            for row in reader(&input).deserialize::<Transaction>() {
+                black_box(row.unwrap());
+            }
+        });
+    });
+    group.bench_function("fast", |b| {
+        b.iter(|| {
+            for row in FastCsvReader::new(reader(&input)).unwrap() {
+                black_box(row.unwrap());
+            }
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, ingest);
+criterion_main!(benches);
@@ -0,0 +1,745 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::BufRead;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::parse::Amount;
+use crate::{ClientId, Transaction, TransactionId, TransactionType};
+
+// Importer for NACHA ACH files: fixed-width, 94 character records grouped as
+//
+//   1 file header
+//     5 batch header
+//       6 entry detail (+ 7 addenda, if its indicator is set)
+//     8 batch control
+//   9 file control (+ all-9 padding up to a multiple of 10 records)
+//
+// The whole file is validated against its batch and file control totals before any
+// transaction is handed out, so a corrupt file never gets partially applied.
+//
+// Entries map onto transactions like so:
+// - Credits (transaction codes x2, and x1 returns) are deposits into the receiver's
+//   account; debits (x7, and x6 returns) are withdrawals. A return is booked in its
+//   own direction, i.e., it reverses the original entry's effect on the balance.
+// - Prenotes (x3/x8) and zero-dollar entries (x4/x9, NOCs) don't move money and are
+//   skipped.
+// - The client is the entry's individual identification number.
+// - The transaction id comes from the entry's full trace number (originating bank and
+//   sequence number), see `TraceIds`. Trace numbers must be unique within a file;
+//   duplicates are rejected rather than guessed around.
+// - Amounts are in cents.
+pub const RECORD_LEN: usize = 94;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AchError {
+    #[error("line {line}: {reason}")]
+    Malformed { line: usize, reason: String },
+    #[error("line {line}: {record} {field} is {expected}, but the entries add up to {actual}")]
+    ControlMismatch {
+        line: usize,
+        record: &'static str,
+        field: &'static str,
+        expected: u64,
+        actual: u64,
+    },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryDirection {
+    Credit,
+    Debit,
+    // Prenotes and zero-dollar entries
+    NonMonetary,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AchEntry {
+    pub transaction_code: u8,
+    pub receiving_dfi: u32,
+    pub account: String,
+    pub amount_cents: u64,
+    pub individual_id: String,
+    pub individual_name: String,
+    pub trace_number: u64,
+    // Set for returns (addenda type 99): the reason code (e.g., R01) and the trace
+    // number of the entry being returned
+    pub return_reason: Option<String>,
+    pub original_trace_number: Option<u64>,
+    line: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AchBatch {
+    pub service_class: u16,
+    pub company_name: String,
+    pub batch_number: u32,
+    pub entries: Vec<AchEntry>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AchFile {
+    pub batches: Vec<AchBatch>,
+}
+
+impl AchEntry {
+    pub fn direction(&self) -> Option<EntryDirection> {
+        match self.transaction_code % 10 {
+            1 | 2 => Some(EntryDirection::Credit),
+            6 | 7 => Some(EntryDirection::Debit),
+            3 | 4 | 8 | 9 => Some(EntryDirection::NonMonetary),
+            _ => None,
+        }
+    }
+
+    pub fn is_return(&self) -> bool {
+        self.return_reason.is_some()
+    }
+
+    // `None` for entries that don't move money
+    pub fn to_transaction(&self, ids: &TraceIds) -> Result<Option<Transaction>, AchError> {
+        let action = match self.direction() {
+            Some(EntryDirection::Credit) => TransactionType::Deposit,
+            Some(EntryDirection::Debit) => TransactionType::Withdrawal,
+            _ => return Ok(None),
+        };
+        if self.amount_cents == 0 {
+            return Ok(None);
+        }
+
+        let client_id = self.individual_id.parse::<ClientId>().map_err(|_| {
+            malformed(
+                self.line,
+                format!("individual id `{}` isn't a client id", self.individual_id),
+            )
+        })?;
+        // Cents => 4 decimals. Ten digits of cents always fit.
+        let cents = i64::try_from(self.amount_cents).unwrap_or(i64::MAX);
+        let amount = Amount::from(cents * 100);
+        let id = ids.id(self.trace_number).ok_or_else(|| {
+            malformed(
+                self.line,
+                format!(
+                    "no transaction ids left for trace number {}",
+                    self.trace_number
+                ),
+            )
+        })?;
+        Ok(Some(Transaction::new(id, client_id, action, Some(amount))))
+    }
+}
+
+// Transaction ids for trace numbers, which at 15 digits don't fit in one. Every trace
+// number gets the next id from the first one on, and keeps it. Clones share the ids, so
+// files processed back to back with the same `TraceIds` never reuse an id, and starting
+// past the ids of other inputs keeps them from colliding.
+#[derive(Clone, Debug)]
+pub struct TraceIds(Arc<Mutex<TraceIdMap>>);
+
+#[derive(Debug)]
+struct TraceIdMap {
+    // `None` once every id has been given out
+    next: Option<TransactionId>,
+    ids: HashMap<u64, TransactionId>,
+}
+
+impl TraceIds {
+    pub fn starting_at(first: TransactionId) -> Self {
+        Self(Arc::new(Mutex::new(TraceIdMap {
+            next: Some(first),
+            ids: HashMap::new(),
+        })))
+    }
+
+    // `None` if the trace number is new and the ids ran out
+    pub fn id(&self, trace_number: u64) -> Option<TransactionId> {
+        let mut map = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(&id) = map.ids.get(&trace_number) {
+            return Some(id);
+        }
+        let id = map.next?;
+        map.next = id.checked_add(1);
+        map.ids.insert(trace_number, id);
+        Some(id)
+    }
+}
+
+impl Default for TraceIds {
+    fn default() -> Self {
+        Self::starting_at(1)
+    }
+}
+
+impl AchFile {
+    pub fn parse(reader: impl BufRead) -> Result<Self, AchError> {
+        let mut parser = Parser::default();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            parser.record(index + 1, line.strip_suffix('\r').unwrap_or(&line))?;
+        }
+        parser.finish()
+    }
+
+    // Monetary entries in file order
+    pub fn transactions(&self, ids: &TraceIds) -> Result<Vec<Transaction>, AchError> {
+        let mut trace_numbers = BTreeSet::new();
+        let mut transactions = Vec::new();
+        for entry in self.batches.iter().flat_map(|batch| &batch.entries) {
+            let Some(transaction) = entry.to_transaction(ids)? else {
+                continue;
+            };
+            if !trace_numbers.insert(entry.trace_number) {
+                return Err(malformed(
+                    entry.line,
+                    format!("trace number {} is used twice", entry.trace_number),
+                ));
+            }
+            transactions.push(transaction);
+        }
+        Ok(transactions)
+    }
+}
+
+fn malformed(line: usize, reason: impl Into<String>) -> AchError {
+    AchError::Malformed {
+        line,
+        reason: reason.into(),
+    }
+}
+
+// Running totals, compared against the batch and file control records
+#[derive(Clone, Copy, Debug, Default)]
+struct Totals {
+    entries_and_addenda: u64,
+    entry_hash: u64,
+    debit_cents: u64,
+    credit_cents: u64,
+}
+
+impl Totals {
+    fn add(&mut self, other: Totals) {
+        self.entries_and_addenda += other.entries_and_addenda;
+        self.entry_hash += other.entry_hash;
+        self.debit_cents += other.debit_cents;
+        self.credit_cents += other.credit_cents;
+    }
+
+    // Control records only keep the rightmost 10 digits of the hash
+    fn check(
+        &self,
+        record: &'static str,
+        line: usize,
+        entries_and_addenda: u64,
+        entry_hash: u64,
+        debit_cents: u64,
+        credit_cents: u64,
+    ) -> Result<(), AchError> {
+        let checks = [
+            (
+                "entry/addenda count",
+                entries_and_addenda,
+                self.entries_and_addenda,
+            ),
+            ("entry hash", entry_hash, self.entry_hash % 10_000_000_000),
+            ("total debit amount", debit_cents, self.debit_cents),
+            ("total credit amount", credit_cents, self.credit_cents),
+        ];
+        for (field, expected, actual) in checks {
+            if expected != actual {
+                return Err(AchError::ControlMismatch {
+                    line,
+                    record,
+                    field,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Parser {
+    file: AchFile,
+    has_header: bool,
+    // Open batch and its totals
+    batch: Option<(AchBatch, Totals)>,
+    totals: Totals,
+    // Line and block count of the file control record. Once it's read, only padding
+    // may follow.
+    file_control: Option<(usize, u64)>,
+    records: usize,
+}
+
+impl Parser {
+    fn record(&mut self, line: usize, record: &str) -> Result<(), AchError> {
+        if record.is_empty() && self.file_control.is_some() {
+            return Ok(());
+        }
+        if record.len() != RECORD_LEN || !record.is_ascii() {
+            return Err(malformed(
+                line,
+                format!("records must be {RECORD_LEN} ASCII characters"),
+            ));
+        }
+        self.records += 1;
+
+        let is_padding = record.bytes().all(|byte| byte == b'9');
+        if self.file_control.is_some() {
+            return if is_padding {
+                Ok(())
+            } else {
+                Err(malformed(line, "only padding may follow the file control"))
+            };
+        }
+
+        match (record.as_bytes()[0], self.has_header, &mut self.batch) {
+            (b'1', false, _) => {
+                if field(record, 35, 37) != "094" {
+                    return Err(malformed(line, "record size must be 094"));
+                }
+                self.has_header = true;
+            }
+            (b'5', true, None) => {
+                let batch = AchBatch {
+                    service_class: number(record, line, 2, 4, "service class code")? as u16,
+                    company_name: field(record, 5, 20).trim().to_owned(),
+                    batch_number: number(record, line, 88, 94, "batch number")? as u32,
+                    entries: Vec::new(),
+                };
+                self.batch = Some((batch, Totals::default()));
+            }
+            (b'6', true, Some((batch, totals))) => {
+                let entry = AchEntry {
+                    transaction_code: number(record, line, 2, 3, "transaction code")? as u8,
+                    receiving_dfi: number(record, line, 4, 11, "receiving DFI id")? as u32,
+                    account: field(record, 13, 29).trim().to_owned(),
+                    amount_cents: number(record, line, 30, 39, "amount")?,
+                    individual_id: field(record, 40, 54).trim().to_owned(),
+                    individual_name: field(record, 55, 76).trim().to_owned(),
+                    trace_number: number(record, line, 80, 94, "trace number")?,
+                    return_reason: None,
+                    original_trace_number: None,
+                    line,
+                };
+                match entry.direction() {
+                    Some(EntryDirection::Debit) => totals.debit_cents += entry.amount_cents,
+                    Some(EntryDirection::Credit) => totals.credit_cents += entry.amount_cents,
+                    Some(EntryDirection::NonMonetary) => {}
+                    None => {
+                        return Err(malformed(
+                            line,
+                            format!("unknown transaction code {}", entry.transaction_code),
+                        ));
+                    }
+                }
+                totals.entries_and_addenda += 1;
+                totals.entry_hash += u64::from(entry.receiving_dfi);
+                batch.entries.push(entry);
+            }
+            (b'7', true, Some((batch, totals))) => {
+                let Some(entry) = batch.entries.last_mut() else {
+                    return Err(malformed(line, "addenda without an entry"));
+                };
+                if field(record, 2, 3) == "99" {
+                    entry.return_reason = Some(field(record, 4, 6).to_owned());
+                    entry.original_trace_number =
+                        Some(number(record, line, 7, 21, "original trace number")?);
+                }
+                totals.entries_and_addenda += 1;
+            }
+            (b'8', true, Some(_)) => {
+                let Some((batch, totals)) = self.batch.take() else {
+                    unreachable!("matched an open batch");
+                };
+                if number(record, line, 88, 94, "batch number")? != u64::from(batch.batch_number) {
+                    return Err(malformed(line, "batch control doesn't match its header"));
+                }
+                totals.check(
+                    "batch control",
+                    line,
+                    number(record, line, 5, 10, "entry/addenda count")?,
+                    number(record, line, 11, 20, "entry hash")?,
+                    number(record, line, 21, 32, "total debit amount")?,
+                    number(record, line, 33, 44, "total credit amount")?,
+                )?;
+                self.totals.add(totals);
+                self.file.batches.push(batch);
+            }
+            (b'9', true, None) => {
+                let batches = number(record, line, 2, 7, "batch count")?;
+                if batches != self.file.batches.len() as u64 {
+                    return Err(AchError::ControlMismatch {
+                        line,
+                        record: "file control",
+                        field: "batch count",
+                        expected: batches,
+                        actual: self.file.batches.len() as u64,
+                    });
+                }
+                self.totals.check(
+                    "file control",
+                    line,
+                    number(record, line, 14, 21, "entry/addenda count")?,
+                    number(record, line, 22, 31, "entry hash")?,
+                    number(record, line, 32, 43, "total debit amount")?,
+                    number(record, line, 44, 55, "total credit amount")?,
+                )?;
+                // Checked once the padding is in
+                self.file_control = Some((line, number(record, line, 8, 13, "block count")?));
+            }
+            (kind, _, _) => {
+                return Err(malformed(
+                    line,
+                    format!("unexpected record type `{}`", char::from(kind)),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<AchFile, AchError> {
+        let Some((line, blocks)) = self.file_control else {
+            return Err(malformed(self.records, "missing file control record"));
+        };
+        // 10 records per block
+        let actual = self.records.div_ceil(10) as u64;
+        if blocks != actual {
+            return Err(AchError::ControlMismatch {
+                line,
+                record: "file control",
+                field: "block count",
+                expected: blocks,
+                actual,
+            });
+        }
+        Ok(self.file)
+    }
+}
+
+// 1-based, inclusive positions, exactly as in the NACHA record layouts
+fn field(record: &str, start: usize, end: usize) -> &str {
+    &record[start - 1..end]
+}
+
+fn number(
+    record: &str,
+    line: usize,
+    start: usize,
+    end: usize,
+    name: &str,
+) -> Result<u64, AchError> {
+    let digits = field(record, start, end);
+    if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(malformed(line, format!("{name} `{digits}` isn't numeric")));
+    }
+    digits
+        .parse()
+        .map_err(|_| malformed(line, format!("{name} `{digits}` isn't numeric")))
+}
+
+#[cfg(test)]
+mod ach_tests {
+    use googletest::prelude::*;
+
+    use super::*;
+    use crate::format::InputFormat;
+
+    fn entry(code: u8, rdfi: u32, cents: u64, client: &str, trace: u64, addenda: bool) -> String {
+        format!(
+            "6{code:02}{rdfi:08}1{:17}{cents:010}{client:<15}{:22}  {}{trace:015}",
+            "12345678",
+            "JANE DOE",
+            u8::from(addenda)
+        )
+    }
+
+    fn return_addenda(reason: &str, original_trace: u64) -> String {
+        format!(
+            "799{reason}{original_trace:015}{:6}09100001{:44}{:015}",
+            "", "", 91000010000004u64
+        )
+    }
+
+    // Two batches: a credit, a debit and a prenote, then the credit's return and a
+    // credit with a payment-related addenda
+    fn fixture() -> Vec<String> {
+        let mut lines = vec![
+            format!(
+                "101 091000019 1234567892610181200A094101{:23}{:23}{:8}",
+                "DEST BANK", "ORIGIN CO", ""
+            ),
+            format!(
+                "5200ORIGIN CO{:27}1234567890PPDPAYROLL   261018261018   1091000010000001",
+                ""
+            ),
+            entry(22, 9100001, 150_000, "1", 91000010000001, false),
+            entry(27, 9100001, 2550, "2", 91000010000002, false),
+            entry(23, 2100002, 0, "3", 91000010000003, false),
+            format!(
+                "820000000300203000040000000025500000001500001234567890{:25}091000010000001",
+                ""
+            ),
+            format!(
+                "5200ORIGIN CO{:27}1234567890PPDRETURNS   261018261018   1091000010000002",
+                ""
+            ),
+            entry(26, 9100001, 150_000, "1", 91000010000004, true),
+            return_addenda("R01", 91000010000001),
+            entry(22, 2100002, 99, "3", 91000010000005, true),
+            format!("705{:80}00010000005", "INVOICE 42"),
+            format!(
+                "820000000400112000030000001500000000000000991234567890{:25}091000010000002",
+                ""
+            ),
+            format!(
+                "9000002000002000000070031500007000000152550000000150099{:39}",
+                ""
+            ),
+        ];
+        lines.extend(std::iter::repeat_n("9".repeat(RECORD_LEN), 7));
+        lines
+    }
+
+    fn parse(lines: &[String]) -> std::result::Result<AchFile, AchError> {
+        AchFile::parse(lines.join("\n").as_bytes())
+    }
+
+    // Replaces the 1-based, inclusive positions `start..=end` of `lines[line]`
+    fn tamper(mut lines: Vec<String>, line: usize, start: usize, value: &str) -> Vec<String> {
+        lines[line].replace_range(start - 1..start - 1 + value.len(), value);
+        lines
+    }
+
+    #[gtest]
+    pub fn fixture_records_are_well_formed() {
+        for line in fixture() {
+            expect_that!(line.len(), eq(RECORD_LEN), "{line}");
+        }
+    }
+
+    #[gtest]
+    pub fn maps_credits_debits_and_returns_to_transactions() {
+        let file = parse(&fixture()).unwrap();
+        expect_that!(file.batches.len(), eq(2));
+        expect_that!(file.batches[0].company_name, eq("ORIGIN CO"));
+
+        let returned = &file.batches[1].entries[0];
+        expect_that!(returned.is_return(), is_true());
+        expect_that!(returned.return_reason.as_deref(), some(eq("R01")));
+        expect_that!(returned.original_trace_number, some(eq(91000010000001)));
+        expect_that!(file.batches[1].entries[1].is_return(), is_false());
+
+        let dollars = |cents: i64| Some(Amount::from(cents * 100));
+        expect_that!(
+            file.transactions(&TraceIds::default()).unwrap(),
+            elements_are![
+                eq(&Transaction::new(
+                    1,
+                    1,
+                    TransactionType::Deposit,
+                    dollars(150_000)
+                )),
+                eq(&Transaction::new(
+                    2,
+                    2,
+                    TransactionType::Withdrawal,
+                    dollars(2550)
+                )),
+                // The prenote moves no money; the return reverses transaction 1
+                eq(&Transaction::new(
+                    3,
+                    1,
+                    TransactionType::Withdrawal,
+                    dollars(150_000)
+                )),
+                eq(&Transaction::new(
+                    4,
+                    3,
+                    TransactionType::Deposit,
+                    dollars(99)
+                )),
+            ]
+        );
+    }
+
+    #[gtest]
+    pub fn validates_batch_control_totals() {
+        // Batch 1's credit total
+        let lines = tamper(fixture(), 5, 33, "000000150001");
+        expect_that!(
+            parse(&lines),
+            err(matches_pattern!(AchError::ControlMismatch {
+                line: eq(&6),
+                record: eq(&"batch control"),
+                field: eq(&"total credit amount"),
+                expected: eq(&150_001),
+                actual: eq(&150_000),
+            }))
+        );
+
+        // Batch 2's entry/addenda count, e.g., a dropped addenda
+        let lines = tamper(fixture(), 11, 5, "000003");
+        expect_that!(
+            parse(&lines),
+            err(matches_pattern!(AchError::ControlMismatch {
+                field: eq(&"entry/addenda count"),
+                ..
+            }))
+        );
+
+        // Entry hash, e.g., an entry routed to the wrong bank
+        let lines = tamper(fixture(), 2, 4, "09100002");
+        expect_that!(
+            parse(&lines),
+            err(matches_pattern!(AchError::ControlMismatch {
+                field: eq(&"entry hash"),
+                ..
+            }))
+        );
+    }
+
+    #[gtest]
+    pub fn validates_file_control_totals() {
+        let lines = tamper(fixture(), 12, 32, "000000152551");
+        expect_that!(
+            parse(&lines),
+            err(matches_pattern!(AchError::ControlMismatch {
+                record: eq(&"file control"),
+                field: eq(&"total debit amount"),
+                ..
+            }))
+        );
+
+        let lines = tamper(fixture(), 12, 2, "000003");
+        expect_that!(
+            parse(&lines),
+            err(matches_pattern!(AchError::ControlMismatch {
+                field: eq(&"batch count"),
+                ..
+            }))
+        );
+
+        let lines = tamper(fixture(), 12, 8, "000003");
+        expect_that!(
+            parse(&lines),
+            err(matches_pattern!(AchError::ControlMismatch {
+                field: eq(&"block count"),
+                expected: eq(&3),
+                actual: eq(&2),
+                ..
+            }))
+        );
+
+        // Padding is only needed to fill blocks on tape, so missing padding is fine
+        let mut lines = fixture();
+        lines.truncate(13);
+        expect_that!(parse(&lines), ok(anything()));
+    }
+
+    #[gtest]
+    pub fn rejects_malformed_structure() {
+        // Entry outside of a batch
+        let mut lines = fixture();
+        lines.remove(1);
+        expect_that!(
+            parse(&lines),
+            err(matches_pattern!(AchError::Malformed { line: eq(&2), .. }))
+        );
+
+        let mut lines = fixture();
+        lines[3].pop();
+        expect_that!(
+            parse(&lines),
+            err(matches_pattern!(AchError::Malformed { line: eq(&4), .. }))
+        );
+
+        let lines = tamper(fixture(), 2, 30, "00001500x0");
+        expect_that!(
+            parse(&lines),
+            err(matches_pattern!(AchError::Malformed { line: eq(&3), .. }))
+        );
+
+        let lines = fixture()[..12].to_vec();
+        expect_that!(parse(&lines), err(anything()));
+    }
+
+    #[gtest]
+    pub fn rejects_unmappable_entries() {
+        let ids = TraceIds::default();
+        // Not a client id
+        let lines = tamper(fixture(), 2, 40, "ABC");
+        expect_that!(
+            parse(&lines).unwrap().transactions(&ids),
+            err(matches_pattern!(AchError::Malformed { line: eq(&3), .. }))
+        );
+
+        // Same sequence number from another bank
+        let lines = tamper(fixture(), 3, 80, "02100002");
+        expect_that!(
+            parse(&lines).unwrap().transactions(&ids),
+            ok(elements_are![
+                field!(Transaction.id, eq(&1)),
+                field!(Transaction.id, eq(&2)),
+                field!(Transaction.id, eq(&3)),
+                field!(Transaction.id, eq(&4)),
+            ])
+        );
+        let lines = tamper(fixture(), 3, 88, "0000001");
+        expect_that!(
+            parse(&lines).unwrap().transactions(&ids),
+            err(matches_pattern!(AchError::Malformed { line: eq(&4), .. }))
+        );
+
+        let ids = TraceIds::starting_at(TransactionId::MAX);
+        expect_that!(
+            parse(&fixture()).unwrap().transactions(&ids),
+            err(matches_pattern!(AchError::Malformed { line: eq(&4), .. }))
+        );
+    }
+
+    #[gtest]
+    pub fn files_processed_back_to_back_get_their_own_ids() {
+        let ids = TraceIds::starting_at(100);
+        let first = parse(&fixture()).unwrap().transactions(&ids).unwrap();
+        // The same sequence numbers from another originating bank
+        let mut lines = fixture();
+        for line in [2, 3, 7, 9] {
+            lines = tamper(lines, line, 80, "02100002");
+        }
+        let second = parse(&lines).unwrap().transactions(&ids).unwrap();
+
+        let tx_ids = |transactions: &[Transaction]| {
+            transactions
+                .iter()
+                .map(|transaction| transaction.id)
+                .collect::<Vec<_>>()
+        };
+        expect_that!(
+            tx_ids(&first),
+            elements_are![eq(&100), eq(&101), eq(&102), eq(&103)]
+        );
+        expect_that!(
+            tx_ids(&second),
+            elements_are![eq(&104), eq(&105), eq(&106), eq(&107)]
+        );
+        // A file seen again keeps its ids
+        let replayed = parse(&fixture()).unwrap().transactions(&ids).unwrap();
+        expect_that!(tx_ids(&replayed), eq(&tx_ids(&first)));
+    }
+
+    #[gtest]
+    pub fn reads_ach_input_format() {
+        let transactions = InputFormat::Ach
+            .transactions(std::io::Cursor::new(fixture().join("\r\n")))
+            .collect::<Vec<_>>();
+        expect_that!(transactions.len(), eq(4));
+
+        let lines = tamper(fixture(), 12, 2, "000003");
+        let transactions = InputFormat::Ach
+            .transactions(std::io::Cursor::new(lines.join("\n")))
+            .collect::<Vec<_>>();
+        expect_that!(transactions, elements_are![err(anything())]);
+    }
+}
@@ -0,0 +1,577 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use quick_xml::Writer;
+use quick_xml::events::{BytesDecl, BytesText, Event};
+
+use crate::currency::Currency;
+use crate::engine::TransactionOutcome;
+use crate::engine::fees::FeeKind;
+use crate::fx::is_calendar_date;
+use crate::parse::Amount;
+use crate::{ClientId, ClientSnapshot, Transaction, TransactionId, TransactionType};
+
+// ISO 20022 bank-to-customer statements (camt.053.001.08), one document per client with
+// a `Stmt` per currency account.
+//
+// Entries are recorded from the outcomes of applied transactions, so rejected ones never
+// show up:
+// - Deposits, withdrawals, chargebacks, conversions, and fees are booked (`BOOK`) and
+//   move the booked balance.
+// - Disputes and resolves only move funds between available and held, so they're
+//   reported as holds and releases with status `INFO` against the available balance.
+//
+// The engine starts every run empty, but the opening balance is derived as the closing
+// balance minus the booked entries rather than assumed to be zero, so the statement
+// always adds up.
+pub const NAMESPACE: &str = "urn:iso:std:iso:20022:tech:xsd:camt.053.001.08";
+
+#[derive(Debug, thiserror::Error)]
+pub enum StatementError {
+    #[error("statement date `{0}` isn't a YYYY-MM-DD date")]
+    InvalidDate(String),
+    #[error("client {0} has funds without a currency; set a base currency for statements")]
+    MissingCurrency(ClientId),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EntryKind {
+    Deposit,
+    Withdrawal,
+    Hold,
+    Release,
+    Chargeback,
+    Conversion,
+    Fee(FeeKind),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Entry {
+    tx: TransactionId,
+    kind: EntryKind,
+    // Signed: negative amounts are debits
+    amount: Amount,
+    booked: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Statements {
+    // YYYY-MM-DD, used as the booking date of every entry
+    date: String,
+    // Currency of currency-less balances
+    default_currency: Option<Currency>,
+    entries: BTreeMap<(ClientId, Option<Currency>), Vec<Entry>>,
+}
+
+impl Statements {
+    pub fn new(date: &str) -> Result<Self, StatementError> {
+        let is_date = date.len() == 10
+            && date.bytes().enumerate().all(|(i, byte)| match i {
+                4 | 7 => byte == b'-',
+                _ => byte.is_ascii_digit(),
+            });
+        // Only sliced once `is_date` holds, so every part is digits
+        let part = |range: std::ops::Range<usize>| date[range].parse().unwrap_or_default();
+        if !is_date || !is_calendar_date(part(0..4), part(5..7), part(8..10)) {
+            return Err(StatementError::InvalidDate(date.to_owned()));
+        }
+
+        Ok(Self {
+            date: date.to_owned(),
+            default_currency: None,
+            entries: BTreeMap::new(),
+        })
+    }
+
+    pub fn with_default_currency(mut self, currency: Option<Currency>) -> Self {
+        self.default_currency = currency;
+        self
+    }
+
+    pub fn record(&mut self, transaction: &Transaction, outcome: &TransactionOutcome) {
+        let kind = match transaction.action {
+            TransactionType::Deposit => EntryKind::Deposit,
+            TransactionType::Withdrawal => EntryKind::Withdrawal,
+            TransactionType::Dispute => EntryKind::Hold,
+            TransactionType::Resolve => EntryKind::Release,
+            TransactionType::Chargeback => EntryKind::Chargeback,
+            TransactionType::Convert => EntryKind::Conversion,
+        };
+        for posting in &outcome.postings {
+            // Holds and releases leave the booked balance as is
+            let (amount, booked) = if posting.total().is_zero() {
+                (posting.available, false)
+            } else {
+                (posting.total(), true)
+            };
+            self.entries
+                .entry((posting.client, posting.currency))
+                .or_default()
+                .push(Entry {
+                    tx: posting.tx,
+                    kind,
+                    amount,
+                    booked,
+                });
+        }
+        for fee in &outcome.fees {
+            self.entries
+                .entry((fee.client, fee.currency))
+                .or_default()
+                .push(Entry {
+                    tx: fee.tx,
+                    kind: EntryKind::Fee(fee.kind),
+                    amount: -fee.amount,
+                    booked: true,
+                });
+        }
+    }
+
+    // Writes the statement of `client`'s accounts in `snapshots` (other clients' are
+    // skipped). Currency-less accounts that never moved are left out.
+    pub fn write<W: Write>(
+        &self,
+        writer: W,
+        client: ClientId,
+        snapshots: &[ClientSnapshot],
+    ) -> Result<W, StatementError> {
+        let mut accounts = Vec::new();
+        for snapshot in snapshots.iter().filter(|s| s.client == client) {
+            let entries = self
+                .entries
+                .get(&(client, snapshot.currency))
+                .map_or(&[][..], Vec::as_slice);
+            let currency = match snapshot.currency.or(self.default_currency) {
+                Some(currency) => currency,
+                None if entries.is_empty() && snapshot.total.is_zero() => continue,
+                None => return Err(StatementError::MissingCurrency(client)),
+            };
+            accounts.push((snapshot, currency, entries));
+        }
+
+        let mut writer = Writer::new_with_indent(writer, b' ', 2);
+        writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+        writer
+            .create_element("Document")
+            .with_attribute(("xmlns", NAMESPACE))
+            .write_inner_content(|writer| {
+                writer
+                    .create_element("BkToCstmrStmt")
+                    .write_inner_content(|writer| {
+                        writer
+                            .create_element("GrpHdr")
+                            .write_inner_content(|writer| {
+                                text(writer, "MsgId", &format!("{}-{client}", self.date))?;
+                                text(writer, "CreDtTm", &self.created())
+                            })?;
+                        for (snapshot, currency, entries) in &accounts {
+                            self.write_statement(writer, snapshot, *currency, entries)?;
+                        }
+                        Ok(())
+                    })?;
+                Ok(())
+            })?;
+        Ok(writer.into_inner())
+    }
+
+    fn created(&self) -> String {
+        format!("{}T00:00:00", self.date)
+    }
+
+    fn write_statement<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        snapshot: &ClientSnapshot,
+        currency: Currency,
+        entries: &[Entry],
+    ) -> std::io::Result<()> {
+        let currency = currency.as_str();
+        let booked = entries
+            .iter()
+            .filter(|entry| entry.booked)
+            .fold(Amount::ZERO, |sum, entry| sum + entry.amount);
+
+        writer
+            .create_element("Stmt")
+            .write_inner_content(|writer| {
+                let id = format!("{}-{}-{currency}", self.date, snapshot.client);
+                text(writer, "Id", &id)?;
+                text(writer, "CreDtTm", &self.created())?;
+                writer
+                    .create_element("Acct")
+                    .write_inner_content(|writer| {
+                        writer.create_element("Id").write_inner_content(|writer| {
+                            writer
+                                .create_element("Othr")
+                                .write_inner_content(|writer| {
+                                    text(writer, "Id", &snapshot.client.to_string())
+                                })?;
+                            Ok(())
+                        })?;
+                        text(writer, "Ccy", currency)
+                    })?;
+
+                self.write_balance(writer, "OPBD", snapshot.total - booked, currency)?;
+                self.write_balance(writer, "CLBD", snapshot.total, currency)?;
+                self.write_balance(writer, "CLAV", snapshot.available, currency)?;
+                for (index, entry) in entries.iter().enumerate() {
+                    self.write_entry(writer, index + 1, entry, currency)?;
+                }
+                Ok(())
+            })?;
+        Ok(())
+    }
+
+    fn write_balance<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        code: &str,
+        balance: Amount,
+        currency: &str,
+    ) -> std::io::Result<()> {
+        writer.create_element("Bal").write_inner_content(|writer| {
+            writer.create_element("Tp").write_inner_content(|writer| {
+                writer
+                    .create_element("CdOrPrtry")
+                    .write_inner_content(|writer| text(writer, "Cd", code))?;
+                Ok(())
+            })?;
+            amount(writer, balance, currency)?;
+            writer
+                .create_element("Dt")
+                .write_inner_content(|writer| text(writer, "Dt", &self.date))?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    fn write_entry<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        number: usize,
+        entry: &Entry,
+        currency: &str,
+    ) -> std::io::Result<()> {
+        let is_credit = entry.amount >= Amount::ZERO;
+        let (domain, family, sub_family) = bank_transaction_code(entry.kind, is_credit);
+        writer
+            .create_element("Ntry")
+            .write_inner_content(|writer| {
+                // A transaction can have several entries (e.g., a hold, then a chargeback),
+                // so entries are numbered and the transaction goes in `AcctSvcrRef`
+                text(writer, "NtryRef", &number.to_string())?;
+                amount(writer, entry.amount, currency)?;
+                writer.create_element("Sts").write_inner_content(|writer| {
+                    text(writer, "Cd", if entry.booked { "BOOK" } else { "INFO" })
+                })?;
+                writer
+                    .create_element("BookgDt")
+                    .write_inner_content(|writer| text(writer, "Dt", &self.date))?;
+                text(writer, "AcctSvcrRef", &entry.tx.to_string())?;
+                writer
+                    .create_element("BkTxCd")
+                    .write_inner_content(|writer| {
+                        writer
+                            .create_element("Domn")
+                            .write_inner_content(|writer| {
+                                text(writer, "Cd", domain)?;
+                                writer
+                                    .create_element("Fmly")
+                                    .write_inner_content(|writer| {
+                                        text(writer, "Cd", family)?;
+                                        text(writer, "SubFmlyCd", sub_family)
+                                    })?;
+                                Ok(())
+                            })?;
+                        Ok(())
+                    })?;
+                text(writer, "AddtlNtryInf", description(entry.kind))
+            })?;
+        Ok(())
+    }
+}
+
+// `Amt` is always positive, with the sign in `CdtDbtInd`
+fn amount<W: Write>(writer: &mut Writer<W>, amount: Amount, currency: &str) -> std::io::Result<()> {
+    let (magnitude, indicator) = if amount < Amount::ZERO {
+        (-amount, "DBIT")
+    } else {
+        (amount, "CRDT")
+    };
+    writer
+        .create_element("Amt")
+        .with_attribute(("Ccy", currency))
+        .write_text_content(BytesText::new(&magnitude.to_string()))?;
+    text(writer, "CdtDbtInd", indicator)
+}
+
+fn text<W: Write>(writer: &mut Writer<W>, name: &str, value: &str) -> std::io::Result<()> {
+    writer
+        .create_element(name)
+        .write_text_content(BytesText::new(value))?;
+    Ok(())
+}
+
+// ISO 20022 external bank transaction domain, family, and sub-family codes
+fn bank_transaction_code(
+    kind: EntryKind,
+    is_credit: bool,
+) -> (&'static str, &'static str, &'static str) {
+    match (kind, is_credit) {
+        (EntryKind::Deposit, _) => ("PMNT", "RCDT", "OTHR"),
+        (EntryKind::Withdrawal, _) => ("PMNT", "ICDT", "OTHR"),
+        // Reversal of a received (deposit) or issued (withdrawal) transfer
+        (EntryKind::Chargeback, false) => ("PMNT", "RCDT", "RRTN"),
+        (EntryKind::Chargeback, true) => ("PMNT", "ICDT", "RRTN"),
+        (EntryKind::Hold | EntryKind::Release, false) => ("ACMT", "MDOP", "OTHR"),
+        (EntryKind::Hold | EntryKind::Release, true) => ("ACMT", "MCOP", "OTHR"),
+        (EntryKind::Conversion, _) => ("FORX", "SPOT", "OTHR"),
+        (EntryKind::Fee(_), _) => ("ACMT", "MDOP", "CHRG"),
+    }
+}
+
+fn description(kind: EntryKind) -> &'static str {
+    match kind {
+        EntryKind::Deposit => "Deposit",
+        EntryKind::Withdrawal => "Withdrawal",
+        EntryKind::Hold => "Hold for dispute",
+        EntryKind::Release => "Release of disputed funds",
+        EntryKind::Chargeback => "Chargeback",
+        EntryKind::Conversion => "Currency conversion",
+        EntryKind::Fee(FeeKind::Deposit) => "Deposit fee",
+        EntryKind::Fee(FeeKind::Withdrawal) => "Withdrawal fee",
+        EntryKind::Fee(FeeKind::Chargeback) => "Chargeback fee",
+        EntryKind::Fee(FeeKind::FxSpread) => "FX spread",
+    }
+}
+
+// Drives the serial engine, which is the only one that reports per-transaction outcomes
+#[cfg(all(test, not(feature = "stream")))]
+mod camt_tests {
+    use std::sync::Arc;
+
+    use googletest::prelude::*;
+    use quick_xml::Reader;
+
+    use super::*;
+    use crate::engine::fees::FeeSchedule;
+    use crate::engine::{Engine, EngineConfig, PaymentEngine};
+    use crate::fx::FxRateTable;
+    use crate::parse::Rate;
+
+    fn usd() -> Currency {
+        Currency::new("USD").unwrap()
+    }
+
+    fn amount(amount: f64) -> Option<Amount> {
+        Amount::new(amount).ok()
+    }
+
+    // Statements and final snapshots for `transactions`; rejected transactions are skipped
+    fn process(
+        config: EngineConfig,
+        transactions: &[Transaction],
+    ) -> (Statements, Vec<ClientSnapshot>) {
+        let mut statements = Statements::new("2026-10-18")
+            .unwrap()
+            .with_default_currency(config.base_currency);
+        let mut engine = Engine::with_config(config);
+        for transaction in transactions {
+            if let Ok(outcome) = engine.process_with_outcome(transaction.clone()) {
+                statements.record(transaction, &outcome);
+            }
+        }
+        let snapshots = engine.finalize().into_iter().map(Result::unwrap).collect();
+        (statements, snapshots)
+    }
+
+    fn write(statements: &Statements, client: ClientId, snapshots: &[ClientSnapshot]) -> String {
+        String::from_utf8(statements.write(Vec::new(), client, snapshots).unwrap()).unwrap()
+    }
+
+    fn disputes() -> (Statements, Vec<ClientSnapshot>) {
+        let config = EngineConfig {
+            base_currency: Some(usd()),
+            ..Default::default()
+        };
+        process(
+            config,
+            &[
+                Transaction::new(1, 1, TransactionType::Deposit, amount(100.0)),
+                Transaction::new(2, 1, TransactionType::Withdrawal, amount(25.5)),
+                Transaction::new(3, 1, TransactionType::Deposit, amount(40.0)),
+                Transaction::new(3, 1, TransactionType::Dispute, None),
+                Transaction::new(3, 1, TransactionType::Chargeback, None),
+                Transaction::new(4, 2, TransactionType::Deposit, amount(10.0)),
+                Transaction::new(4, 2, TransactionType::Dispute, None),
+                Transaction::new(4, 2, TransactionType::Resolve, None),
+                // Insufficient funds, so it's not on the statement
+                Transaction::new(5, 2, TransactionType::Withdrawal, amount(50.0)),
+            ],
+        )
+    }
+
+    #[gtest]
+    pub fn matches_sample_statements_with_holds_and_chargebacks() {
+        let (statements, snapshots) = disputes();
+        expect_that!(
+            write(&statements, 1, &snapshots),
+            eq(include_str!("../tests/fixtures/camt053/chargeback.xml"))
+        );
+        expect_that!(
+            write(&statements, 2, &snapshots),
+            eq(include_str!(
+                "../tests/fixtures/camt053/dispute-resolved.xml"
+            ))
+        );
+    }
+
+    #[gtest]
+    pub fn matches_sample_statement_with_fees_and_conversions() {
+        let eur = Currency::new("EUR").unwrap();
+        let rates = csv::Reader::from_reader("from,to,rate\nUSD,EUR,0.9\n".as_bytes());
+        let config = EngineConfig {
+            fee_schedule: FeeSchedule {
+                deposit_flat: Amount::new(0.5).unwrap(),
+                withdrawal_flat: Amount::new(1.0).unwrap(),
+                withdrawal_rate: Rate::new(0.01).unwrap(),
+                ..Default::default()
+            },
+            fx_rates: Arc::new(FxRateTable::from_reader(rates).unwrap()),
+            fx_spread: Rate::new(0.01).unwrap(),
+            ..Default::default()
+        };
+        let (statements, snapshots) = process(
+            config,
+            &[
+                Transaction::new(1, 7, TransactionType::Deposit, amount(100.0)).in_currency(usd()),
+                Transaction::new(2, 7, TransactionType::Convert, amount(50.0))
+                    .in_currency(usd())
+                    .to_currency(eur),
+                Transaction::new(3, 7, TransactionType::Withdrawal, amount(10.0)).in_currency(eur),
+            ],
+        );
+        expect_that!(
+            write(&statements, 7, &snapshots),
+            eq(include_str!(
+                "../tests/fixtures/camt053/fees-and-conversion.xml"
+            ))
+        );
+    }
+
+    // Opening balance + booked entries = closing balance, for every `Stmt` in `xml`
+    fn check_balances(xml: &str) -> Vec<(String, i64, i64)> {
+        let mut reader = Reader::from_str(xml);
+        reader.config_mut().trim_text(true);
+
+        let mut path = Vec::<String>::new();
+        let (mut amount, mut status) = (0i64, String::new());
+        let (mut opening, mut booked, mut closing) = (0i64, 0i64, 0i64);
+        let mut balance_code = String::new();
+        let mut statements = Vec::new();
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Start(start) => {
+                    path.push(String::from_utf8(start.name().as_ref().to_vec()).unwrap());
+                }
+                Event::Text(text) => {
+                    let text = text.decode().unwrap().into_owned();
+                    match path
+                        .iter()
+                        .rev()
+                        .take(2)
+                        .map(String::as_str)
+                        .collect::<Vec<_>>()[..]
+                    {
+                        ["Amt", _] => amount = text.parse::<Amount>().unwrap().units(),
+                        ["CdtDbtInd", _] if text == "DBIT" => amount = -amount,
+                        ["Cd", "CdOrPrtry"] => balance_code = text,
+                        ["Cd", "Sts"] => status = text,
+                        _ => {}
+                    }
+                }
+                Event::End(_) => match path.pop().as_deref() {
+                    Some("Bal") if balance_code == "OPBD" => opening = amount,
+                    Some("Bal") if balance_code == "CLBD" => closing = amount,
+                    Some("Ntry") if status == "BOOK" => booked += amount,
+                    Some("Stmt") => {
+                        statements.push((balance_code.clone(), opening + booked, closing));
+                        booked = 0;
+                    }
+                    _ => {}
+                },
+                Event::Eof => return statements,
+                _ => {}
+            }
+        }
+    }
+
+    #[gtest]
+    pub fn sample_statements_add_up() {
+        for xml in [
+            include_str!("../tests/fixtures/camt053/chargeback.xml"),
+            include_str!("../tests/fixtures/camt053/dispute-resolved.xml"),
+            include_str!("../tests/fixtures/camt053/fees-and-conversion.xml"),
+        ] {
+            let statements = check_balances(xml);
+            expect_that!(statements, not(is_empty()));
+            for (_, expected, actual) in statements {
+                expect_that!(expected, eq(actual));
+            }
+        }
+    }
+
+    #[gtest]
+    pub fn opening_balance_covers_unrecorded_activity() {
+        let (mut statements, snapshots) = disputes();
+        // As if client 1's first deposit was processed in an earlier run
+        statements
+            .entries
+            .get_mut(&(1, Some(usd())))
+            .unwrap()
+            .remove(0);
+        let xml = write(&statements, 1, &snapshots);
+        expect_that!(xml, contains_substring("<Amt Ccy=\"USD\">100.0000</Amt>"));
+        expect_that!(
+            check_balances(&xml),
+            elements_are![(anything(), eq(&745_000), eq(&745_000))]
+        );
+    }
+
+    #[gtest]
+    pub fn rejects_currency_less_funds_and_malformed_dates() {
+        let (statements, snapshots) = process(
+            EngineConfig::default(),
+            &[Transaction::new(
+                1,
+                1,
+                TransactionType::Deposit,
+                amount(1.0),
+            )],
+        );
+        expect_that!(
+            statements.write(Vec::new(), 1, &snapshots),
+            err(matches_pattern!(StatementError::MissingCurrency(eq(&1))))
+        );
+        let statements = statements.with_default_currency(Some(usd()));
+        expect_that!(statements.write(Vec::new(), 1, &snapshots), ok(anything()));
+
+        for date in [
+            "2026-1-18",
+            "18.10.2026",
+            "2026-10-18T00:00:00",
+            "2026-99-99",
+            "2026-00-10",
+            "2026-04-31",
+            "2026-02-29",
+        ] {
+            expect_that!(
+                Statements::new(date),
+                err(matches_pattern!(StatementError::InvalidDate(anything())))
+            );
+        }
+        expect_that!(Statements::new("2028-02-29"), ok(anything()));
+    }
+}
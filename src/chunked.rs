@@ -0,0 +1,312 @@
+use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::Context;
+use memmap2::Mmap;
+
+use crate::Transaction;
+use crate::fast_csv::FastCsvReader;
+use crate::format::{Compression, InputFormat, csv_reader};
+use crate::parse::binary::{self, BinaryReader};
+
+// Parses a large, uncompressed input file on several threads. The file is memory
+// mapped and split into chunks at line boundaries; up to `threads` chunks are parsed at
+// once and their transactions handed out strictly in file order, so the engine sees
+// exactly the same sequence as with the single threaded reader (CSV order = logical
+// order).
+//
+// Only whole lines are ever split apart, so this assumes no field contains a quoted
+// newline, which always holds for transactions.
+pub struct ChunkedReader {
+    // `None` for empty files, which can't be mapped
+    mmap: Option<Mmap>,
+    format: InputFormat,
+    // The CSV or binary header, prepended to every chunk so each one can be parsed on
+    // its own
+    header: Range<usize>,
+    // Binary chunks end at a whole record of the header's version
+    record_len: usize,
+    next_start: usize,
+    chunk_size: usize,
+    threads: usize,
+    batch: std::vec::IntoIter<anyhow::Result<Transaction>>,
+}
+
+impl ChunkedReader {
+    const DEFAULT_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+    // `None` if the file is compressed or ACH, since neither can be split
+    pub fn open(
+        path: impl AsRef<Path>,
+        format: InputFormat,
+        threads: usize,
+    ) -> anyhow::Result<Option<Self>> {
+        let file = File::open(path)?;
+        let mmap = if file.metadata()?.len() == 0 {
+            None
+        } else {
+            // SAFETY: The input must not be modified while it's being processed, which
+            // also holds for every other reader (the results would be garbage anyway).
+            Some(unsafe { Mmap::map(&file)? })
+        };
+
+        let bytes = mmap.as_deref().unwrap_or_default();
+        if format == InputFormat::Ach || Compression::from_magic(bytes).is_some() {
+            return Ok(None);
+        }
+
+        let header = match format {
+            InputFormat::Csv => 0..line_end(bytes, 0),
+            InputFormat::JsonLines => 0..0,
+            InputFormat::Binary => 0..binary::HEADER_LEN.min(bytes.len()),
+            InputFormat::Ach => unreachable!("ACH files aren't split"),
+        };
+        // A bad header is reported when the first chunk is parsed
+        let record_len = bytes
+            .first_chunk()
+            .and_then(|header| binary::record_len(header).ok())
+            .unwrap_or(binary::RECORD_LEN);
+        Ok(Some(Self {
+            record_len,
+            next_start: header.end,
+            mmap,
+            format,
+            header,
+            chunk_size: Self::DEFAULT_CHUNK_SIZE,
+            threads: threads.max(1),
+            batch: Vec::new().into_iter(),
+        }))
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.mmap.as_deref().unwrap_or_default()
+    }
+
+    // Next `threads` chunks, each ending right after a newline or a whole binary
+    // record (or at the end of file)
+    fn next_chunks(&mut self) -> Vec<Range<usize>> {
+        let len = self.bytes().len();
+        let mut chunks = Vec::with_capacity(self.threads);
+        while chunks.len() < self.threads && self.next_start < len {
+            let start = self.next_start;
+            let end = match self.format {
+                InputFormat::Csv | InputFormat::JsonLines => {
+                    line_end(self.bytes(), (start + self.chunk_size).min(len))
+                }
+                InputFormat::Binary => {
+                    let records = self.chunk_size.div_ceil(self.record_len);
+                    (start + records * self.record_len).min(len)
+                }
+                InputFormat::Ach => unreachable!("ACH files aren't split"),
+            };
+            chunks.push(start..end);
+            self.next_start = end;
+        }
+        chunks
+    }
+
+    fn parse_chunk(&self, chunk: Range<usize>) -> Vec<anyhow::Result<Transaction>> {
+        let bytes = self.bytes();
+        let context = || format!("in bytes {}..{} of the input", chunk.start, chunk.end);
+        match self.format {
+            InputFormat::Csv => {
+                let input =
+                    std::io::Read::chain(&bytes[self.header.clone()], &bytes[chunk.clone()]);
+                match FastCsvReader::new(csv_reader(input, true)) {
+                    Ok(reader) => reader.map(|row| row.with_context(context)).collect(),
+                    Err(err) => vec![Err(err).with_context(context)],
+                }
+            }
+            InputFormat::JsonLines => serde_json::Deserializer::from_slice(&bytes[chunk.clone()])
+                .into_iter()
+                .map(|row| row.with_context(context))
+                .collect(),
+            InputFormat::Binary => {
+                let input =
+                    std::io::Read::chain(&bytes[self.header.clone()], &bytes[chunk.clone()]);
+                match BinaryReader::new(input) {
+                    Ok(reader) => reader.map(|row| row.with_context(context)).collect(),
+                    Err(err) => vec![Err(err).with_context(context)],
+                }
+            }
+            InputFormat::Ach => unreachable!("ACH files aren't split"),
+        }
+    }
+}
+
+impl Iterator for ChunkedReader {
+    type Item = anyhow::Result<Transaction>;
+
+    // TODO (PERF): Threads wait for the slowest chunk of every batch. A work queue
+    // with a bounded reorder buffer would keep them all busy.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(transaction) = self.batch.next() {
+                return Some(transaction);
+            }
+
+            let chunks = self.next_chunks();
+            if chunks.is_empty() {
+                return None;
+            }
+
+            let this = &*self;
+            let parsed = std::thread::scope(|scope| {
+                let workers = chunks
+                    .into_iter()
+                    .map(|chunk| scope.spawn(move || this.parse_chunk(chunk)))
+                    .collect::<Vec<_>>();
+                // Joined in spawn order => file order
+                workers
+                    .into_iter()
+                    .flat_map(|worker| {
+                        worker
+                            .join()
+                            .unwrap_or_else(|err| std::panic::resume_unwind(err))
+                    })
+                    .collect::<Vec<_>>()
+            });
+            self.batch = parsed.into_iter();
+        }
+    }
+}
+
+// Index right after the first newline at or after `from`, or the end of `bytes`
+fn line_end(bytes: &[u8], from: usize) -> usize {
+    bytes[from..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(bytes.len(), |newline| from + newline + 1)
+}
+
+#[cfg(test)]
+mod chunked_reader_tests {
+    use std::io::Write;
+
+    use googletest::prelude::*;
+
+    use super::*;
+
+    fn temp_file(name: &str, contents: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "payment-engine-chunked-{}-{name}",
+            std::process::id()
+        ));
+        File::create(&path).unwrap().write_all(contents).unwrap();
+        path
+    }
+
+    fn csv_input(rows: u32) -> String {
+        let mut csv = String::from("type, client, tx, amount\n");
+        for tx in 1..=rows {
+            csv.push_str(&format!("deposit, {}, {tx}, {}.5\n", tx % 7, tx % 100));
+        }
+        // Invalid row, which must still come out in position
+        csv.push_str("deposit, 1, 0, 1.23456\n");
+        csv.push_str("withdrawal, 1, 99999, 1.0");
+        csv
+    }
+
+    fn single_threaded(
+        format: InputFormat,
+        input: &str,
+    ) -> Vec<std::result::Result<Transaction, ()>> {
+        format
+            .transactions(std::io::Cursor::new(input.to_owned()))
+            .map(|row| row.map_err(|_| ()))
+            .collect()
+    }
+
+    fn chunked(reader: ChunkedReader) -> Vec<std::result::Result<Transaction, ()>> {
+        reader.map(|row| row.map_err(|_| ())).collect()
+    }
+
+    #[gtest]
+    pub fn keeps_file_order_across_chunks_and_threads() {
+        let input = csv_input(1000);
+        let path = temp_file("order.csv", input.as_bytes());
+
+        for (chunk_size, threads) in [(1, 4), (100, 3), (1 << 20, 2)] {
+            let reader = ChunkedReader::open(&path, InputFormat::Csv, threads)
+                .unwrap()
+                .unwrap()
+                .with_chunk_size(chunk_size);
+            expect_that!(
+                chunked(reader),
+                eq(&single_threaded(InputFormat::Csv, &input))
+            );
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[gtest]
+    pub fn splits_json_lines() {
+        let input = (1..=200)
+            .map(|tx| format!(r#"{{"type":"deposit","client":1,"tx":{tx},"amount":1.5}}"#))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let path = temp_file("order.jsonl", input.as_bytes());
+
+        let reader = ChunkedReader::open(&path, InputFormat::JsonLines, 4)
+            .unwrap()
+            .unwrap()
+            .with_chunk_size(64);
+        let transactions = chunked(reader);
+        std::fs::remove_file(path).unwrap();
+
+        expect_that!(transactions.len(), eq(200));
+        expect_that!(
+            transactions,
+            eq(&single_threaded(InputFormat::JsonLines, &input))
+        );
+    }
+
+    #[gtest]
+    pub fn handles_empty_and_compressed_files() {
+        let empty = temp_file("empty.csv", b"");
+        let gzip = temp_file("tx.csv.gz", &[0x1f, 0x8b, 0x08, 0x00]);
+
+        let reader = ChunkedReader::open(&empty, InputFormat::Csv, 2).unwrap();
+        expect_that!(reader.map(Iterator::count), some(eq(0)));
+        expect_that!(
+            ChunkedReader::open(&gzip, InputFormat::Csv, 2)
+                .unwrap()
+                .is_none(),
+            is_true()
+        );
+        std::fs::remove_file(empty).unwrap();
+        std::fs::remove_file(gzip).unwrap();
+    }
+
+    #[gtest]
+    pub fn splits_binary_records() {
+        use crate::TransactionType;
+        use crate::parse::Amount;
+        use crate::parse::binary::BinaryWriter;
+
+        let transactions = (1..=100)
+            .map(|tx| Transaction::new(tx, 1, TransactionType::Deposit, Some(Amount::from(7))))
+            .collect::<Vec<_>>();
+        let mut writer = BinaryWriter::new(Vec::new()).unwrap();
+        for transaction in &transactions {
+            writer.write(transaction).unwrap();
+        }
+        let path = temp_file("order.petx", &writer.finish().unwrap());
+
+        // Chunk size that isn't a multiple of the record length
+        let reader = ChunkedReader::open(&path, InputFormat::Binary, 3)
+            .unwrap()
+            .unwrap()
+            .with_chunk_size(100);
+        let read = reader.map(Result::unwrap).collect::<Vec<_>>();
+        std::fs::remove_file(path).unwrap();
+
+        expect_that!(read, eq(&transactions));
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::parse::{Amount, RoundingMode};
+
+// ISO 4217 alphabetic code, stored inline so `Currency` stays `Copy` and can be
+// used as a map key without allocating.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency([u8; 3]);
+
+#[derive(Clone, Debug, thiserror::Error, PartialEq, Eq)]
+pub enum CurrencyParseError {
+    #[error("invalid currency code `{0}`, expected 3 uppercase ASCII letters")]
+    Malformed(String),
+    #[error("`{0}` is not an ISO 4217 currency code")]
+    NotIso4217(String),
+}
+
+impl Currency {
+    // Only checks the shape of the code. Whether the currency is actually accepted
+    // is up to the engine's configured `CurrencyList`.
+    pub fn new(code: &str) -> Result<Self, CurrencyParseError> {
+        match code.as_bytes() {
+            &[a, b, c] if [a, b, c].iter().all(u8::is_ascii_uppercase) => Ok(Currency([a, b, c])),
+            _ => Err(CurrencyParseError::Malformed(code.to_owned())),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Always valid UTF-8: `new` only accepts ASCII uppercase letters
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = CurrencyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Currency::new(s.trim())
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct CurrencyVisitor;
+
+        impl serde::de::Visitor<'_> for CurrencyVisitor {
+            type Value = Currency;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("3 uppercase ASCII letters")
+            }
+
+            fn visit_str<E>(self, code: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Currency::new(code)
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Str(code), &self))
+            }
+        }
+
+        deserializer.deserialize_str(CurrencyVisitor)
+    }
+}
+
+// Active ISO 4217 codes and their number of minor units (digits after the decimal).
+#[rustfmt::skip]
+const ISO_4217: &[(&str, u32)] = &[
+    ("AED", 2), ("AFN", 2), ("ALL", 2), ("AMD", 2), ("ANG", 2), ("AOA", 2), ("ARS", 2),
+    ("AUD", 2), ("AWG", 2), ("AZN", 2), ("BAM", 2), ("BBD", 2), ("BDT", 2), ("BGN", 2),
+    ("BHD", 3), ("BIF", 0), ("BMD", 2), ("BND", 2), ("BOB", 2), ("BRL", 2), ("BSD", 2),
+    ("BTN", 2), ("BWP", 2), ("BYN", 2), ("BZD", 2), ("CAD", 2), ("CDF", 2), ("CHF", 2),
+    ("CLP", 0), ("CNY", 2), ("COP", 2), ("CRC", 2), ("CUP", 2), ("CVE", 2), ("CZK", 2),
+    ("DJF", 0), ("DKK", 2), ("DOP", 2), ("DZD", 2), ("EGP", 2), ("ERN", 2), ("ETB", 2),
+    ("EUR", 2), ("FJD", 2), ("FKP", 2), ("GBP", 2), ("GEL", 2), ("GHS", 2), ("GIP", 2),
+    ("GMD", 2), ("GNF", 0), ("GTQ", 2), ("GYD", 2), ("HKD", 2), ("HNL", 2), ("HTG", 2),
+    ("HUF", 2), ("IDR", 2), ("ILS", 2), ("INR", 2), ("IQD", 3), ("IRR", 2), ("ISK", 0),
+    ("JMD", 2), ("JOD", 3), ("JPY", 0), ("KES", 2), ("KGS", 2), ("KHR", 2), ("KMF", 0),
+    ("KPW", 2), ("KRW", 0), ("KWD", 3), ("KYD", 2), ("KZT", 2), ("LAK", 2), ("LBP", 2),
+    ("LKR", 2), ("LRD", 2), ("LSL", 2), ("LYD", 3), ("MAD", 2), ("MDL", 2), ("MGA", 2),
+    ("MKD", 2), ("MMK", 2), ("MNT", 2), ("MOP", 2), ("MRU", 2), ("MUR", 2), ("MVR", 2),
+    ("MWK", 2), ("MXN", 2), ("MYR", 2), ("MZN", 2), ("NAD", 2), ("NGN", 2), ("NIO", 2),
+    ("NOK", 2), ("NPR", 2), ("NZD", 2), ("OMR", 3), ("PAB", 2), ("PEN", 2), ("PGK", 2),
+    ("PHP", 2), ("PKR", 2), ("PLN", 2), ("PYG", 0), ("QAR", 2), ("RON", 2), ("RSD", 2),
+    ("RUB", 2), ("RWF", 0), ("SAR", 2), ("SBD", 2), ("SCR", 2), ("SDG", 2), ("SEK", 2),
+    ("SGD", 2), ("SHP", 2), ("SLE", 2), ("SOS", 2), ("SRD", 2), ("SSP", 2), ("STN", 2),
+    ("SVC", 2), ("SYP", 2), ("SZL", 2), ("THB", 2), ("TJS", 2), ("TMT", 2), ("TND", 3),
+    ("TOP", 2), ("TRY", 2), ("TTD", 2), ("TWD", 2), ("TZS", 2), ("UAH", 2), ("UGX", 0),
+    ("USD", 2), ("UYU", 2), ("UZS", 2), ("VES", 2), ("VND", 0), ("VUV", 0), ("WST", 2),
+    ("XAF", 0), ("XCD", 2), ("XOF", 0), ("XPF", 0), ("YER", 2), ("ZAR", 2), ("ZMW", 2),
+    ("ZWG", 2),
+];
+
+// Currencies the engine accepts, keyed by code with the currency's minor units.
+// Defaults to every ISO 4217 currency; use `CurrencyList::only` to restrict it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CurrencyList {
+    currencies: BTreeMap<Currency, u32>,
+}
+
+impl CurrencyList {
+    pub fn iso_4217() -> Self {
+        let currencies = ISO_4217
+            .iter()
+            .filter_map(|&(code, minor_units)| Some((Currency::new(code).ok()?, minor_units)))
+            .collect();
+        Self { currencies }
+    }
+
+    // Restricts the list to `codes`, each of which must be an ISO 4217 currency
+    pub fn only<'a>(codes: impl IntoIterator<Item = &'a str>) -> Result<Self, CurrencyParseError> {
+        let iso = Self::iso_4217();
+        let mut currencies = BTreeMap::new();
+        for code in codes {
+            let currency = Currency::new(code.trim())?;
+            let minor_units = iso
+                .minor_units(currency)
+                .ok_or_else(|| CurrencyParseError::NotIso4217(code.to_owned()))?;
+            currencies.insert(currency, minor_units);
+        }
+        Ok(Self { currencies })
+    }
+
+    pub fn contains(&self, currency: Currency) -> bool {
+        self.currencies.contains_key(&currency)
+    }
+
+    pub fn minor_units(&self, currency: Currency) -> Option<u32> {
+        self.currencies.get(&currency).copied()
+    }
+
+    // Whether `amount` has no digits past `currency`'s minor units, e.g., 0.5 JPY
+    // doesn't. Currency-less amounts have no minor units to respect.
+    pub fn fits_minor_units(&self, currency: Option<Currency>, amount: Amount) -> bool {
+        self.round_to_minor_units(currency, amount, RoundingMode::Down) == Some(amount)
+    }
+
+    // Rounds `amount` to `currency`'s minor units, e.g., computed fees and conversions.
+    // `None` if rounding away from zero overflows.
+    pub fn round_to_minor_units(
+        &self,
+        currency: Option<Currency>,
+        amount: Amount,
+        rounding: RoundingMode,
+    ) -> Option<Amount> {
+        match currency.and_then(|currency| self.minor_units(currency)) {
+            Some(minor_units) => amount.round_to(minor_units, rounding),
+            None => Some(amount),
+        }
+    }
+}
+
+impl Default for CurrencyList {
+    fn default() -> Self {
+        Self::iso_4217()
+    }
+}
+
+impl FromStr for CurrencyList {
+    type Err = CurrencyParseError;
+
+    // Comma separated codes, e.g., "USD,EUR,JPY"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::only(s.split(',').filter(|code| !code.trim().is_empty()))
+    }
+}
+
+#[cfg(test)]
+mod currency_tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[gtest]
+    pub fn new_currency_rejects_malformed_codes() {
+        for code in ["usd", "US", "USDT", "U$D", ""] {
+            expect_that!(
+                Currency::new(code),
+                err(eq(&CurrencyParseError::Malformed(code.to_owned())))
+            );
+        }
+    }
+
+    #[gtest]
+    pub fn default_list_contains_iso_4217_currencies() {
+        let currencies = CurrencyList::default();
+        expect_that!(
+            currencies.minor_units(Currency::new("USD").unwrap()),
+            some(eq(2))
+        );
+        expect_that!(
+            currencies.minor_units(Currency::new("JPY").unwrap()),
+            some(eq(0))
+        );
+        expect_that!(
+            currencies.contains(Currency::new("XYZ").unwrap()),
+            is_false()
+        );
+    }
+
+    #[gtest]
+    pub fn restricted_list_only_accepts_configured_currencies() {
+        let currencies: CurrencyList = "USD, EUR".parse().unwrap();
+        expect_that!(
+            currencies.contains(Currency::new("USD").unwrap()),
+            is_true()
+        );
+        expect_that!(
+            currencies.contains(Currency::new("EUR").unwrap()),
+            is_true()
+        );
+        expect_that!(
+            currencies.contains(Currency::new("GBP").unwrap()),
+            is_false()
+        );
+    }
+
+    #[gtest]
+    pub fn restricted_list_rejects_non_iso_4217_codes() {
+        expect_that!(
+            "USD,BTC".parse::<CurrencyList>(),
+            err(eq(&CurrencyParseError::NotIso4217("BTC".to_owned())))
+        );
+    }
+
+    #[gtest]
+    pub fn amounts_must_fit_minor_units() {
+        let currencies = CurrencyList::default();
+        let usd = Currency::new("USD").ok();
+        let jpy = Currency::new("JPY").ok();
+
+        expect_that!(
+            currencies.fits_minor_units(usd, Amount::new(1.25).unwrap()),
+            is_true()
+        );
+        expect_that!(
+            currencies.fits_minor_units(usd, Amount::new(0.0001).unwrap()),
+            is_false()
+        );
+        expect_that!(
+            currencies.fits_minor_units(jpy, Amount::new(0.5).unwrap()),
+            is_false()
+        );
+        expect_that!(
+            currencies.fits_minor_units(None, Amount::new(0.0001).unwrap()),
+            is_true()
+        );
+        expect_that!(
+            currencies.round_to_minor_units(jpy, Amount::new(2.5).unwrap(), RoundingMode::HalfEven),
+            some(eq(Amount::new(2.0).unwrap()))
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+
+use super::fees::HouseAccount;
+use super::ledger::{Ledger, LedgerAccount};
+use crate::currency::Currency;
+use crate::parse::{Amount, WideAmount};
+use crate::{Balance, Client, ClientActivity, ClientId, Transaction, TransactionId};
+
+// Everything the engine needs to pick up where it left off after a restart: balances,
+// lock flags, the disputable transactions and open disputes, activity counters, and
+// the ledger's accounts. The house's revenue isn't stored since it's the sum of the
+// clients' fees.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EngineState {
+    clients: Vec<ClientState>,
+    // Missing from checkpoints taken before the ledger existed, which restore opening
+    // balances instead
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ledger: Vec<LedgerState>,
+}
+
+// Kept apart from `Client` so the on-disk format doesn't change with its in-memory
+// representation (and since JSON can't key a map by `Option<Currency>`)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct ClientState {
+    client: ClientId,
+    balances: Vec<BalanceState>,
+    locked: bool,
+    transactions: Vec<Transaction>,
+    disputes: Vec<TransactionId>,
+    activity: ClientActivity,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct BalanceState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    currency: Option<Currency>,
+    available: Amount,
+    held: Amount,
+    fees: Amount,
+}
+
+// Balances may exceed `Amount` since they add up every client's, and serde has no
+// `WideAmount` deserializer, so they're stored as raw units
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct LedgerState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    currency: Option<Currency>,
+    account: LedgerAccount,
+    units: i128,
+}
+
+impl EngineState {
+    pub fn clients(&self) -> usize {
+        self.clients.len()
+    }
+
+    // In client id order, so the same state always serializes the same way.
+    // `transactions` are the engine's disputable transactions of every client.
+    pub(crate) fn capture<'a>(
+        clients: impl IntoIterator<Item = &'a Client>,
+        transactions: Vec<Transaction>,
+        ledger: &Ledger,
+    ) -> Self {
+        let mut clients = clients
+            .into_iter()
+            .map(ClientState::from)
+            .collect::<Vec<_>>();
+        clients.sort_unstable_by_key(|c| c.client);
+        for transaction in transactions {
+            if let Ok(i) = clients.binary_search_by_key(&transaction.client_id, |c| c.client) {
+                clients[i].transactions.push(transaction);
+            }
+        }
+        let ledger = ledger
+            .accounts()
+            .map(|(currency, account, balance)| LedgerState {
+                currency,
+                account,
+                units: balance.units(),
+            })
+            .collect();
+        Self { clients, ledger }
+    }
+
+    // The clients, their disputable transactions, the house, and the ledger
+    pub(crate) fn restore(self) -> (Vec<Client>, Vec<Transaction>, HouseAccount, Ledger) {
+        let mut transactions = Vec::new();
+        let clients = self
+            .clients
+            .into_iter()
+            .map(|mut state| {
+                transactions.append(&mut state.transactions);
+                Client::from(state)
+            })
+            .collect::<Vec<_>>();
+        let house = HouseAccount::from_fees(&clients);
+        let ledger = if self.ledger.is_empty() {
+            Ledger::opening(
+                clients
+                    .iter()
+                    .flat_map(|client| client.balances.iter().map(|(&c, &b)| (c, b))),
+            )
+        } else {
+            Ledger::from_accounts(
+                self.ledger
+                    .into_iter()
+                    .map(|l| (l.currency, l.account, WideAmount::from(l.units))),
+            )
+        };
+        (clients, transactions, house, ledger)
+    }
+}
+
+impl From<&Client> for ClientState {
+    fn from(client: &Client) -> Self {
+        ClientState {
+            client: client.id,
+            balances: client
+                .balances
+                .iter()
+                .map(|(&currency, balance)| BalanceState {
+                    currency,
+                    available: balance.available,
+                    held: balance.held,
+                    fees: balance.fees,
+                })
+                .collect(),
+            locked: client.is_locked,
+            transactions: Vec::new(),
+            disputes: client.disputes.iter().copied().collect(),
+            activity: client.activity,
+        }
+    }
+}
+
+impl From<ClientState> for Client {
+    fn from(state: ClientState) -> Self {
+        let mut client = Client::new(state.client);
+        client.balances = state
+            .balances
+            .into_iter()
+            .map(|b| {
+                let balance = Balance {
+                    available: b.available,
+                    held: b.held,
+                    fees: b.fees,
+                };
+                (b.currency, balance)
+            })
+            .collect();
+        client.is_locked = state.locked;
+        client.disputes = state.disputes.into_iter().collect();
+        client.activity = state.activity;
+        client
+    }
+}
+
+#[cfg(test)]
+mod checkpoint_tests {
+    use googletest::prelude::*;
+
+    use super::*;
+    use crate::engine::fees::FeeSchedule;
+    use crate::engine::{Engine, EngineConfig, PaymentEngine};
+    use crate::parse::WideAmount;
+    use crate::{Transaction, TransactionType};
+
+    fn amount(value: f64) -> Option<Amount> {
+        Some(Amount::new(value).unwrap())
+    }
+
+    #[gtest]
+    pub fn restored_engine_continues_where_the_captured_one_left_off() {
+        let usd = Currency::new("USD").unwrap();
+        let config = EngineConfig {
+            fee_schedule: FeeSchedule {
+                deposit_flat: Amount::new(0.5).unwrap(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut engine = Engine::with_config(config.clone());
+        for transaction in [
+            Transaction::new(1, 1, TransactionType::Deposit, amount(10.0)),
+            Transaction::new(2, 1, TransactionType::Deposit, amount(5.0)).in_currency(usd),
+            Transaction::new(1, 1, TransactionType::Dispute, None),
+            Transaction::new(3, 2, TransactionType::Deposit, amount(3.0)),
+            Transaction::new(3, 2, TransactionType::Dispute, None),
+            Transaction::new(3, 2, TransactionType::Chargeback, None),
+        ] {
+            engine.process(transaction).unwrap();
+        }
+
+        let state = engine.state().unwrap();
+        let json = serde_json::to_string(&state).unwrap();
+        let mut restored = Engine::restore(config, serde_json::from_str(&json).unwrap()).unwrap();
+
+        expect_that!(restored.state(), ok(eq(&state)));
+        expect_that!(restored.snapshots(), eq(&engine.snapshots()));
+        expect_that!(restored.ledger(), eq(engine.ledger()));
+        expect_that!(restored.trial_balance(), ok(anything()));
+        expect_that!(
+            restored.house_account().revenue(None),
+            eq(WideAmount::from(Amount::new(1.0).unwrap()))
+        );
+        // The open dispute and the chargeback's lock survive
+        expect_that!(
+            restored.process_with_outcome(Transaction::new(1, 1, TransactionType::Resolve, None)),
+            ok(anything())
+        );
+        expect_that!(
+            restored.process_with_outcome(Transaction::new(
+                4,
+                2,
+                TransactionType::Deposit,
+                amount(1.0)
+            )),
+            err(anything())
+        );
+    }
+
+    #[gtest]
+    pub fn restores_checkpoints_without_a_ledger_with_opening_balances() {
+        let mut engine = Engine::default();
+        for transaction in [
+            Transaction::new(1, 1, TransactionType::Deposit, amount(10.0)),
+            Transaction::new(1, 1, TransactionType::Dispute, None),
+        ] {
+            engine.process(transaction).unwrap();
+        }
+        let mut json = serde_json::to_value(engine.state().unwrap()).unwrap();
+        json.as_object_mut().unwrap().remove("ledger");
+
+        let mut restored = Engine::restore(
+            EngineConfig::default(),
+            serde_json::from_value(json).unwrap(),
+        )
+        .unwrap();
+
+        expect_that!(restored.trial_balance(), ok(anything()));
+        expect_that!(
+            restored.process_with_outcome(Transaction::new(
+                1,
+                1,
+                TransactionType::Chargeback,
+                None
+            )),
+            ok(anything())
+        );
+        expect_that!(restored.trial_balance(), ok(anything()));
+        expect_that!(
+            restored
+                .ledger()
+                .balance(None, LedgerAccount::ExternalFunding),
+            eq(WideAmount::from(Amount::new(-10.0).unwrap()))
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::ledger::{Ledger, LedgerAccount};
+use crate::currency::Currency;
+use crate::parse::{Amount, Rate, RoundingMode, WideAmount};
+use crate::{Client, ClientId, TransactionId};
+
+// Fees charged by the business when a transaction is applied, in the transaction's
+// currency. Every fee is booked as a debit against the client's available funds and a
+// matching credit to the house (revenue) account, so no money is created or destroyed
+// by charging a fee.
+//
+// The default schedule charges nothing, which keeps the engine's behavior (and output)
+// identical to before fees existed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FeeSchedule {
+    pub deposit_flat: Amount,
+    pub withdrawal_flat: Amount,
+    // Fraction of the withdrawn amount, e.g., 0.015 => 1.5%
+    pub withdrawal_rate: Rate,
+    pub chargeback_flat: Amount,
+    // Applied when the percentage fee doesn't fit in `Amount`'s precision
+    pub rounding: RoundingMode,
+}
+
+#[derive(Clone, Copy, Debug, thiserror::Error, PartialEq, Eq)]
+#[error("The {0} can't be negative")]
+pub struct NegativeFeeError(pub &'static str);
+
+impl FeeSchedule {
+    // A negative fee would credit the client and debit the house, i.e., create money
+    pub fn validate(&self) -> Result<(), NegativeFeeError> {
+        let fees = [
+            ("deposit fee", self.deposit_flat < Amount::ZERO),
+            ("withdrawal fee", self.withdrawal_flat < Amount::ZERO),
+            ("withdrawal fee rate", self.withdrawal_rate < Rate::ZERO),
+            ("chargeback fee", self.chargeback_flat < Amount::ZERO),
+        ];
+        match fees.into_iter().find(|&(_, negative)| negative) {
+            Some((fee, _)) => Err(NegativeFeeError(fee)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !(self.deposit_flat.is_zero()
+            && self.withdrawal_flat.is_zero()
+            && self.withdrawal_rate.is_zero()
+            && self.chargeback_flat.is_zero())
+    }
+
+    pub fn deposit_fee(&self) -> Amount {
+        self.deposit_flat
+    }
+
+    // `None` if the percentage fee overflows, which can't happen for rates <= 1.0
+    // and amounts that uphold `Amount`'s invariants.
+    pub fn withdrawal_fee(&self, amount: Amount) -> Option<Amount> {
+        let percentage = amount.checked_mul_rate(self.withdrawal_rate, self.rounding)?;
+        Some(self.withdrawal_flat + percentage)
+    }
+
+    pub fn chargeback_fee(&self) -> Amount {
+        self.chargeback_flat
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeeKind {
+    Deposit,
+    Withdrawal,
+    Chargeback,
+    // Difference between the FX rate and what the client receives on a conversion
+    FxSpread,
+}
+
+// A single fee booking, reported in the outcome of the transaction that incurred it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FeeLine {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    pub kind: FeeKind,
+    pub amount: Amount,
+}
+
+// Revenue account credited with every fee charged to clients, per currency. Revenue
+// accumulates across all clients, so it's kept as a `WideAmount`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HouseAccount {
+    revenue: BTreeMap<Option<Currency>, WideAmount>,
+}
+
+impl HouseAccount {
+    pub fn revenue(&self, currency: Option<Currency>) -> WideAmount {
+        self.revenue.get(&currency).copied().unwrap_or_default()
+    }
+
+    // Moves `fee` from the client's available funds to the house, posting it to the
+    // ledger's fee revenue. Zero fees aren't booked at all so they don't clutter the
+    // outcome.
+    pub(crate) fn book(
+        &mut self,
+        ledger: &mut Ledger,
+        client: &mut Client,
+        currency: Option<Currency>,
+        tx: TransactionId,
+        kind: FeeKind,
+        fee: Amount,
+    ) -> Option<FeeLine> {
+        if fee.is_zero() {
+            return None;
+        }
+
+        ledger.post(
+            client,
+            tx,
+            currency,
+            LedgerAccount::Available,
+            LedgerAccount::FeeRevenue,
+            fee,
+        );
+        client.balance_mut(currency).fees += fee;
+        *self.revenue.entry(currency).or_default() += fee;
+        Some(FeeLine {
+            client: client.id,
+            tx,
+            currency,
+            kind,
+            amount: fee,
+        })
+    }
+
+    // Takes back the revenue of `fees`, e.g., once the transaction that charged them
+    // couldn't be stored
+    pub(crate) fn revert(&mut self, fees: &[FeeLine]) {
+        for fee in fees {
+            *self.revenue.entry(fee.currency).or_default() -= WideAmount::from(fee.amount);
+        }
+    }
+
+    // Every fee the clients were charged went to the house, e.g., when restoring the
+    // clients from a checkpoint
+    #[cfg(not(feature = "stream"))]
+    pub(crate) fn from_fees(clients: &[Client]) -> Self {
+        Self::from_balance_fees(clients.iter().flat_map(|client| {
+            client
+                .balances
+                .iter()
+                .map(|(&currency, balance)| (currency, balance.fees))
+        }))
+    }
+
+    // Same as `from_fees`, from the `fees` of every client's balance in each currency
+    #[cfg(any(not(feature = "stream"), feature = "sqlite"))]
+    pub(crate) fn from_balance_fees(
+        fees: impl IntoIterator<Item = (Option<Currency>, Amount)>,
+    ) -> Self {
+        let mut house = Self::default();
+        for (currency, fee) in fees {
+            *house.revenue.entry(currency).or_default() += fee;
+        }
+        house
+    }
+}
+
+#[cfg(test)]
+mod fee_schedule_tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[gtest]
+    pub fn default_schedule_is_disabled() {
+        let schedule = FeeSchedule::default();
+        expect_that!(schedule.is_enabled(), is_false());
+        expect_that!(
+            schedule.withdrawal_fee(Amount::new(10.0).unwrap()),
+            some(eq(Amount::ZERO))
+        );
+    }
+
+    #[gtest]
+    pub fn withdrawal_fee_is_flat_plus_percentage() {
+        let schedule = FeeSchedule {
+            withdrawal_flat: Amount::new(0.5).unwrap(),
+            withdrawal_rate: Rate::new(0.01).unwrap(),
+            ..Default::default()
+        };
+
+        expect_that!(schedule.is_enabled(), is_true());
+        expect_that!(
+            schedule.withdrawal_fee(Amount::new(123.45).unwrap()),
+            // 0.5 + 1.2345
+            some(eq(Amount::new(1.7345).unwrap()))
+        );
+    }
+
+    #[gtest]
+    pub fn rejects_negative_fees() {
+        expect_that!(FeeSchedule::default().validate(), ok(anything()));
+        let schedule = FeeSchedule {
+            deposit_flat: Amount::new(1.0).unwrap(),
+            withdrawal_rate: Rate::new(-0.01).unwrap(),
+            ..Default::default()
+        };
+        expect_that!(
+            schedule.validate(),
+            err(eq(NegativeFeeError("withdrawal fee rate")))
+        );
+        let schedule = FeeSchedule {
+            chargeback_flat: Amount::new(-5.0).unwrap(),
+            ..Default::default()
+        };
+        expect_that!(
+            schedule.validate(),
+            err(eq(NegativeFeeError("chargeback fee")))
+        );
+    }
+
+    #[gtest]
+    pub fn withdrawal_fee_rounds_with_schedule_rounding_mode() {
+        let schedule = FeeSchedule {
+            withdrawal_rate: Rate::new(0.015).unwrap(),
+            rounding: RoundingMode::Up,
+            ..Default::default()
+        };
+
+        // 0.0001 * 0.015 = 0.0000015 => rounded up to 0.0001
+        expect_that!(
+            schedule.withdrawal_fee(Amount::from(1)),
+            some(eq(Amount::from(1)))
+        );
+    }
+}
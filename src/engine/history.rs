@@ -0,0 +1,764 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use log::{debug, warn};
+
+use crate::currency::Currency;
+use crate::parse::Amount;
+use crate::{ClientId, TransactionId};
+#[cfg(not(feature = "stream"))]
+use crate::{Transaction, TransactionType};
+
+// How much of the disputable transactions (deposits and withdrawals) stays in memory.
+// Once over budget, the least recently used ones are spilled to disk and reloaded the
+// next time a dispute, resolve or chargeback references them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HistoryConfig {
+    // In bytes. `None` keeps every transaction in memory and never touches the disk.
+    pub memory_budget: Option<usize>,
+    // Where the spill file is created, the system's temp dir by default
+    pub spill_dir: Option<PathBuf>,
+}
+
+// Approximate memory taken by a cached transaction with a budget: its slot in every array
+// of `HotTransactions` and its `slots` entry with the hash table's control byte, doubled
+// since the table may be up to half empty after growing
+const ENTRY_BYTES: usize = size_of::<ClientId>()
+    + size_of::<Amount>()
+    + size_of::<Option<Currency>>()
+    + size_of::<TransactionId>()
+    + 2 * size_of::<Slot>()
+    + 2 * (size_of::<(TransactionId, Slot)>() + 1);
+
+impl HistoryConfig {
+    fn capacity(&self) -> Option<usize> {
+        self.memory_budget.map(|bytes| (bytes / ENTRY_BYTES).max(1))
+    }
+}
+
+// Everything a dispute needs from a deposit or withdrawal, in the currency it was booked
+// in. Withdrawals are negated, so disputing either moves `amount` from available to held.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Disputable {
+    pub(crate) amount: Amount,
+    pub(crate) currency: Option<Currency>,
+}
+
+impl Disputable {
+    // `None` for transactions that can't be disputed
+    #[cfg(not(feature = "stream"))]
+    pub(crate) fn of(transaction: &Transaction) -> Option<Self> {
+        let amount = transaction.amount?;
+        let amount = match transaction.action {
+            TransactionType::Deposit => amount,
+            TransactionType::Withdrawal => -amount,
+            _ => return None,
+        };
+        Some(Self {
+            amount,
+            currency: transaction.currency,
+        })
+    }
+
+    // Only the sign is kept, so a zero withdrawal comes back as a zero deposit, which
+    // disputes the same
+    #[cfg(not(feature = "stream"))]
+    fn transaction(self, client: ClientId, id: TransactionId) -> Transaction {
+        let (action, amount) = if self.amount < Amount::ZERO {
+            (TransactionType::Withdrawal, -self.amount)
+        } else {
+            (TransactionType::Deposit, self.amount)
+        };
+        let mut transaction = Transaction::new(id, client, action, Some(amount));
+        transaction.currency = self.currency;
+        transaction
+    }
+}
+
+// Disputable transactions of every client. Clones share the same store, e.g., so the
+// stream engine's workers can share a memory budget.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TransactionHistory {
+    store: Arc<Mutex<HistoryStore>>,
+}
+
+impl TransactionHistory {
+    pub(crate) fn new(config: &HistoryConfig) -> Self {
+        Self {
+            store: Arc::new(Mutex::new(HistoryStore::new(config))),
+        }
+    }
+
+    // Replaces any transaction with the same client and id, like a map would
+    pub(crate) fn insert(
+        &self,
+        client: ClientId,
+        id: TransactionId,
+        disputable: Disputable,
+    ) -> io::Result<()> {
+        self.lock().insert(client, id, disputable)
+    }
+
+    pub(crate) fn get(
+        &self,
+        client: ClientId,
+        id: TransactionId,
+    ) -> io::Result<Option<Disputable>> {
+        self.lock().get(client, id)
+    }
+
+    // Forgets the client's transaction, in memory and on disk, e.g., once it couldn't be
+    // stored
+    pub(crate) fn remove(&self, client: ClientId, id: TransactionId) -> io::Result<()> {
+        self.lock().remove(client, id)
+    }
+
+    // Every transaction, spilled or not, in client then transaction id order, e.g., to
+    // checkpoint the engine. Reads the whole spill file back into memory.
+    #[cfg(not(feature = "stream"))]
+    pub(crate) fn transactions(&self) -> io::Result<Vec<Transaction>> {
+        self.lock().transactions()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HistoryStore> {
+        self.store.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[derive(Debug)]
+struct HistoryStore {
+    hot: HotTransactions,
+    // Transactions sharing an id with another client's. They're invalid input, so rare
+    // enough to always keep in memory instead of giving them slots of their own.
+    collisions: HashMap<(ClientId, TransactionId), Disputable>,
+    spill_dir: PathBuf,
+    // Created on the first spill
+    spill: Option<SpillFile>,
+}
+
+impl Default for HistoryStore {
+    fn default() -> Self {
+        Self::new(&HistoryConfig::default())
+    }
+}
+
+impl HistoryStore {
+    fn new(config: &HistoryConfig) -> Self {
+        Self {
+            hot: HotTransactions::new(config.capacity()),
+            collisions: HashMap::new(),
+            spill_dir: config.spill_dir.clone().unwrap_or_else(std::env::temp_dir),
+            spill: None,
+        }
+    }
+
+    fn insert(
+        &mut self,
+        client: ClientId,
+        id: TransactionId,
+        disputable: Disputable,
+    ) -> io::Result<()> {
+        if self.hot.client(id).is_some_and(|cached| cached != client) {
+            self.collisions.insert((client, id), disputable);
+            return Ok(());
+        }
+        if !self.collisions.is_empty() {
+            self.collisions.remove(&(client, id));
+        }
+
+        match self.hot.insert(client, id, disputable) {
+            Some((client, id, disputable)) => self.spill(client, id, disputable),
+            None => Ok(()),
+        }
+    }
+
+    fn get(&mut self, client: ClientId, id: TransactionId) -> io::Result<Option<Disputable>> {
+        if let Some((cached, disputable)) = self.hot.get(id)
+            && cached == client
+        {
+            return Ok(Some(disputable));
+        }
+        if let Some(&disputable) = self.collisions.get(&(client, id)) {
+            return Ok(Some(disputable));
+        }
+        let Some(spill) = &mut self.spill else {
+            return Ok(None);
+        };
+        // The slot may hold another client's transaction with the same id
+        let Some((_, disputable)) = spill.read(id)?.filter(|&(spilled, _)| spilled == client)
+        else {
+            return Ok(None);
+        };
+
+        debug!("[Client {client}] Reloading spilled transaction {id}");
+        self.insert(client, id, disputable)?;
+        Ok(Some(disputable))
+    }
+
+    fn remove(&mut self, client: ClientId, id: TransactionId) -> io::Result<()> {
+        if self.hot.client(id) == Some(client) {
+            self.hot.remove(id);
+        }
+        self.collisions.remove(&(client, id));
+        // Reloaded transactions stay on disk too
+        match &mut self.spill {
+            Some(spill) => spill.remove(client, id),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(feature = "stream"))]
+    fn transactions(&mut self) -> io::Result<Vec<Transaction>> {
+        let mut transactions = self
+            .hot
+            .iter()
+            .chain(self.collisions.iter().map(|(&(c, id), &d)| (c, id, d)))
+            .map(|(client, id, disputable)| disputable.transaction(client, id))
+            .collect::<Vec<_>>();
+        if let Some(spill) = &mut self.spill {
+            for id in spill.ids() {
+                // Reloaded transactions stay on disk, but the cached one is the latest
+                if let Some((client, disputable)) = spill.read(id)?
+                    && self.hot.client(id) != Some(client)
+                    && !self.collisions.contains_key(&(client, id))
+                {
+                    transactions.push(disputable.transaction(client, id));
+                }
+            }
+        }
+        transactions.sort_unstable_by_key(|t| (t.client_id, t.id));
+        Ok(transactions)
+    }
+
+    // Writes a transaction evicted from the cache to disk, or keeps it with the
+    // collisions if its slot on disk holds another client's
+    fn spill(
+        &mut self,
+        client: ClientId,
+        id: TransactionId,
+        disputable: Disputable,
+    ) -> io::Result<()> {
+        let spill = match &mut self.spill {
+            Some(spill) => spill,
+            spill => spill.insert(SpillFile::create(&self.spill_dir)?),
+        };
+        match spill.write(client, id, disputable) {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                self.collisions.insert((client, id), disputable);
+                Ok(())
+            }
+            Err(err) => {
+                self.collisions.insert((client, id), disputable);
+                Err(err)
+            }
+        }
+    }
+}
+
+// Index into the arrays of `HotTransactions`. Transaction ids are `u32`s too, so there
+// are never more slots than fit.
+type Slot = u32;
+
+// End of the recency list
+const NIL: Slot = Slot::MAX;
+
+// Cached transactions keyed by id, with one array per field so no memory goes to padding.
+// With a capacity, the slots also form a doubly linked list from the most to the least
+// recently used, and the least recently used slot is reused once full. Without one,
+// nothing is ever evicted, so neither the links nor the slots' ids are kept at all.
+// Slots of removed transactions are reused before the arrays grow.
+#[derive(Debug)]
+struct HotTransactions {
+    capacity: Option<usize>,
+    slots: HashMap<TransactionId, Slot>,
+    clients: Vec<ClientId>,
+    amounts: Vec<Amount>,
+    currencies: Vec<Option<Currency>>,
+    // Only kept with a capacity
+    ids: Vec<TransactionId>,
+    newer: Vec<Slot>,
+    older: Vec<Slot>,
+    newest: Slot,
+    oldest: Slot,
+    // Removed transactions' slots, which are in neither `slots` nor the recency list
+    free: Vec<Slot>,
+}
+
+impl HotTransactions {
+    fn new(capacity: Option<usize>) -> Self {
+        Self {
+            capacity,
+            slots: HashMap::new(),
+            clients: Vec::new(),
+            amounts: Vec::new(),
+            currencies: Vec::new(),
+            ids: Vec::new(),
+            newer: Vec::new(),
+            older: Vec::new(),
+            newest: NIL,
+            oldest: NIL,
+            free: Vec::new(),
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn client(&self, id: TransactionId) -> Option<ClientId> {
+        self.slots.get(&id).map(|&slot| self.clients[slot as usize])
+    }
+
+    fn get(&mut self, id: TransactionId) -> Option<(ClientId, Disputable)> {
+        let slot = *self.slots.get(&id)?;
+        self.touch(slot);
+        Some(self.read(slot))
+    }
+
+    #[cfg(not(feature = "stream"))]
+    fn iter(&self) -> impl Iterator<Item = (ClientId, TransactionId, Disputable)> + '_ {
+        self.slots.iter().map(|(&id, &slot)| {
+            let (client, disputable) = self.read(slot);
+            (client, id, disputable)
+        })
+    }
+
+    // Hands back the least recently used transaction if it was evicted to make room
+    fn insert(
+        &mut self,
+        client: ClientId,
+        id: TransactionId,
+        disputable: Disputable,
+    ) -> Option<(ClientId, TransactionId, Disputable)> {
+        if let Some(&slot) = self.slots.get(&id) {
+            self.write(slot, client, disputable);
+            self.touch(slot);
+            return None;
+        }
+
+        let Some(capacity) = self.capacity else {
+            let slot = self.allocate(client, id, disputable);
+            self.slots.insert(id, slot);
+            return None;
+        };
+        if self.slots.len() < capacity {
+            let slot = self.allocate(client, id, disputable);
+            self.link_newest(slot);
+            self.slots.insert(id, slot);
+            return None;
+        }
+
+        let slot = self.oldest;
+        let evicted_id = std::mem::replace(&mut self.ids[slot as usize], id);
+        let (evicted_client, evicted) = self.read(slot);
+        self.slots.remove(&evicted_id);
+        self.write(slot, client, disputable);
+        self.touch(slot);
+        self.slots.insert(id, slot);
+        Some((evicted_client, evicted_id, evicted))
+    }
+
+    fn remove(&mut self, id: TransactionId) {
+        let Some(slot) = self.slots.remove(&id) else {
+            return;
+        };
+        if self.capacity.is_some() {
+            self.unlink(slot);
+        }
+        self.free.push(slot);
+    }
+
+    // A free slot, else a new one at the end of the arrays. Not linked yet.
+    fn allocate(&mut self, client: ClientId, id: TransactionId, disputable: Disputable) -> Slot {
+        if let Some(slot) = self.free.pop() {
+            self.write(slot, client, disputable);
+            if self.capacity.is_some() {
+                self.ids[slot as usize] = id;
+            }
+            return slot;
+        }
+
+        if let Some(capacity) = self.capacity {
+            self.reserve_up_to(capacity);
+            self.ids.push(id);
+            self.newer.push(NIL);
+            self.older.push(NIL);
+        }
+        self.push(client, disputable);
+        self.clients.len() as Slot - 1
+    }
+
+    // Grows the arrays like `Vec` would, but never past `capacity`, so they don't take
+    // up to twice the budget
+    fn reserve_up_to(&mut self, capacity: usize) {
+        let len = self.clients.len();
+        if len < self.clients.capacity() {
+            return;
+        }
+        let additional = len.max(4).min(capacity - len);
+        self.clients.reserve_exact(additional);
+        self.amounts.reserve_exact(additional);
+        self.currencies.reserve_exact(additional);
+        self.ids.reserve_exact(additional);
+        self.newer.reserve_exact(additional);
+        self.older.reserve_exact(additional);
+    }
+
+    fn push(&mut self, client: ClientId, disputable: Disputable) {
+        self.clients.push(client);
+        self.amounts.push(disputable.amount);
+        self.currencies.push(disputable.currency);
+    }
+
+    fn read(&self, slot: Slot) -> (ClientId, Disputable) {
+        let slot = slot as usize;
+        let disputable = Disputable {
+            amount: self.amounts[slot],
+            currency: self.currencies[slot],
+        };
+        (self.clients[slot], disputable)
+    }
+
+    fn write(&mut self, slot: Slot, client: ClientId, disputable: Disputable) {
+        let slot = slot as usize;
+        self.clients[slot] = client;
+        self.amounts[slot] = disputable.amount;
+        self.currencies[slot] = disputable.currency;
+    }
+
+    fn touch(&mut self, slot: Slot) {
+        if self.capacity.is_none() || self.newest == slot {
+            return;
+        }
+        self.unlink(slot);
+        self.link_newest(slot);
+    }
+
+    fn unlink(&mut self, slot: Slot) {
+        let (newer, older) = (self.newer[slot as usize], self.older[slot as usize]);
+        match newer {
+            NIL => self.newest = older,
+            newer => self.older[newer as usize] = older,
+        }
+        match older {
+            NIL => self.oldest = newer,
+            older => self.newer[older as usize] = newer,
+        }
+    }
+
+    fn link_newest(&mut self, slot: Slot) {
+        self.newer[slot as usize] = NIL;
+        self.older[slot as usize] = self.newest;
+        match self.newest {
+            NIL => self.oldest = slot,
+            newest => self.newer[newest as usize] = slot,
+        }
+        self.newest = slot;
+    }
+}
+
+// Size of a spilled transaction: a marker, padding, client, amount (in units) and
+// currency (zeroed for none), then padding. The id is where the record is.
+const RECORD_BYTES: u64 = 16;
+
+// Distinguishes spill files of different engines in the same process
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+// On-disk index of spilled transactions, addressed by transaction id: the record of
+// transaction `id` is at `id * RECORD_BYTES`. The file is sparse, so it only takes up
+// disk space for the pages spilled transactions were written to. Removed on drop.
+#[derive(Debug)]
+struct SpillFile {
+    path: PathBuf,
+    file: File,
+    // Bitset of the ids with a record, so misses (e.g., disputes of unknown
+    // transactions) don't hit the disk. Only words with a bit set are kept, so a few
+    // large ids don't take up memory for every id below them.
+    spilled: BTreeMap<usize, u64>,
+}
+
+impl SpillFile {
+    fn create(dir: &std::path::Path) -> io::Result<Self> {
+        let path = dir.join(format!(
+            "payment-engine-{}-{}.spill",
+            std::process::id(),
+            SPILL_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        debug!("Spilling transaction history to {}", path.display());
+        Ok(Self {
+            path,
+            file,
+            spilled: BTreeMap::new(),
+        })
+    }
+
+    fn contains(&self, id: TransactionId) -> bool {
+        let id = id as usize;
+        self.spilled
+            .get(&(id / 64))
+            .is_some_and(|bits| bits & (1 << (id % 64)) != 0)
+    }
+
+    #[cfg(not(feature = "stream"))]
+    fn ids(&self) -> Vec<TransactionId> {
+        let mut ids = Vec::new();
+        for (&word, &bits) in &self.spilled {
+            for bit in 0..64 {
+                if bits & (1 << bit) != 0 {
+                    // Only ever set for a `TransactionId`
+                    ids.push((word * 64 + bit) as TransactionId);
+                }
+            }
+        }
+        ids
+    }
+
+    fn read(&mut self, id: TransactionId) -> io::Result<Option<(ClientId, Disputable)>> {
+        if !self.contains(id) {
+            return Ok(None);
+        }
+        let mut record = [0; RECORD_BYTES as usize];
+        self.file
+            .seek(SeekFrom::Start(u64::from(id) * RECORD_BYTES))?;
+        self.file.read_exact(&mut record)?;
+        decode(&record).map(Some)
+    }
+
+    // Only if the record is the client's, since ids are shared by every client
+    fn remove(&mut self, client: ClientId, id: TransactionId) -> io::Result<()> {
+        if self.read(id)?.is_some_and(|(spilled, _)| spilled == client) {
+            let id = id as usize;
+            if let Some(bits) = self.spilled.get_mut(&(id / 64)) {
+                *bits &= !(1 << (id % 64));
+                if *bits == 0 {
+                    self.spilled.remove(&(id / 64));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // `false` if the transaction can't be spilled since its slot holds another client's
+    fn write(
+        &mut self,
+        client: ClientId,
+        id: TransactionId,
+        disputable: Disputable,
+    ) -> io::Result<bool> {
+        if self.read(id)?.is_some_and(|(spilled, _)| spilled != client) {
+            return Ok(false);
+        }
+
+        self.file
+            .seek(SeekFrom::Start(u64::from(id) * RECORD_BYTES))?;
+        self.file.write_all(&encode(client, disputable))?;
+        let id = id as usize;
+        *self.spilled.entry(id / 64).or_default() |= 1 << (id % 64);
+        Ok(true)
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!("Failed to remove {}: {err}", self.path.display());
+        }
+    }
+}
+
+fn encode(client: ClientId, disputable: Disputable) -> [u8; RECORD_BYTES as usize] {
+    let mut record = [0; RECORD_BYTES as usize];
+    record[0] = 1;
+    record[2..4].copy_from_slice(&client.to_le_bytes());
+    record[4..12].copy_from_slice(&disputable.amount.units().to_le_bytes());
+    if let Some(currency) = disputable.currency {
+        record[12..15].copy_from_slice(currency.as_str().as_bytes());
+    }
+    record
+}
+
+fn decode(record: &[u8; RECORD_BYTES as usize]) -> io::Result<(ClientId, Disputable)> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bad {what}"));
+    if record[0] != 1 {
+        return Err(invalid("record marker"));
+    }
+    let client = ClientId::from_le_bytes([record[2], record[3]]);
+    let mut amount = [0; 8];
+    amount.copy_from_slice(&record[4..12]);
+    let currency = match &record[12..15] {
+        [0, 0, 0] => None,
+        code => {
+            let code = std::str::from_utf8(code).map_err(|_| invalid("currency"))?;
+            Some(Currency::new(code).map_err(|_| invalid("currency"))?)
+        }
+    };
+
+    let disputable = Disputable {
+        amount: Amount::from(i64::from_le_bytes(amount)),
+        currency,
+    };
+    Ok((client, disputable))
+}
+
+#[cfg(test)]
+mod history_tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    fn disputable(units: i64) -> Disputable {
+        Disputable {
+            amount: Amount::from(units),
+            currency: None,
+        }
+    }
+
+    fn history(entries: usize) -> TransactionHistory {
+        TransactionHistory::new(&HistoryConfig {
+            memory_budget: Some(entries * ENTRY_BYTES),
+            spill_dir: None,
+        })
+    }
+
+    #[gtest]
+    pub fn reloads_spilled_transactions() {
+        let history = history(2);
+        let withdrawal = Disputable {
+            amount: Amount::from(-7),
+            currency: Some(Currency::new("USD").unwrap()),
+        };
+        history.insert(1, 1, disputable(5)).unwrap();
+        history.insert(1, 2, withdrawal).unwrap();
+        history.insert(2, 3, disputable(9)).unwrap();
+        history.insert(2, 4, disputable(1)).unwrap();
+
+        expect_that!(history.lock().hot.len(), eq(2));
+        expect_that!(history.get(1, 2), ok(some(eq(&withdrawal))));
+        expect_that!(history.get(1, 1), ok(some(eq(&disputable(5)))));
+        expect_that!(history.get(2, 3), ok(some(eq(&disputable(9)))));
+        // Another client's transaction with the same id isn't a match
+        expect_that!(history.get(2, 1), ok(none()));
+        expect_that!(history.get(1, 5), ok(none()));
+        expect_that!(history.lock().hot.len(), eq(2));
+        // Only the serial engine checkpoints its history
+        #[cfg(not(feature = "stream"))]
+        expect_that!(
+            history.transactions(),
+            ok(eq(&vec![
+                Transaction::new(1, 1, TransactionType::Deposit, Some(Amount::from(5))),
+                Transaction::new(2, 1, TransactionType::Withdrawal, Some(Amount::from(7)))
+                    .in_currency(Currency::new("USD").unwrap()),
+                Transaction::new(3, 2, TransactionType::Deposit, Some(Amount::from(9))),
+                Transaction::new(4, 2, TransactionType::Deposit, Some(Amount::from(1))),
+            ]))
+        );
+    }
+
+    #[gtest]
+    pub fn evicts_the_least_recently_used_transaction() {
+        let mut hot = HotTransactions::new(Some(2));
+        expect_that!(hot.insert(1, 1, disputable(1)), none());
+        expect_that!(hot.insert(1, 2, disputable(2)), none());
+        expect_that!(hot.get(1), some(eq((1, disputable(1)))));
+        expect_that!(
+            hot.insert(1, 3, disputable(3)),
+            some(eq((1, 2, disputable(2))))
+        );
+        expect_that!(
+            hot.insert(1, 4, disputable(4)),
+            some(eq((1, 1, disputable(1))))
+        );
+        expect_that!(hot.client(2), none());
+        expect_that!(hot.len(), eq(2));
+    }
+
+    #[gtest]
+    pub fn keeps_colliding_transactions_in_memory() {
+        let history = history(1);
+        history.insert(1, 1, disputable(5)).unwrap();
+        // Client 2's transaction 1 can't take client 1's slot in the cache...
+        history.insert(2, 1, disputable(6)).unwrap();
+        history.insert(2, 2, disputable(7)).unwrap();
+        // ...nor on disk, once client 1's was spilled
+        history.insert(2, 1, disputable(8)).unwrap();
+        history.insert(2, 3, disputable(9)).unwrap();
+
+        expect_that!(history.get(1, 1), ok(some(eq(&disputable(5)))));
+        expect_that!(history.get(2, 1), ok(some(eq(&disputable(8)))));
+        expect_that!(history.get(2, 2), ok(some(eq(&disputable(7)))));
+        expect_that!(history.get(2, 3), ok(some(eq(&disputable(9)))));
+        #[cfg(not(feature = "stream"))]
+        expect_that!(history.transactions(), ok(len(eq(4))));
+    }
+
+    #[gtest]
+    pub fn spilling_large_ids_takes_memory_for_them_only() {
+        let history = history(1);
+        history
+            .insert(1, TransactionId::MAX - 1, disputable(5))
+            .unwrap();
+        history.insert(1, 1, disputable(6)).unwrap();
+
+        expect_that!(history.lock().spill.as_ref().unwrap().spilled.len(), eq(1));
+        expect_that!(
+            history.get(1, TransactionId::MAX - 1),
+            ok(some(eq(&disputable(5))))
+        );
+        #[cfg(not(feature = "stream"))]
+        expect_that!(history.transactions(), ok(len(eq(2))));
+    }
+
+    #[gtest]
+    pub fn removes_transactions_and_reuses_their_slots() {
+        let history = history(2);
+        history.insert(1, 1, disputable(5)).unwrap();
+        history.insert(1, 2, disputable(6)).unwrap();
+        // Spills tx 1
+        history.insert(1, 3, disputable(7)).unwrap();
+        // Another client's tx 2 isn't touched
+        history.remove(2, 2).unwrap();
+        history.remove(1, 1).unwrap();
+        history.remove(1, 3).unwrap();
+        history.insert(1, 4, disputable(8)).unwrap();
+
+        expect_that!(history.get(1, 1), ok(none()));
+        expect_that!(history.get(1, 3), ok(none()));
+        expect_that!(history.get(1, 2), ok(some(eq(&disputable(6)))));
+        expect_that!(history.get(1, 4), ok(some(eq(&disputable(8)))));
+        expect_that!(history.lock().hot.clients.len(), eq(2));
+        #[cfg(not(feature = "stream"))]
+        expect_that!(history.transactions(), ok(len(eq(2))));
+
+        let unbounded = TransactionHistory::default();
+        unbounded.insert(1, 1, disputable(5)).unwrap();
+        unbounded.remove(1, 1).unwrap();
+        unbounded.insert(1, 2, disputable(6)).unwrap();
+        expect_that!(unbounded.get(1, 1), ok(none()));
+        expect_that!(unbounded.get(1, 2), ok(some(eq(&disputable(6)))));
+        expect_that!(unbounded.lock().hot.clients.len(), eq(1));
+    }
+
+    #[gtest]
+    pub fn removes_the_spill_file_on_drop() {
+        let history = history(1);
+        history.insert(1, 1, disputable(5)).unwrap();
+        history.insert(1, 2, disputable(6)).unwrap();
+        let path = history.lock().spill.as_ref().unwrap().path.clone();
+
+        expect_that!(path.exists(), is_true());
+        drop(history);
+        expect_that!(path.exists(), is_false());
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(any(not(feature = "stream"), feature = "sqlite"))]
+use crate::Balance;
+use crate::currency::Currency;
+use crate::parse::{Amount, WideAmount};
+use crate::{Client, ClientId, TransactionId};
+
+// Accounts money moves between. `Available` and `Held` are the client's own, and are
+// kept in its balances so they stay partitioned with the client (e.g., per stream
+// worker). The rest are the outside world's and the house's side of every movement.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerAccount {
+    Available,
+    Held,
+    // Where deposits come from and withdrawals go
+    ExternalFunding,
+    // Held funds taken back by chargebacks
+    ChargebackLoss,
+    // Fees charged to clients, i.e., the `HouseAccount`'s revenue
+    FeeRevenue,
+    // The house's side of conversions: what it took in the source currency and paid
+    // out in the target currency
+    FxExchange,
+}
+
+impl LedgerAccount {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            LedgerAccount::Available => "available",
+            LedgerAccount::Held => "held",
+            LedgerAccount::ExternalFunding => "external_funding",
+            LedgerAccount::ChargebackLoss => "chargeback_loss",
+            LedgerAccount::FeeRevenue => "fee_revenue",
+            LedgerAccount::FxExchange => "fx_exchange",
+        }
+    }
+
+    pub const fn is_client(self) -> bool {
+        matches!(self, LedgerAccount::Available | LedgerAccount::Held)
+    }
+}
+
+// Moves `amount` from one account to the other, so every posting sums to zero on its own
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LedgerPosting {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    pub from: LedgerAccount,
+    pub to: LedgerAccount,
+    // Never negative: negative amounts are posted the other way around
+    pub amount: Amount,
+}
+
+#[derive(Clone, Copy, Debug, thiserror::Error, PartialEq, Eq)]
+#[error("Trial balance is off by {imbalance} in {currency:?}")]
+pub struct TrialBalanceError {
+    pub currency: Option<Currency>,
+    pub imbalance: WideAmount,
+}
+
+// Double-entry ledger every balance change is posted to. Balances are only ever changed
+// by moving money between two accounts, so, per currency, all accounts (the clients'
+// included) always sum to zero, which `trial_balance` checks.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Ledger {
+    // Every account but the clients', per currency
+    accounts: BTreeMap<(Option<Currency>, LedgerAccount), WideAmount>,
+    // Posted since `take_postings` was last called
+    postings: Vec<LedgerPosting>,
+}
+
+impl Ledger {
+    // Always zero for the clients' accounts, which are in their balances
+    pub fn balance(&self, currency: Option<Currency>, account: LedgerAccount) -> WideAmount {
+        self.accounts
+            .get(&(currency, account))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // Zero amounts aren't posted at all, like zero fees
+    pub(crate) fn post(
+        &mut self,
+        client: &mut Client,
+        tx: TransactionId,
+        currency: Option<Currency>,
+        from: LedgerAccount,
+        to: LedgerAccount,
+        amount: Amount,
+    ) {
+        let (from, to, amount) = if amount < Amount::ZERO {
+            (to, from, -amount)
+        } else {
+            (from, to, amount)
+        };
+        if amount.is_zero() {
+            return;
+        }
+
+        self.apply(client, currency, from, -amount);
+        self.apply(client, currency, to, amount);
+        self.postings.push(LedgerPosting {
+            client: client.id,
+            tx,
+            currency,
+            from,
+            to,
+            amount,
+        });
+    }
+
+    fn apply(
+        &mut self,
+        client: &mut Client,
+        currency: Option<Currency>,
+        account: LedgerAccount,
+        amount: Amount,
+    ) {
+        match account {
+            LedgerAccount::Available => client.balance_mut(currency).available += amount,
+            LedgerAccount::Held => client.balance_mut(currency).held += amount,
+            account => *self.accounts.entry((currency, account)).or_default() += amount,
+        }
+    }
+
+    // Takes back what `postings` did to every account but the clients', e.g., once the
+    // transaction that made them couldn't be stored. Client balances are up to whoever
+    // holds the client.
+    pub(crate) fn revert(&mut self, postings: &[LedgerPosting]) {
+        for posting in postings {
+            for (account, amount) in [
+                (posting.from, posting.amount),
+                (posting.to, -posting.amount),
+            ] {
+                if !account.is_client() {
+                    *self
+                        .accounts
+                        .entry((posting.currency, account))
+                        .or_default() += amount;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn take_postings(&mut self) -> Vec<LedgerPosting> {
+        std::mem::take(&mut self.postings)
+    }
+
+    // Checks that, in every currency, all accounts of the ledger and `clients` sum to
+    // zero. `clients` must be every client the ledger was posted for.
+    pub(crate) fn trial_balance<'a>(
+        &self,
+        clients: impl IntoIterator<Item = &'a Client>,
+    ) -> Result<(), TrialBalanceError> {
+        let mut totals = BTreeMap::<Option<Currency>, WideAmount>::new();
+        for (&(currency, _), &balance) in &self.accounts {
+            *totals.entry(currency).or_default() += balance;
+        }
+        for client in clients {
+            for (&currency, balance) in &client.balances {
+                let total = totals.entry(currency).or_default();
+                *total += balance.available;
+                *total += balance.held;
+            }
+        }
+
+        match totals
+            .into_iter()
+            .find(|(_, total)| *total != WideAmount::ZERO)
+        {
+            Some((currency, imbalance)) => Err(TrialBalanceError {
+                currency,
+                imbalance,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // Every account but the clients', e.g., to persist them
+    #[cfg(any(not(feature = "stream"), feature = "sqlite"))]
+    pub(crate) fn accounts(
+        &self,
+    ) -> impl Iterator<Item = (Option<Currency>, LedgerAccount, WideAmount)> + '_ {
+        self.accounts
+            .iter()
+            .map(|(&(currency, account), &balance)| (currency, account, balance))
+    }
+
+    #[cfg(any(not(feature = "stream"), feature = "sqlite"))]
+    pub(crate) fn from_accounts(
+        accounts: impl IntoIterator<Item = (Option<Currency>, LedgerAccount, WideAmount)>,
+    ) -> Self {
+        let mut ledger = Self::default();
+        for (currency, account, balance) in accounts {
+            *ledger.accounts.entry((currency, account)).or_default() += balance;
+        }
+        ledger
+    }
+
+    // Balances the clients' funds against external funding and their fees against
+    // revenue, e.g., for clients restored without the ledger they were booked in
+    #[cfg(any(not(feature = "stream"), feature = "sqlite"))]
+    pub(crate) fn opening(balances: impl IntoIterator<Item = (Option<Currency>, Balance)>) -> Self {
+        let mut ledger = Self::default();
+        for (currency, balance) in balances {
+            let funding = ledger
+                .accounts
+                .entry((currency, LedgerAccount::ExternalFunding))
+                .or_default();
+            *funding -= WideAmount::from(balance.available + balance.held + balance.fees);
+            *ledger
+                .accounts
+                .entry((currency, LedgerAccount::FeeRevenue))
+                .or_default() += balance.fees;
+        }
+        ledger
+    }
+}
+
+#[cfg(test)]
+mod ledger_tests {
+    use googletest::prelude::*;
+
+    use super::*;
+    use crate::Balance;
+
+    #[gtest]
+    pub fn postings_move_money_between_accounts() {
+        let mut ledger = Ledger::default();
+        let mut client = Client::new(1);
+        let usd = Some(Currency::new("USD").unwrap());
+
+        ledger.post(
+            &mut client,
+            1,
+            usd,
+            LedgerAccount::ExternalFunding,
+            LedgerAccount::Available,
+            Amount::from(100),
+        );
+        // Negative amounts go the other way around
+        ledger.post(
+            &mut client,
+            1,
+            usd,
+            LedgerAccount::Held,
+            LedgerAccount::Available,
+            Amount::from(-30),
+        );
+        ledger.post(
+            &mut client,
+            1,
+            usd,
+            LedgerAccount::Held,
+            LedgerAccount::ChargebackLoss,
+            Amount::ZERO,
+        );
+
+        expect_that!(
+            ledger.take_postings(),
+            elements_are![
+                field!(LedgerPosting.from, eq(&LedgerAccount::ExternalFunding)),
+                all![
+                    field!(LedgerPosting.from, eq(&LedgerAccount::Available)),
+                    field!(LedgerPosting.to, eq(&LedgerAccount::Held)),
+                    field!(LedgerPosting.amount, eq(&Amount::from(30))),
+                ],
+            ]
+        );
+        expect_that!(ledger.take_postings(), is_empty());
+        expect_that!(
+            client.balances[&usd],
+            eq(Balance {
+                available: Amount::from(70),
+                held: Amount::from(30),
+                fees: Amount::ZERO,
+            })
+        );
+        expect_that!(
+            ledger.balance(usd, LedgerAccount::ExternalFunding),
+            eq(WideAmount::from(-100))
+        );
+        expect_that!(ledger.trial_balance([&client]), ok(anything()));
+    }
+
+    #[gtest]
+    pub fn trial_balance_catches_money_created_outside_the_ledger() {
+        let mut ledger = Ledger::default();
+        let mut client = Client::new(1);
+        ledger.post(
+            &mut client,
+            1,
+            None,
+            LedgerAccount::ExternalFunding,
+            LedgerAccount::Available,
+            Amount::from(100),
+        );
+        client.balance_mut(None).held += Amount::from(5);
+
+        expect_that!(
+            ledger.trial_balance([&client]),
+            err(eq(TrialBalanceError {
+                currency: None,
+                imbalance: WideAmount::from(5),
+            }))
+        );
+    }
+
+    #[gtest]
+    #[cfg(any(not(feature = "stream"), feature = "sqlite"))]
+    pub fn opening_balances_restored_clients() {
+        let mut client = Client::new(1);
+        client.balances.insert(
+            None,
+            Balance {
+                available: Amount::from(70),
+                held: Amount::from(20),
+                fees: Amount::from(10),
+            },
+        );
+        let ledger = Ledger::opening(client.balances.iter().map(|(&c, &b)| (c, b)));
+
+        expect_that!(
+            ledger.balance(None, LedgerAccount::FeeRevenue),
+            eq(WideAmount::from(10))
+        );
+        expect_that!(ledger.trial_balance([&client]), ok(anything()));
+    }
+}
@@ -0,0 +1,350 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use super::TransactionProcessError;
+use crate::TransactionType;
+
+// Upper bounds of the latency histograms' buckets, in seconds
+const LATENCY_BUCKETS: [f64; 8] = [0.000_001, 0.000_01, 0.000_1, 0.001, 0.01, 0.1, 1.0, 10.0];
+
+// In declaration order, so a type's index is its discriminant
+const TRANSACTION_TYPES: [TransactionType; 6] = [
+    TransactionType::Deposit,
+    TransactionType::Withdrawal,
+    TransactionType::Dispute,
+    TransactionType::Resolve,
+    TransactionType::Chargeback,
+    TransactionType::Convert,
+];
+
+// Labels of `TransactionProcessError`'s variants, indexed by `error_index`
+const ERROR_LABELS: [&str; 13] = [
+    "client_locked",
+    "insufficient_funds",
+    "invalid_dispute_not_found",
+    "invalid_dispute_duplicate",
+    "invalid_resolve_not_found",
+    "invalid_resolve_not_disputed",
+    "invalid_chargeback_not_found",
+    "invalid_chargeback_not_disputed",
+    "unsupported_currency",
+    "invalid_conversion",
+    "fx_rate_not_found",
+    "too_precise",
+    "unknown",
+];
+
+// Counters and histograms shared by every processor of an engine, rendered in the
+// Prometheus text exposition format. Everything is atomic, so workers recording
+// transactions never wait on each other.
+#[derive(Debug, Default)]
+pub struct EngineMetrics {
+    // Indexed like `TRANSACTION_TYPES`
+    transactions: [AtomicU64; TRANSACTION_TYPES.len()],
+    // Indexed like `ERROR_LABELS`
+    rejections: [AtomicU64; ERROR_LABELS.len()],
+    // Lock events since the engine started. Clients restored already locked (e.g., from
+    // a database or a checkpoint) aren't counted.
+    locks: AtomicU64,
+    process_seconds: Histogram,
+    // Only the stream engine queues transactions
+    queue: Option<QueueMetrics>,
+}
+
+#[derive(Debug, Default)]
+struct QueueMetrics {
+    depth: AtomicU64,
+    // From enqueueing a transaction until its worker is done with it. Workers are spawned
+    // per client, so they aren't labeled: that would be a series per client.
+    worker_latency_seconds: Histogram,
+}
+
+// Cumulative, like Prometheus buckets: `buckets[i]` counts observations <= LATENCY_BUCKETS[i]
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    // In nanoseconds, so it can be summed atomically
+    sum_nanos: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, &bound) in self.buckets.iter().zip(&LATENCY_BUCKETS) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        for (count, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            let count = count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {count}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum {sum}");
+        let _ = writeln!(out, "{name}_count {count}");
+    }
+}
+
+impl EngineMetrics {
+    pub(crate) fn record(
+        &self,
+        action: TransactionType,
+        result: Result<(), &TransactionProcessError>,
+        locked: bool,
+        elapsed: Duration,
+    ) {
+        self.transactions[action as usize].fetch_add(1, Ordering::Relaxed);
+        if let Err(err) = result {
+            self.rejections[error_index(err)].fetch_add(1, Ordering::Relaxed);
+        }
+        if locked {
+            self.locks.fetch_add(1, Ordering::Relaxed);
+        }
+        self.process_seconds.observe(elapsed);
+    }
+
+    // Prometheus text exposition format (version 0.0.4)
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "payment_engine_transactions_total",
+            "counter",
+            "Transactions processed, applied or not",
+        );
+        let types = TRANSACTION_TYPES.map(TransactionType::as_str);
+        for (action, count) in nonzero_by_label(&types, &self.transactions) {
+            let _ = writeln!(
+                out,
+                "payment_engine_transactions_total{{type=\"{action}\"}} {count}"
+            );
+        }
+
+        header(
+            &mut out,
+            "payment_engine_rejections_total",
+            "counter",
+            "Transactions rejected by the business rules",
+        );
+        for (error, count) in nonzero_by_label(&ERROR_LABELS, &self.rejections) {
+            let _ = writeln!(
+                out,
+                "payment_engine_rejections_total{{error=\"{error}\"}} {count}"
+            );
+        }
+
+        header(
+            &mut out,
+            "payment_engine_locks_total",
+            "counter",
+            "Clients locked by a chargeback",
+        );
+        let _ = writeln!(
+            out,
+            "payment_engine_locks_total {}",
+            self.locks.load(Ordering::Relaxed)
+        );
+
+        header(
+            &mut out,
+            "payment_engine_process_seconds",
+            "histogram",
+            "Time spent applying a transaction",
+        );
+        self.process_seconds
+            .render(&mut out, "payment_engine_process_seconds");
+
+        if let Some(queue) = &self.queue {
+            header(
+                &mut out,
+                "payment_engine_queue_depth",
+                "gauge",
+                "Transactions enqueued but not yet applied by a worker",
+            );
+            let _ = writeln!(
+                out,
+                "payment_engine_queue_depth {}",
+                queue.depth.load(Ordering::Relaxed)
+            );
+
+            header(
+                &mut out,
+                "payment_engine_worker_latency_seconds",
+                "histogram",
+                "Time from enqueueing a transaction until its worker has applied it",
+            );
+            queue
+                .worker_latency_seconds
+                .render(&mut out, "payment_engine_worker_latency_seconds");
+        }
+        out
+    }
+}
+
+// Only the stream engine queues transactions
+#[cfg(feature = "stream")]
+impl EngineMetrics {
+    // Also reports queue depth and worker latency
+    pub(crate) fn with_queue() -> Self {
+        Self {
+            queue: Some(QueueMetrics::default()),
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn record_enqueued(&self) {
+        if let Some(queue) = &self.queue {
+            queue.depth.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Once the worker is done with the transaction
+    pub(crate) fn record_dequeued(&self, latency: Duration) {
+        if let Some(queue) = &self.queue {
+            let _ = queue
+                .depth
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |depth| {
+                    Some(depth.saturating_sub(1))
+                });
+            queue.worker_latency_seconds.observe(latency);
+        }
+    }
+}
+
+// Counters that aren't zero with their labels, in label order
+fn nonzero_by_label<'a>(labels: &[&'a str], counts: &[AtomicU64]) -> Vec<(&'a str, u64)> {
+    let mut counts = labels
+        .iter()
+        .zip(counts)
+        .map(|(&label, count)| (label, count.load(Ordering::Relaxed)))
+        .filter(|&(_, count)| count > 0)
+        .collect::<Vec<_>>();
+    counts.sort_unstable();
+    counts
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+pub(super) fn error_label(err: &TransactionProcessError) -> &'static str {
+    ERROR_LABELS[error_index(err)]
+}
+
+fn error_index(err: &TransactionProcessError) -> usize {
+    use TransactionProcessError as E;
+
+    match err {
+        E::ClientLocked(..) => 0,
+        E::InsufficientFunds(..) => 1,
+        E::InvalidDisputeNotFound(..) => 2,
+        E::InvalidDisputeDuplicate(..) => 3,
+        E::InvalidResolveNotFound(..) => 4,
+        E::InvalidResolveNotDisputed(..) => 5,
+        E::InvalidChargeBackNotFound(..) => 6,
+        E::InvalidChargeBackNotDisputed(..) => 7,
+        E::UnsupportedCurrency(..) => 8,
+        E::InvalidConversion(..) => 9,
+        E::FxRateNotFound(..) => 10,
+        E::TooPrecise(..) => 11,
+        E::Unknown => 12,
+    }
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[gtest]
+    pub fn renders_text_exposition_format() {
+        let metrics = EngineMetrics::default();
+        let millisecond = Duration::from_millis(1);
+        metrics.record(TransactionType::Deposit, Ok(()), false, millisecond);
+        metrics.record(
+            TransactionType::Withdrawal,
+            Err(&TransactionProcessError::InsufficientFunds(1, 2)),
+            false,
+            millisecond,
+        );
+        metrics.record(TransactionType::Chargeback, Ok(()), true, millisecond);
+
+        expect_that!(
+            metrics.render(),
+            eq(
+                r#"# HELP payment_engine_transactions_total Transactions processed, applied or not
+# TYPE payment_engine_transactions_total counter
+payment_engine_transactions_total{type="chargeback"} 1
+payment_engine_transactions_total{type="deposit"} 1
+payment_engine_transactions_total{type="withdrawal"} 1
+# HELP payment_engine_rejections_total Transactions rejected by the business rules
+# TYPE payment_engine_rejections_total counter
+payment_engine_rejections_total{error="insufficient_funds"} 1
+# HELP payment_engine_locks_total Clients locked by a chargeback
+# TYPE payment_engine_locks_total counter
+payment_engine_locks_total 1
+# HELP payment_engine_process_seconds Time spent applying a transaction
+# TYPE payment_engine_process_seconds histogram
+payment_engine_process_seconds_bucket{le="0.000001"} 0
+payment_engine_process_seconds_bucket{le="0.00001"} 0
+payment_engine_process_seconds_bucket{le="0.0001"} 0
+payment_engine_process_seconds_bucket{le="0.001"} 3
+payment_engine_process_seconds_bucket{le="0.01"} 3
+payment_engine_process_seconds_bucket{le="0.1"} 3
+payment_engine_process_seconds_bucket{le="1"} 3
+payment_engine_process_seconds_bucket{le="10"} 3
+payment_engine_process_seconds_bucket{le="+Inf"} 3
+payment_engine_process_seconds_sum 0.003
+payment_engine_process_seconds_count 3
+"#
+            )
+        );
+    }
+
+    #[gtest]
+    #[cfg(feature = "stream")]
+    pub fn renders_queue_metrics() {
+        let metrics = EngineMetrics::with_queue();
+        metrics.record_enqueued();
+        metrics.record_enqueued();
+        metrics.record_dequeued(Duration::from_millis(20));
+
+        let rendered = metrics.render();
+        let queue = &rendered[rendered.find("# HELP payment_engine_queue_depth").unwrap()..];
+        expect_that!(
+            queue,
+            eq(
+                r#"# HELP payment_engine_queue_depth Transactions enqueued but not yet applied by a worker
+# TYPE payment_engine_queue_depth gauge
+payment_engine_queue_depth 1
+# HELP payment_engine_worker_latency_seconds Time from enqueueing a transaction until its worker has applied it
+# TYPE payment_engine_worker_latency_seconds histogram
+payment_engine_worker_latency_seconds_bucket{le="0.000001"} 0
+payment_engine_worker_latency_seconds_bucket{le="0.00001"} 0
+payment_engine_worker_latency_seconds_bucket{le="0.0001"} 0
+payment_engine_worker_latency_seconds_bucket{le="0.001"} 0
+payment_engine_worker_latency_seconds_bucket{le="0.01"} 0
+payment_engine_worker_latency_seconds_bucket{le="0.1"} 1
+payment_engine_worker_latency_seconds_bucket{le="1"} 1
+payment_engine_worker_latency_seconds_bucket{le="10"} 1
+payment_engine_worker_latency_seconds_bucket{le="+Inf"} 1
+payment_engine_worker_latency_seconds_sum 0.02
+payment_engine_worker_latency_seconds_count 1
+"#
+            )
+        );
+    }
+}
@@ -2,26 +2,61 @@
 #[cfg_attr(feature = "stream", path = "stream.rs")]
 pub(crate) mod engine_impl;
 
+#[cfg(not(feature = "stream"))]
+pub mod checkpoint;
+pub mod fees;
+pub mod history;
+pub mod ledger;
+pub mod metrics;
+pub mod observer;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod subscriptions;
+
 pub type Engine = engine_impl::Engine;
 
 use log::{debug, error};
-use std::{collections::HashMap, fmt::Display};
-
-use crate::{Client, ClientId, ClientSnapshot, Transaction, TransactionId, TransactionType};
+use std::{collections::HashMap, fmt::Display, io, sync::Arc, time::Instant};
+
+use crate::currency::{Currency, CurrencyList};
+use crate::fx::FxRateTable;
+use crate::parse::{Amount, Rate, RoundingMode};
+use crate::{
+    Client, ClientId, ClientSnapshot, ExtendedClientSnapshot, Transaction, TransactionId,
+    TransactionType,
+};
+use fees::{FeeKind, FeeLine, FeeSchedule, HouseAccount, NegativeFeeError};
+use history::{Disputable, HistoryConfig, TransactionHistory};
+use ledger::{Ledger, LedgerAccount, LedgerPosting};
+use metrics::EngineMetrics;
+use observer::{EngineObserver, Observers};
+use subscriptions::{BalanceChange, BalanceChanges, Subscribers, Subscription};
 
 // Manages client(s) and is used by TransactionProcessor.
 //
 // TODO (PERF + ENHANCEMENT + MAINTANABILITY): Ideally, the
-// disputes and basic_transaction (deposits + withdrawals) in Client should
-// be moved into the implementation of this trait. This would allow us to have
-// a single BTreeMap/HashMap for disputes, and a single BTreeSet/HashSet
-// for *all* clients, for example, while also supporting client-partitioned data
-// structures. TransactionProcessor would then insert disputes, remove disputes,
-// and insert transactions via this interface. I think I've already shown that I
-// can generalize via traits, though, so I'm not going to add more noise + spend
-// the time to do that.
+// disputes in Client should be moved into the implementation of this trait,
+// like the disputable transactions were moved into `TransactionHistory`. This
+// would allow us to have a single BTreeSet/HashSet for *all* clients, for
+// example, while also supporting client-partitioned data structures.
+// TransactionProcessor would then insert and remove disputes via this
+// interface. I think I've already shown that I can generalize via traits,
+// though, so I'm not going to add more noise + spend the time to do that.
 trait ClientManager {
     fn get_or_insert_client_mut(&mut self, client_id: ClientId) -> &mut Client;
+
+    // Called once `transaction` has been processed, applied or not, so persistent
+    // managers can store everything it changed in its client atomically. Failing it fails
+    // the transaction with `Unknown`, and the processor takes back what the transaction
+    // did outside of the client, which the manager has to drop or restore. In-memory
+    // managers have nothing to do.
+    fn persist(
+        &mut self,
+        _transaction: &Transaction,
+        _result: Result<&TransactionOutcome, &TransactionProcessError>,
+    ) -> Result<(), TransactionProcessError> {
+        Ok(())
+    }
 }
 
 #[derive(Debug, Default)]
@@ -47,15 +82,137 @@ pub enum TransactionProcessError {
     InvalidResolveNotDisputed(ClientId, TransactionId),
     InvalidChargeBackNotFound(ClientId, TransactionId),
     InvalidChargeBackNotDisputed(ClientId, TransactionId),
+    UnsupportedCurrency(ClientId, TransactionId),
+    InvalidConversion(ClientId, TransactionId),
+    FxRateNotFound(ClientId, TransactionId),
+    // The amount has digits past its currency's minor units, e.g., 0.5 JPY
+    TooPrecise(ClientId, TransactionId),
     Unknown,
 }
 
+impl TransactionProcessError {
+    // Stable snake_case name of the error, e.g., `insufficient_funds`, for anything that
+    // outlives this build: metric labels, the SQLite journal and HTTP responses
+    pub fn code(&self) -> &'static str {
+        metrics::error_label(self)
+    }
+}
+
 impl Display for TransactionProcessError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!("[Client] Transaction failed: {:?}", self))
     }
 }
 
+// Business rules shared by every engine implementation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EngineConfig {
+    pub fee_schedule: FeeSchedule,
+    // Currencies a transaction may be booked in
+    pub currencies: CurrencyList,
+    // Currency for transactions without a `currency` column. `None` keeps
+    // them in a separate, currency-less balance.
+    pub base_currency: Option<Currency>,
+    // Shared since every stream worker needs the (possibly large) table
+    pub fx_rates: Arc<FxRateTable>,
+    // Fraction of every conversion kept by the house, e.g., 0.005 => 0.5%
+    pub fx_spread: Rate,
+    pub fx_rounding: RoundingMode,
+    pub history: HistoryConfig,
+}
+
+#[derive(Clone, Copy, Debug, thiserror::Error, PartialEq, Eq)]
+pub enum EngineConfigError {
+    #[error(transparent)]
+    NegativeFee(#[from] NegativeFeeError),
+    #[error("The FX spread must be at least 0 and less than 1")]
+    FxSpreadOutOfRange,
+}
+
+impl EngineConfig {
+    // Fees that would create money, or a spread that would take more than the
+    // converted amount and leave the target balance negative
+    pub fn validate(&self) -> Result<(), EngineConfigError> {
+        self.fee_schedule.validate()?;
+        if !(Rate::ZERO..Rate::ONE).contains(&self.fx_spread) {
+            return Err(EngineConfigError::FxSpreadOutOfRange);
+        }
+        Ok(())
+    }
+
+    fn charges_fees(&self) -> bool {
+        self.fee_schedule.is_enabled() || !self.fx_spread.is_zero()
+    }
+
+    // Resolves the currency a deposit/withdrawal is booked in, or hands back
+    // the offending currency if it isn't in the configured list.
+    fn booking_currency(&self, currency: Option<Currency>) -> Result<Option<Currency>, Currency> {
+        match currency.or(self.base_currency) {
+            Some(currency) if !self.currencies.contains(currency) => Err(currency),
+            currency => Ok(currency),
+        }
+    }
+
+    // Rounds a computed amount (e.g., a percentage fee or a conversion) to `currency`'s
+    // minor units, so balances never hold digits their currency doesn't have
+    fn minor_units(
+        &self,
+        currency: Option<Currency>,
+        amount: Amount,
+        rounding: RoundingMode,
+    ) -> Result<Amount, TransactionProcessError> {
+        self.currencies
+            .round_to_minor_units(currency, amount, rounding)
+            .ok_or(TransactionProcessError::Unknown)
+    }
+}
+
+// Side effects of a successfully applied transaction
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransactionOutcome {
+    // Balance changes, excluding fees
+    pub postings: Vec<Posting>,
+    pub fees: Vec<FeeLine>,
+    // Every posting made to the ledger, fees included
+    pub ledger: Vec<LedgerPosting>,
+}
+
+// Signed change to one of a client's balances. Disputes, resolves, and chargebacks post
+// in the disputed transaction's currency, and conversions post to both currencies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Posting {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub currency: Option<Currency>,
+    pub available: Amount,
+    pub held: Amount,
+}
+
+impl Posting {
+    pub fn total(&self) -> Amount {
+        self.available + self.held
+    }
+}
+
+impl TransactionOutcome {
+    fn post(
+        &mut self,
+        client: &Client,
+        tx: TransactionId,
+        currency: Option<Currency>,
+        available: Amount,
+        held: Amount,
+    ) {
+        self.postings.push(Posting {
+            client: client.id,
+            tx,
+            currency,
+            available,
+            held,
+        });
+    }
+}
+
 // Contains the core business logic for processing transactions
 #[derive(Debug, Default)]
 struct TransactionProcessor<C>
@@ -63,13 +220,136 @@ where
     C: ClientManager,
 {
     client_manager: C,
+    config: EngineConfig,
+    house: HouseAccount,
+    // Every balance change is posted here, so no money is created or destroyed
+    ledger: Ledger,
+    // Deposits and withdrawals, for disputes to look up
+    history: TransactionHistory,
+    subscribers: Subscribers,
+    observers: Observers,
+    metrics: Arc<EngineMetrics>,
 }
 
 impl<C> TransactionProcessor<C>
 where
     C: ClientManager,
 {
-    fn process(&mut self, transaction: Transaction) -> Result<(), TransactionProcessError> {
+    fn with_config(client_manager: C, config: EngineConfig) -> Self {
+        TransactionProcessor {
+            client_manager,
+            history: TransactionHistory::new(&config.history),
+            config,
+            house: HouseAccount::default(),
+            ledger: Ledger::default(),
+            subscribers: Subscribers::default(),
+            observers: Observers::default(),
+            metrics: Arc::default(),
+        }
+    }
+
+    // Applies `transaction`, counts it in the client's activity and metrics, applied or
+    // not, persists the client, and tells subscribers and observers about it
+    fn process(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<TransactionOutcome, TransactionProcessError> {
+        let (client_id, id, action) = (transaction.client_id, transaction.id, transaction.action);
+        let before = self
+            .subscribers
+            .wants(client_id)
+            .then(|| self.balances(client_id));
+        let was_locked = self
+            .client_manager
+            .get_or_insert_client_mut(client_id)
+            .is_locked;
+        let started = Instant::now();
+        let result = self.apply(transaction.clone());
+        let elapsed = started.elapsed();
+        let ledger = self.ledger.take_postings();
+        let result = result.map(|outcome| TransactionOutcome { ledger, ..outcome });
+        let client = self.client_manager.get_or_insert_client_mut(client_id);
+        let open_disputes = client.disputes.len();
+        client
+            .activity
+            .record(id, action, result.is_ok(), open_disputes);
+        let is_locked = client.is_locked;
+        let persisted = self.client_manager.persist(&transaction, result.as_ref());
+        if persisted.is_err()
+            && let Ok(outcome) = &result
+        {
+            self.revert(&transaction, outcome);
+        }
+        let result = persisted.and(result);
+        // A transaction that couldn't be stored was taken back, lock included
+        let newly_locked = result.is_ok() && is_locked && !was_locked;
+        self.metrics
+            .record(action, result.as_ref().map(|_| ()), newly_locked, elapsed);
+
+        if !self.observers.is_empty() {
+            match &result {
+                Ok(outcome) => {
+                    let currency = outcome.postings.first().and_then(|p| p.currency);
+                    let booked = self
+                        .balances(client_id)
+                        .into_iter()
+                        .find(|s| s.currency == currency);
+                    if let Some(snapshot) = booked {
+                        self.observers.applied(&transaction, &snapshot);
+                    }
+                    for fee in &outcome.fees {
+                        self.observers.fee(fee);
+                    }
+                }
+                Err(err) => self.observers.rejected(&transaction, err),
+            }
+            if newly_locked {
+                self.observers.locked(client_id);
+            }
+        }
+
+        if let Some(before) = before {
+            for after in self.balances(client_id) {
+                let previous = before.iter().find(|s| s.currency == after.currency);
+                if let Some(change) = BalanceChange::between(id, previous, after) {
+                    self.subscribers.publish(&change);
+                }
+            }
+        }
+        result
+    }
+
+    // Takes back what applying `transaction` did outside of its client once it couldn't be
+    // stored, so memory agrees with the database. The client manager drops the client,
+    // which is reloaded as stored.
+    fn revert(&mut self, transaction: &Transaction, outcome: &TransactionOutcome) {
+        self.ledger.revert(&outcome.ledger);
+        self.house.revert(&outcome.fees);
+        if matches!(
+            transaction.action,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) && let Err(err) = self.history.remove(transaction.client_id, transaction.id)
+        {
+            error!(
+                "[Client {}] Can't forget transaction {}: {err}",
+                transaction.client_id, transaction.id
+            );
+        }
+    }
+
+    fn balances(&mut self, client_id: ClientId) -> Vec<ClientSnapshot> {
+        let client = self.client_manager.get_or_insert_client_mut(client_id);
+        client
+            .snapshots(self.config.base_currency, self.config.charges_fees())
+            .into_iter()
+            .map(|extended| extended.snapshot)
+            .collect()
+    }
+
+    fn apply(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<TransactionOutcome, TransactionProcessError> {
         debug!(
             "[Client {}] Processing transaction: {:?}",
             transaction.client_id, transaction
@@ -86,6 +366,7 @@ where
             return Err(TransactionProcessError::ClientLocked(client.id, id));
         }
 
+        let mut outcome = TransactionOutcome::default();
         match transaction.action {
             TransactionType::Deposit => {
                 // As mentioned elsewhere, if csv + serde weren't giving me problems,
@@ -95,117 +376,274 @@ where
                 // This invariant is *currently* upheld throughout the project, though,
                 // so this error will never be returned.
                 let amount = transaction.amount.ok_or(TransactionProcessError::Unknown)?;
-                client.available += amount;
-                client
-                    .basic_transactions
-                    .insert(transaction.id, transaction);
-                Ok(())
+                let currency = self
+                    .config
+                    .booking_currency(transaction.currency)
+                    .map_err(|_| TransactionProcessError::UnsupportedCurrency(client.id, id))?;
+                if !self.config.currencies.fits_minor_units(currency, amount) {
+                    return Err(TransactionProcessError::TooPrecise(client.id, id));
+                }
+                let fee = self.config.minor_units(
+                    currency,
+                    self.config.fee_schedule.deposit_fee(),
+                    self.config.fee_schedule.rounding,
+                )?;
+                if client.balance(currency).available + amount < fee {
+                    return Err(TransactionProcessError::InsufficientFunds(client.id, id));
+                }
+
+                // Remember the resolved currency so disputes apply in the same one
+                let disputable = Disputable { amount, currency };
+                self.history
+                    .insert(transaction.client_id, id, disputable)
+                    .map_err(history_failed)?;
+                self.ledger.post(
+                    client,
+                    id,
+                    currency,
+                    LedgerAccount::ExternalFunding,
+                    LedgerAccount::Available,
+                    amount,
+                );
+                outcome.post(client, id, currency, amount, Amount::ZERO);
+                outcome.fees.extend(self.house.book(
+                    &mut self.ledger,
+                    client,
+                    currency,
+                    id,
+                    FeeKind::Deposit,
+                    fee,
+                ));
+                Ok(outcome)
             }
             TransactionType::Withdrawal => {
                 let amount = transaction.amount.ok_or(TransactionProcessError::Unknown)?;
-                if client.available < amount {
+                let currency = self
+                    .config
+                    .booking_currency(transaction.currency)
+                    .map_err(|_| TransactionProcessError::UnsupportedCurrency(client.id, id))?;
+                if !self.config.currencies.fits_minor_units(currency, amount) {
+                    return Err(TransactionProcessError::TooPrecise(client.id, id));
+                }
+                let fee = self
+                    .config
+                    .fee_schedule
+                    .withdrawal_fee(amount)
+                    .ok_or(TransactionProcessError::Unknown)?;
+                let fee =
+                    self.config
+                        .minor_units(currency, fee, self.config.fee_schedule.rounding)?;
+                if client.balance(currency).available < amount + fee {
                     return Err(TransactionProcessError::InsufficientFunds(client.id, id));
                 }
 
-                client.available -= amount;
-                client
-                    .basic_transactions
-                    .insert(transaction.id, transaction);
-                Ok(())
+                let disputable = Disputable {
+                    amount: -amount,
+                    currency,
+                };
+                self.history
+                    .insert(transaction.client_id, id, disputable)
+                    .map_err(history_failed)?;
+                self.ledger.post(
+                    client,
+                    id,
+                    currency,
+                    LedgerAccount::Available,
+                    LedgerAccount::ExternalFunding,
+                    amount,
+                );
+                outcome.post(client, id, currency, -amount, Amount::ZERO);
+                outcome.fees.extend(self.house.book(
+                    &mut self.ledger,
+                    client,
+                    currency,
+                    id,
+                    FeeKind::Withdrawal,
+                    fee,
+                ));
+                Ok(outcome)
             }
             TransactionType::Dispute => {
-                let basic_transaction = client.basic_transactions.get(&id).ok_or(
-                    TransactionProcessError::InvalidDisputeNotFound(client.id, id),
-                )?;
+                let disputed = self
+                    .history
+                    .get(client.id, id)
+                    .map_err(history_failed)?
+                    .ok_or(TransactionProcessError::InvalidDisputeNotFound(
+                        client.id, id,
+                    ))?;
                 if !client.disputes.insert(id) {
                     return Err(TransactionProcessError::InvalidDisputeDuplicate(
                         client.id, id,
                     ));
                 }
 
-                let amount = basic_transaction
-                    .amount
-                    .ok_or(TransactionProcessError::Unknown)?;
-
-                // Not sure if charging back a withdrawal (sending money back) makes sense...
-                // TODO (ENHANCEMENT + MAINTAINABILITY): We should have a single variant
-                // for this + simply change amount's sign.
-                match basic_transaction.action {
-                    TransactionType::Deposit => {
-                        client.available -= amount;
-                        client.held += amount;
-                    }
-                    TransactionType::Withdrawal => {
-                        client.available += amount;
-                        client.held -= amount;
-                    }
-                    _ => unreachable!("invariant violated"),
-                }
-                Ok(())
+                // Withdrawals are negated in the history, so disputing one moves funds
+                // from held back to available. Not sure if charging back a withdrawal
+                // (sending money back) makes sense...
+                let held = disputed.amount;
+                self.ledger.post(
+                    client,
+                    id,
+                    disputed.currency,
+                    LedgerAccount::Available,
+                    LedgerAccount::Held,
+                    held,
+                );
+                outcome.post(client, id, disputed.currency, -held, held);
+                Ok(outcome)
             }
             TransactionType::Resolve => {
-                let basic_transaction = client.basic_transactions.get(&id).ok_or(
-                    TransactionProcessError::InvalidResolveNotFound(client.id, id),
-                )?;
+                let disputed = self
+                    .history
+                    .get(client.id, id)
+                    .map_err(history_failed)?
+                    .ok_or(TransactionProcessError::InvalidResolveNotFound(
+                        client.id, id,
+                    ))?;
                 if !client.disputes.remove(&id) {
                     return Err(TransactionProcessError::InvalidResolveNotDisputed(
                         client.id, id,
                     ));
                 }
 
-                let amount = basic_transaction
-                    .amount
-                    .ok_or(TransactionProcessError::Unknown)?;
-
-                // Not sure if charging back a withdrawal (sending money back) makes sense...
-                // TODO (ENHANCEMENT + MAINTAINABILITY): We should have a single variant
-                // for this + simply change amount's sign.
-                match basic_transaction.action {
-                    TransactionType::Deposit => {
-                        client.held -= amount;
-                        client.available += amount;
-                    }
-                    TransactionType::Withdrawal => {
-                        client.held += amount;
-                        client.available -= amount;
-                    }
-                    _ => unreachable!("invariant violated"),
-                }
-                Ok(())
+                let released = disputed.amount;
+                self.ledger.post(
+                    client,
+                    id,
+                    disputed.currency,
+                    LedgerAccount::Held,
+                    LedgerAccount::Available,
+                    released,
+                );
+                outcome.post(client, id, disputed.currency, released, -released);
+                Ok(outcome)
             }
             TransactionType::Chargeback => {
-                let basic_transaction = client.basic_transactions.get(&id).ok_or(
-                    TransactionProcessError::InvalidChargeBackNotFound(client.id, id),
-                )?;
-                if !client.disputes.remove(&id) {
+                let disputed = self
+                    .history
+                    .get(client.id, id)
+                    .map_err(history_failed)?
+                    .ok_or(TransactionProcessError::InvalidChargeBackNotFound(
+                        client.id, id,
+                    ))?;
+                if !client.disputes.contains(&id) {
                     return Err(TransactionProcessError::InvalidChargeBackNotDisputed(
                         client.id, id,
                     ));
                 }
-
-                let amount = basic_transaction
-                    .amount
-                    .ok_or(TransactionProcessError::Unknown)?;
+                let charged_back = disputed.amount;
+                let currency = disputed.currency;
+                // The chargeback fee is owed regardless of the remaining funds, so this
+                // is the one place where `available` may go negative.
+                let fee = self.config.minor_units(
+                    currency,
+                    self.config.fee_schedule.chargeback_fee(),
+                    self.config.fee_schedule.rounding,
+                )?;
 
                 // Should we lock the account if the user charge backs a withdrawal (sends money back)??
+                client.disputes.remove(&id);
                 client.is_locked = true;
+                self.ledger.post(
+                    client,
+                    id,
+                    currency,
+                    LedgerAccount::Held,
+                    LedgerAccount::ChargebackLoss,
+                    charged_back,
+                );
+                outcome.post(client, id, currency, Amount::ZERO, -charged_back);
+                outcome.fees.extend(self.house.book(
+                    &mut self.ledger,
+                    client,
+                    currency,
+                    id,
+                    FeeKind::Chargeback,
+                    fee,
+                ));
+                Ok(outcome)
+            }
+            TransactionType::Convert => {
+                let amount = transaction.amount.ok_or(TransactionProcessError::Unknown)?;
+                let client_id = client.id;
+                let from = self
+                    .config
+                    .booking_currency(transaction.currency)
+                    .map_err(|_| TransactionProcessError::UnsupportedCurrency(client_id, id))?;
+                let to = transaction
+                    .to_currency
+                    .ok_or(TransactionProcessError::InvalidConversion(client_id, id))?;
+                let Some(from) = from.filter(|&from| from != to) else {
+                    return Err(TransactionProcessError::InvalidConversion(client_id, id));
+                };
+                if !self.config.currencies.contains(to) {
+                    return Err(TransactionProcessError::UnsupportedCurrency(client_id, id));
+                }
+                if !self.config.currencies.fits_minor_units(Some(from), amount) {
+                    return Err(TransactionProcessError::TooPrecise(client_id, id));
+                }
 
-                // Not sure if charging back a withdrawal (sending money back) makes sense...
-                // TODO (ENHANCEMENT + MAINTAINABILITY): We should have a single variant
-                // for this + simply change amount's sign.
-                match basic_transaction.action {
-                    TransactionType::Deposit => {
-                        client.held -= amount;
-                    }
-                    TransactionType::Withdrawal => {
-                        client.held += amount;
-                    }
-                    _ => unreachable!("invariant violated"),
+                let rate = self
+                    .config
+                    .fx_rates
+                    .rate(from, to)
+                    .ok_or(TransactionProcessError::FxRateNotFound(client_id, id))?;
+                let rounding = self.config.fx_rounding;
+                let converted = amount
+                    .checked_mul_rate(rate, rounding)
+                    .ok_or(TransactionProcessError::Unknown)?;
+                let converted = self.config.minor_units(Some(to), converted, rounding)?;
+                let spread = converted
+                    .checked_mul_rate(self.config.fx_spread, rounding)
+                    .ok_or(TransactionProcessError::Unknown)?;
+                let spread = self.config.minor_units(Some(to), spread, rounding)?;
+
+                if client.balance(Some(from)).available < amount {
+                    return Err(TransactionProcessError::InsufficientFunds(client_id, id));
                 }
-                Ok(())
+
+                // Conversions aren't disputable, so they're not kept around in `history`.
+                // The house takes the source currency and pays out the target one.
+                self.ledger.post(
+                    client,
+                    id,
+                    Some(from),
+                    LedgerAccount::Available,
+                    LedgerAccount::FxExchange,
+                    amount,
+                );
+                self.ledger.post(
+                    client,
+                    id,
+                    Some(to),
+                    LedgerAccount::FxExchange,
+                    LedgerAccount::Available,
+                    converted,
+                );
+                outcome.post(client, id, Some(from), -amount, Amount::ZERO);
+                outcome.post(client, id, Some(to), converted, Amount::ZERO);
+                outcome.fees.extend(self.house.book(
+                    &mut self.ledger,
+                    client,
+                    Some(to),
+                    id,
+                    FeeKind::FxSpread,
+                    spread,
+                ));
+                Ok(outcome)
             }
         }
     }
+
+    fn snapshots(&self, client: &Client) -> Vec<ExtendedClientSnapshot> {
+        client.snapshots(self.config.base_currency, self.config.charges_fees())
+    }
+}
+
+// Without its history the engine can't tell which disputes are valid anymore
+fn history_failed(err: io::Error) -> TransactionProcessError {
+    error!("Transaction history failed: {err}");
+    TransactionProcessError::Unknown
 }
 
 // Represents a engine for processing all payments in a system
@@ -214,7 +652,42 @@ pub trait PaymentEngine {
     type SnapshotError;
 
     fn process(&mut self, transaction: Transaction) -> Result<(), Self::ProcessError>;
-    fn finalize(self) -> Vec<Result<ClientSnapshot, Self::SnapshotError>>;
+
+    // Same as `process`, but waits until the transaction is applied and hands back its
+    // outcome (e.g., fee lines) and business logic errors instead of only logging them
+    fn process_with_outcome(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<TransactionOutcome, TransactionProcessError>;
+
+    // Current balances of `client_id` without finalizing, e.g., to serve queries while
+    // transactions keep coming in. `None` if the engine has never seen the client.
+    fn client_snapshots(
+        &mut self,
+        client_id: ClientId,
+    ) -> Result<Option<Vec<ExtendedClientSnapshot>>, Self::SnapshotError>;
+
+    // Receives a `BalanceChange` every time a transaction processed from now on changes a
+    // matching client's balances. Dropping the `BalanceChanges` ends the subscription.
+    fn subscribe(&self, subscription: Subscription) -> BalanceChanges;
+
+    // Calls `observer`'s hooks for every transaction processed from now on
+    fn register_observer(&self, observer: Arc<dyn EngineObserver>);
+
+    // Live metrics of everything processed so far, e.g., to render after `finalize`
+    fn metrics(&self) -> Arc<EngineMetrics>;
+
+    fn finalize_extended(self) -> Vec<Result<ExtendedClientSnapshot, Self::SnapshotError>>;
+
+    fn finalize(self) -> Vec<Result<ClientSnapshot, Self::SnapshotError>>
+    where
+        Self: Sized,
+    {
+        self.finalize_extended()
+            .into_iter()
+            .map(|snapshot| snapshot.map(|extended| extended.snapshot))
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -224,7 +697,41 @@ mod processor_tests {
     use googletest::prelude::*;
 
     use super::*;
-    use crate::parse::Amount;
+    use crate::parse::{Amount, Rate, WideAmount};
+    use crate::{Balance, ClientActivity};
+
+    fn ledger_posting(
+        tx: TransactionId,
+        currency: Option<Currency>,
+        from: LedgerAccount,
+        to: LedgerAccount,
+        amount: f64,
+    ) -> LedgerPosting {
+        LedgerPosting {
+            client: 1,
+            tx,
+            currency,
+            from,
+            to,
+            amount: Amount::new(amount).unwrap(),
+        }
+    }
+
+    fn fee_processor() -> TransactionProcessor<MultiClientManager> {
+        TransactionProcessor::with_config(
+            MultiClientManager::default(),
+            EngineConfig {
+                fee_schedule: FeeSchedule {
+                    deposit_flat: Amount::new(0.5).unwrap(),
+                    withdrawal_flat: Amount::new(1.0).unwrap(),
+                    withdrawal_rate: Rate::new(0.01).unwrap(),
+                    chargeback_flat: Amount::new(15.0).unwrap(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+    }
 
     #[gtest]
     pub fn can_not_double_resolve() {
@@ -237,7 +744,7 @@ mod processor_tests {
                 TransactionType::Deposit,
                 Amount::new(3.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
         assert_that!(
             processor.process(Transaction::new(
@@ -246,21 +753,23 @@ mod processor_tests {
                 TransactionType::Deposit,
                 Amount::new(3.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Dispute, None)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Resolve, None,)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Resolve, None,)),
-            err(eq(TransactionProcessError::InvalidResolveNotDisputed(1, 2)))
+            err(eq(&TransactionProcessError::InvalidResolveNotDisputed(
+                1, 2
+            )))
         );
     }
 
@@ -272,7 +781,7 @@ mod processor_tests {
 
         assert_that!(
             processor.process(resolve.clone()),
-            err(eq(TransactionProcessError::InvalidResolveNotFound(1, 2)))
+            err(eq(&TransactionProcessError::InvalidResolveNotFound(1, 2)))
         );
 
         assert_that!(
@@ -282,24 +791,28 @@ mod processor_tests {
                 TransactionType::Deposit,
                 Amount::new(3.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
             processor.process(resolve.clone()),
-            err(eq(TransactionProcessError::InvalidResolveNotDisputed(1, 2)))
+            err(eq(&TransactionProcessError::InvalidResolveNotDisputed(
+                1, 2
+            )))
         );
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Dispute, None)),
-            ok(())
+            ok(anything())
         );
 
-        assert_that!(processor.process(resolve.clone()), ok(()));
+        assert_that!(processor.process(resolve.clone()), ok(anything()));
 
         assert_that!(
             processor.process(resolve),
-            err(eq(TransactionProcessError::InvalidResolveNotDisputed(1, 2)))
+            err(eq(&TransactionProcessError::InvalidResolveNotDisputed(
+                1, 2
+            )))
         );
     }
 
@@ -311,7 +824,9 @@ mod processor_tests {
 
         assert_that!(
             processor.process(chargeback.clone()),
-            err(eq(TransactionProcessError::InvalidChargeBackNotFound(1, 2)))
+            err(eq(&TransactionProcessError::InvalidChargeBackNotFound(
+                1, 2
+            )))
         );
 
         assert_that!(
@@ -321,26 +836,26 @@ mod processor_tests {
                 TransactionType::Deposit,
                 Amount::new(3.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
             processor.process(chargeback.clone()),
-            err(eq(TransactionProcessError::InvalidChargeBackNotDisputed(
+            err(eq(&TransactionProcessError::InvalidChargeBackNotDisputed(
                 1, 2
             )))
         );
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Dispute, None)),
-            ok(())
+            ok(anything())
         );
 
-        assert_that!(processor.process(chargeback.clone()), ok(()));
+        assert_that!(processor.process(chargeback.clone()), ok(anything()));
 
         assert_that!(
             processor.process(chargeback),
-            err(eq(TransactionProcessError::ClientLocked(1, 2)))
+            err(eq(&TransactionProcessError::ClientLocked(1, 2)))
         );
     }
 
@@ -355,7 +870,7 @@ mod processor_tests {
                 TransactionType::Deposit,
                 Amount::new(5.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
@@ -365,12 +880,16 @@ mod processor_tests {
                 TransactionType::Deposit,
                 Amount::new(3.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
-            processor.client_manager.get_or_insert_client_mut(1).deref(),
-            matches_pattern!(&Client {
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(None)
+                .deref(),
+            matches_pattern!(&Balance {
                 available: Amount::new(8.0).unwrap(),
                 held: Amount::from(0),
                 ..
@@ -379,12 +898,16 @@ mod processor_tests {
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Dispute, None)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
-            processor.client_manager.get_or_insert_client_mut(1).deref(),
-            matches_pattern!(&Client {
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(None)
+                .deref(),
+            matches_pattern!(&Balance {
                 available: Amount::new(5.0).unwrap(),
                 held: Amount::new(3.0).unwrap(),
                 ..
@@ -393,12 +916,16 @@ mod processor_tests {
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Dispute, None)),
-            err(eq(TransactionProcessError::InvalidDisputeDuplicate(1, 2))),
+            err(eq(&TransactionProcessError::InvalidDisputeDuplicate(1, 2))),
         );
 
         assert_that!(
-            processor.client_manager.get_or_insert_client_mut(1).deref(),
-            matches_pattern!(&Client {
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(None)
+                .deref(),
+            matches_pattern!(&Balance {
                 available: Amount::new(5.0).unwrap(),
                 held: Amount::new(3.0).unwrap(),
                 ..
@@ -417,12 +944,12 @@ mod processor_tests {
                 TransactionType::Deposit,
                 Amount::new(3.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Dispute, None)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
@@ -435,7 +962,7 @@ mod processor_tests {
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Chargeback, None)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
@@ -458,7 +985,7 @@ mod processor_tests {
                 TransactionType::Deposit,
                 Amount::new(1.5).ok()
             )),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
@@ -468,12 +995,16 @@ mod processor_tests {
                 TransactionType::Deposit,
                 Amount::new(3.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
-            processor.client_manager.get_or_insert_client_mut(1).deref(),
-            matches_pattern!(&Client {
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(None)
+                .deref(),
+            matches_pattern!(&Balance {
                 available: Amount::new(4.5).unwrap(),
                 held: Amount::from(0),
                 ..
@@ -482,12 +1013,16 @@ mod processor_tests {
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Dispute, None)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
-            processor.client_manager.get_or_insert_client_mut(1).deref(),
-            matches_pattern!(&Client {
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(None)
+                .deref(),
+            matches_pattern!(&Balance {
                 available: Amount::new(1.5).unwrap(),
                 held: Amount::new(3.0).unwrap(),
                 ..
@@ -496,12 +1031,16 @@ mod processor_tests {
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Chargeback, None)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
-            processor.client_manager.get_or_insert_client_mut(1).deref(),
-            matches_pattern!(&Client {
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(None)
+                .deref(),
+            matches_pattern!(&Balance {
                 available: Amount::new(1.5).unwrap(),
                 held: Amount::from(0),
                 ..
@@ -530,7 +1069,7 @@ mod processor_tests {
                     transaction_type,
                     Amount::new(3.0).ok()
                 )),
-                err(eq(TransactionProcessError::ClientLocked(1, 2)))
+                err(eq(&TransactionProcessError::ClientLocked(1, 2)))
             );
         }
     }
@@ -549,12 +1088,12 @@ mod processor_tests {
                 TransactionType::Deposit,
                 Amount::new(3.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
             processor.process(Transaction::new(2, 2, TransactionType::Dispute, None)),
-            err(eq(TransactionProcessError::InvalidDisputeNotFound(2, 2)))
+            err(eq(&TransactionProcessError::InvalidDisputeNotFound(2, 2)))
         );
     }
 
@@ -572,17 +1111,17 @@ mod processor_tests {
                 TransactionType::Deposit,
                 Amount::new(3.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Dispute, None)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Chargeback, None)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
@@ -602,6 +1141,84 @@ mod processor_tests {
         );
     }
 
+    #[gtest]
+    fn counts_client_activity_including_rejections() {
+        let mut processor = TransactionProcessor::<MultiClientManager>::default();
+        let transactions = [
+            Transaction::new(1, 1, TransactionType::Deposit, Amount::new(10.0).ok()),
+            Transaction::new(2, 1, TransactionType::Deposit, Amount::new(5.0).ok()),
+            Transaction::new(3, 1, TransactionType::Withdrawal, Amount::new(2.0).ok()),
+            // Insufficient funds
+            Transaction::new(4, 1, TransactionType::Withdrawal, Amount::new(100.0).ok()),
+            Transaction::new(1, 1, TransactionType::Dispute, None),
+            Transaction::new(2, 1, TransactionType::Dispute, None),
+            Transaction::new(2, 1, TransactionType::Chargeback, None),
+            // Locked
+            Transaction::new(5, 1, TransactionType::Deposit, Amount::new(1.0).ok()),
+        ];
+        for transaction in transactions {
+            let _ = processor.process(transaction);
+        }
+
+        let client = processor.client_manager.get_or_insert_client_mut(1);
+        assert_that!(
+            client.activity,
+            eq(ClientActivity {
+                deposits: 2,
+                withdrawals: 1,
+                open_disputes: 1,
+                chargebacks: 1,
+                rejected: 2,
+                last_tx: Some(5),
+            })
+        );
+        let snapshots = processor.snapshots(processor.client_manager.clients.get(&1).unwrap());
+        assert_that!(snapshots[0].activity.rejected, eq(2));
+    }
+
+    #[gtest]
+    fn publishes_balance_changes_to_subscribers() {
+        let mut processor = TransactionProcessor::<MultiClientManager>::default();
+        let client_changes = processor.subscribers.subscribe(Subscription::Client(1));
+        let all_changes = processor.subscribers.subscribe(Subscription::AllClients);
+        let transactions = [
+            Transaction::new(1, 1, TransactionType::Deposit, Amount::new(3.0).ok()),
+            Transaction::new(2, 2, TransactionType::Deposit, Amount::new(1.0).ok()),
+            // Insufficient funds
+            Transaction::new(3, 1, TransactionType::Withdrawal, Amount::new(100.0).ok()),
+            Transaction::new(1, 1, TransactionType::Dispute, None),
+            Transaction::new(1, 1, TransactionType::Chargeback, None),
+        ];
+        for transaction in transactions {
+            let _ = processor.process(transaction);
+        }
+
+        let amount = |value| Amount::new(value).unwrap();
+        assert_that!(
+            client_changes
+                .try_iter()
+                .map(|c| (c.tx, c.available_delta, c.held_delta, c.snapshot.locked))
+                .collect::<Vec<_>>(),
+            elements_are![
+                eq(&(1, amount(3.0), Amount::ZERO, false)),
+                eq(&(1, amount(-3.0), amount(3.0), false)),
+                eq(&(1, Amount::ZERO, amount(-3.0), true)),
+            ]
+        );
+        assert_that!(
+            all_changes
+                .try_iter()
+                .map(|c| (c.snapshot.client, c.tx))
+                .collect::<Vec<_>>(),
+            elements_are![eq(&(1, 1)), eq(&(2, 2)), eq(&(1, 1)), eq(&(1, 1))]
+        );
+
+        // Dropped receivers are unsubscribed without waiting for a change to send them
+        drop(all_changes);
+        assert_that!(processor.subscribers.wants(2), eq(false));
+        assert_that!(processor.subscribers.wants(1), eq(true));
+    }
+
     #[gtest]
     fn dispute_and_resolve_is_noop() {
         let mut processor = TransactionProcessor::<MultiClientManager>::default();
@@ -613,31 +1230,33 @@ mod processor_tests {
                 TransactionType::Deposit,
                 Amount::new(3.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
             processor
                 .client_manager
                 .get_or_insert_client_mut(1)
+                .balance_mut(None)
                 .available,
             eq(Amount::new(3.0).unwrap()),
         );
 
         assert_that!(
             processor.process(Transaction::new(1, 1, TransactionType::Dispute, None)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
             processor.process(Transaction::new(1, 1, TransactionType::Resolve, None)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
             processor
                 .client_manager
                 .get_or_insert_client_mut(1)
+                .balance_mut(None)
                 .available,
             eq(Amount::new(3.0).unwrap()),
         );
@@ -654,7 +1273,7 @@ mod processor_tests {
                 TransactionType::Deposit,
                 Amount::new(3.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
@@ -664,12 +1283,16 @@ mod processor_tests {
                 TransactionType::Withdrawal,
                 Amount::new(1.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
-            processor.client_manager.get_or_insert_client_mut(1).deref(),
-            matches_pattern!(&Client {
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(None)
+                .deref(),
+            matches_pattern!(&Balance {
                 available: Amount::new(2.0).unwrap(),
                 held: Amount::from(0),
                 ..
@@ -678,12 +1301,16 @@ mod processor_tests {
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Dispute, None)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
-            processor.client_manager.get_or_insert_client_mut(1).deref(),
-            matches_pattern!(&Client {
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(None)
+                .deref(),
+            matches_pattern!(&Balance {
                 available: Amount::new(3.0).unwrap(),
                 held: Amount::new(-1.0).unwrap(),
                 ..
@@ -692,12 +1319,16 @@ mod processor_tests {
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Resolve, None)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
-            processor.client_manager.get_or_insert_client_mut(1).deref(),
-            matches_pattern!(&Client {
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(None)
+                .deref(),
+            matches_pattern!(&Balance {
                 available: Amount::new(2.0).unwrap(),
                 held: Amount::from(0),
                 ..
@@ -716,7 +1347,7 @@ mod processor_tests {
                 TransactionType::Deposit,
                 Amount::new(3.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
@@ -726,17 +1357,21 @@ mod processor_tests {
                 TransactionType::Withdrawal,
                 Amount::new(1.0).ok()
             )),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Dispute, None)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
-            processor.client_manager.get_or_insert_client_mut(1).deref(),
-            matches_pattern!(&Client {
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(None)
+                .deref(),
+            matches_pattern!(&Balance {
                 available: Amount::new(3.0).unwrap(),
                 held: Amount::new(-1.0).unwrap(),
                 ..
@@ -745,17 +1380,726 @@ mod processor_tests {
 
         assert_that!(
             processor.process(Transaction::new(2, 1, TransactionType::Chargeback, None)),
-            ok(())
+            ok(anything())
         );
 
         assert_that!(
-            processor.client_manager.get_or_insert_client_mut(1).deref(),
-            matches_pattern!(&Client {
-                is_locked: true,
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(None)
+                .deref(),
+            matches_pattern!(&Balance {
                 available: Amount::new(3.0).unwrap(),
                 held: Amount::from(0),
                 ..
             })
         );
     }
+
+    #[gtest]
+    fn fees_are_debited_from_client_and_credited_to_house() {
+        let mut processor = fee_processor();
+
+        assert_that!(
+            processor.process(Transaction::new(
+                1,
+                1,
+                TransactionType::Deposit,
+                Amount::new(100.0).ok()
+            )),
+            ok(eq(&TransactionOutcome {
+                postings: vec![Posting {
+                    client: 1,
+                    tx: 1,
+                    currency: None,
+                    available: Amount::new(100.0).unwrap(),
+                    held: Amount::ZERO,
+                }],
+                fees: vec![FeeLine {
+                    client: 1,
+                    tx: 1,
+                    currency: None,
+                    kind: FeeKind::Deposit,
+                    amount: Amount::new(0.5).unwrap(),
+                }],
+                ledger: vec![
+                    ledger_posting(
+                        1,
+                        None,
+                        LedgerAccount::ExternalFunding,
+                        LedgerAccount::Available,
+                        100.0
+                    ),
+                    ledger_posting(
+                        1,
+                        None,
+                        LedgerAccount::Available,
+                        LedgerAccount::FeeRevenue,
+                        0.5
+                    ),
+                ],
+            }))
+        );
+
+        assert_that!(
+            processor.process(Transaction::new(
+                2,
+                1,
+                TransactionType::Withdrawal,
+                Amount::new(50.0).ok()
+            )),
+            ok(eq(&TransactionOutcome {
+                postings: vec![Posting {
+                    client: 1,
+                    tx: 2,
+                    currency: None,
+                    available: Amount::new(-50.0).unwrap(),
+                    held: Amount::ZERO,
+                }],
+                fees: vec![FeeLine {
+                    client: 1,
+                    tx: 2,
+                    currency: None,
+                    kind: FeeKind::Withdrawal,
+                    amount: Amount::new(1.5).unwrap(),
+                }],
+                ledger: vec![
+                    ledger_posting(
+                        2,
+                        None,
+                        LedgerAccount::Available,
+                        LedgerAccount::ExternalFunding,
+                        50.0
+                    ),
+                    ledger_posting(
+                        2,
+                        None,
+                        LedgerAccount::Available,
+                        LedgerAccount::FeeRevenue,
+                        1.5
+                    ),
+                ],
+            }))
+        );
+
+        assert_that!(
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(None)
+                .deref(),
+            matches_pattern!(&Balance {
+                available: Amount::new(48.0).unwrap(),
+                held: Amount::from(0),
+                fees: Amount::new(2.0).unwrap(),
+                ..
+            })
+        );
+        assert_that!(
+            processor.house.revenue(None),
+            eq(WideAmount::from(Amount::new(2.0).unwrap()))
+        );
+    }
+
+    #[gtest]
+    fn withdrawal_requires_funds_for_amount_and_fee() {
+        let mut processor = fee_processor();
+
+        assert_that!(
+            processor.process(Transaction::new(
+                1,
+                1,
+                TransactionType::Deposit,
+                Amount::new(10.5).ok()
+            )),
+            ok(anything())
+        );
+
+        // 10.0 available, but withdrawing 9.0 costs 9.0 + 1.0 + 0.09
+        assert_that!(
+            processor.process(Transaction::new(
+                2,
+                1,
+                TransactionType::Withdrawal,
+                Amount::new(9.0).ok()
+            )),
+            err(eq(&TransactionProcessError::InsufficientFunds(1, 2)))
+        );
+
+        assert_that!(
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(None)
+                .available,
+            eq(Amount::new(10.0).unwrap())
+        );
+        assert_that!(
+            processor.house.revenue(None),
+            eq(WideAmount::from(Amount::new(0.5).unwrap()))
+        );
+    }
+
+    #[gtest]
+    fn deposit_smaller_than_fee_is_rejected() {
+        let mut processor = fee_processor();
+
+        assert_that!(
+            processor.process(Transaction::new(
+                1,
+                1,
+                TransactionType::Deposit,
+                Amount::new(0.25).ok()
+            )),
+            err(eq(&TransactionProcessError::InsufficientFunds(1, 1)))
+        );
+        assert_that!(processor.house.revenue(None), eq(WideAmount::ZERO));
+    }
+
+    #[gtest]
+    fn chargeback_is_not_applied_if_its_fee_fails() {
+        let jpy = Currency::new("JPY").unwrap();
+        // Rounding the fee up to whole yen overflows
+        let mut processor = TransactionProcessor::with_config(
+            MultiClientManager::default(),
+            EngineConfig {
+                fee_schedule: FeeSchedule {
+                    chargeback_flat: Amount::MAX,
+                    rounding: RoundingMode::Up,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+
+        for transaction in [
+            Transaction::new(1, 1, TransactionType::Deposit, Amount::new(10.0).ok())
+                .in_currency(jpy),
+            Transaction::new(1, 1, TransactionType::Dispute, None),
+        ] {
+            assert_that!(processor.process(transaction), ok(anything()));
+        }
+        assert_that!(
+            processor.process(Transaction::new(1, 1, TransactionType::Chargeback, None)),
+            err(eq(&TransactionProcessError::Unknown))
+        );
+
+        let client = processor.client_manager.get_or_insert_client_mut(1);
+        expect_that!(client.is_locked, eq(false));
+        expect_that!(client.disputes.contains(&1), eq(true));
+        expect_that!(
+            client.balance(Some(jpy)).held,
+            eq(Amount::new(10.0).unwrap())
+        );
+        expect_that!(
+            processor
+                .ledger
+                .balance(Some(jpy), LedgerAccount::ChargebackLoss),
+            eq(WideAmount::ZERO)
+        );
+    }
+
+    #[gtest]
+    fn chargeback_fee_may_overdraw_client() {
+        let mut processor = fee_processor();
+
+        assert_that!(
+            processor.process(Transaction::new(
+                1,
+                1,
+                TransactionType::Deposit,
+                Amount::new(10.5).ok()
+            )),
+            ok(anything())
+        );
+        assert_that!(
+            processor.process(Transaction::new(1, 1, TransactionType::Dispute, None)),
+            ok(anything())
+        );
+        assert_that!(
+            processor.process(Transaction::new(1, 1, TransactionType::Chargeback, None)),
+            ok(eq(&TransactionOutcome {
+                postings: vec![Posting {
+                    client: 1,
+                    tx: 1,
+                    currency: None,
+                    available: Amount::ZERO,
+                    held: Amount::new(-10.5).unwrap(),
+                }],
+                fees: vec![FeeLine {
+                    client: 1,
+                    tx: 1,
+                    currency: None,
+                    kind: FeeKind::Chargeback,
+                    amount: Amount::new(15.0).unwrap(),
+                }],
+                ledger: vec![
+                    ledger_posting(
+                        1,
+                        None,
+                        LedgerAccount::Held,
+                        LedgerAccount::ChargebackLoss,
+                        10.5
+                    ),
+                    ledger_posting(
+                        1,
+                        None,
+                        LedgerAccount::Available,
+                        LedgerAccount::FeeRevenue,
+                        15.0
+                    ),
+                ],
+            }))
+        );
+
+        assert_that!(
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(None)
+                .deref(),
+            matches_pattern!(&Balance {
+                available: Amount::new(-15.5).unwrap(),
+                held: Amount::from(0),
+                fees: Amount::new(15.5).unwrap(),
+                ..
+            })
+        );
+        // The chargeback's loss and the fee leave the ledger balanced
+        let clients = processor.client_manager.clients.values();
+        assert_that!(processor.ledger.trial_balance(clients), ok(anything()));
+    }
+
+    #[gtest]
+    fn rejects_amounts_finer_than_the_currency_minor_units() {
+        let mut processor = fee_processor();
+        let usd = Currency::new("USD").unwrap();
+        let jpy = Currency::new("JPY").unwrap();
+
+        assert_that!(
+            processor.process(
+                Transaction::new(1, 1, TransactionType::Deposit, Amount::new(0.5).ok())
+                    .in_currency(jpy)
+            ),
+            err(eq(&TransactionProcessError::TooPrecise(1, 1)))
+        );
+        assert_that!(
+            processor.process(
+                Transaction::new(2, 1, TransactionType::Deposit, Amount::new(0.0001).ok())
+                    .in_currency(usd)
+            ),
+            err(eq(&TransactionProcessError::TooPrecise(1, 2)))
+        );
+        assert_that!(
+            processor.process(
+                Transaction::new(3, 1, TransactionType::Deposit, Amount::new(100.0).ok())
+                    .in_currency(usd)
+            ),
+            ok(anything())
+        );
+        // The 1.0 + 1% fee on 12.34 is 1.1234, which is rounded to cents
+        assert_that!(
+            processor.process(
+                Transaction::new(4, 1, TransactionType::Withdrawal, Amount::new(12.34).ok())
+                    .in_currency(usd)
+            ),
+            ok(field!(
+                TransactionOutcome.fees,
+                elements_are![field!(FeeLine.amount, eq(&Amount::new(1.12).unwrap()))]
+            ))
+        );
+        assert_that!(
+            processor.process(
+                Transaction::new(5, 1, TransactionType::Withdrawal, Amount::new(1.001).ok())
+                    .in_currency(usd)
+            ),
+            err(eq(&TransactionProcessError::TooPrecise(1, 5)))
+        );
+    }
+
+    #[gtest]
+    fn balances_are_kept_per_currency_and_disputes_use_original_currency() {
+        let mut processor = TransactionProcessor::<MultiClientManager>::default();
+        let usd = Currency::new("USD").unwrap();
+        let eur = Currency::new("EUR").unwrap();
+
+        assert_that!(
+            processor.process(
+                Transaction::new(1, 1, TransactionType::Deposit, Amount::new(10.0).ok())
+                    .in_currency(usd)
+            ),
+            ok(anything())
+        );
+        assert_that!(
+            processor.process(
+                Transaction::new(2, 1, TransactionType::Deposit, Amount::new(5.0).ok())
+                    .in_currency(eur)
+            ),
+            ok(anything())
+        );
+
+        // The dispute row doesn't say which currency, but the deposit it refers to does
+        assert_that!(
+            processor.process(Transaction::new(2, 1, TransactionType::Dispute, None)),
+            ok(anything())
+        );
+
+        let client = processor.client_manager.get_or_insert_client_mut(1);
+        assert_that!(
+            client.balance_mut(Some(usd)).deref(),
+            matches_pattern!(&Balance {
+                available: Amount::new(10.0).unwrap(),
+                held: Amount::from(0),
+                ..
+            })
+        );
+        assert_that!(
+            client.balance_mut(Some(eur)).deref(),
+            matches_pattern!(&Balance {
+                available: Amount::from(0),
+                held: Amount::new(5.0).unwrap(),
+                ..
+            })
+        );
+    }
+
+    #[gtest]
+    fn withdrawal_can_not_use_funds_in_another_currency() {
+        let mut processor = TransactionProcessor::<MultiClientManager>::default();
+
+        assert_that!(
+            processor.process(
+                Transaction::new(1, 1, TransactionType::Deposit, Amount::new(10.0).ok())
+                    .in_currency(Currency::new("USD").unwrap())
+            ),
+            ok(anything())
+        );
+        assert_that!(
+            processor.process(
+                Transaction::new(2, 1, TransactionType::Withdrawal, Amount::new(1.0).ok())
+                    .in_currency(Currency::new("EUR").unwrap())
+            ),
+            err(eq(&TransactionProcessError::InsufficientFunds(1, 2)))
+        );
+        // The rejected withdrawal doesn't open a (zero) EUR balance
+        let client = processor.client_manager.get_or_insert_client_mut(1);
+        expect_that!(
+            client.balances.keys().collect::<Vec<_>>(),
+            elements_are![eq(&&Some(Currency::new("USD").unwrap()))]
+        );
+    }
+
+    #[gtest]
+    fn currencies_outside_configured_list_are_rejected() {
+        let mut processor = TransactionProcessor::with_config(
+            MultiClientManager::default(),
+            EngineConfig {
+                currencies: "USD".parse().unwrap(),
+                ..Default::default()
+            },
+        );
+
+        assert_that!(
+            processor.process(
+                Transaction::new(1, 1, TransactionType::Deposit, Amount::new(10.0).ok())
+                    .in_currency(Currency::new("EUR").unwrap())
+            ),
+            err(eq(&TransactionProcessError::UnsupportedCurrency(1, 1)))
+        );
+        assert_that!(
+            processor.process(
+                Transaction::new(2, 1, TransactionType::Deposit, Amount::new(10.0).ok())
+                    .in_currency(Currency::new("XYZ").unwrap())
+            ),
+            err(eq(&TransactionProcessError::UnsupportedCurrency(1, 2)))
+        );
+    }
+
+    #[gtest]
+    fn transactions_without_currency_use_base_currency() {
+        let usd = Currency::new("USD").unwrap();
+        let mut processor = TransactionProcessor::with_config(
+            MultiClientManager::default(),
+            EngineConfig {
+                base_currency: Some(usd),
+                ..Default::default()
+            },
+        );
+
+        assert_that!(
+            processor.process(Transaction::new(
+                1,
+                1,
+                TransactionType::Deposit,
+                Amount::new(10.0).ok()
+            )),
+            ok(anything())
+        );
+        assert_that!(
+            processor.process(
+                Transaction::new(2, 1, TransactionType::Withdrawal, Amount::new(4.0).ok())
+                    .in_currency(usd)
+            ),
+            ok(anything())
+        );
+
+        let client = processor.client_manager.get_or_insert_client_mut(1);
+        assert_that!(
+            client.balance_mut(Some(usd)).available,
+            eq(Amount::new(6.0).unwrap())
+        );
+        assert_that!(client.balances.len(), eq(1));
+    }
+
+    fn fx_processor() -> TransactionProcessor<MultiClientManager> {
+        let mut fx_rates = FxRateTable::default();
+        fx_rates.insert(crate::fx::FxRate {
+            from: Currency::new("USD").unwrap(),
+            to: Currency::new("EUR").unwrap(),
+            rate: Rate::new(0.9).unwrap(),
+            date: None,
+        });
+
+        TransactionProcessor::with_config(
+            MultiClientManager::default(),
+            EngineConfig {
+                fx_rates: Arc::new(fx_rates),
+                fx_spread: Rate::new(0.01).unwrap(),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[gtest]
+    fn config_rejects_spreads_that_take_the_whole_conversion() {
+        let config = |spread: f64| EngineConfig {
+            fx_spread: Rate::new(spread).unwrap(),
+            ..Default::default()
+        };
+
+        expect_that!(config(0.0).validate(), ok(anything()));
+        expect_that!(config(0.9999).validate(), ok(anything()));
+        expect_that!(
+            config(1.0).validate(),
+            err(eq(EngineConfigError::FxSpreadOutOfRange))
+        );
+        expect_that!(
+            config(1.5).validate(),
+            err(eq(EngineConfigError::FxSpreadOutOfRange))
+        );
+        expect_that!(
+            config(-0.1).validate(),
+            err(eq(EngineConfigError::FxSpreadOutOfRange))
+        );
+    }
+
+    #[gtest]
+    fn convert_moves_funds_between_currencies_and_books_spread() {
+        let mut processor = fx_processor();
+        let usd = Currency::new("USD").unwrap();
+        let eur = Currency::new("EUR").unwrap();
+
+        assert_that!(
+            processor.process(
+                Transaction::new(1, 1, TransactionType::Deposit, Amount::new(100.0).ok())
+                    .in_currency(usd)
+            ),
+            ok(anything())
+        );
+
+        // 50 USD => 45 EUR, of which 1% (0.45 EUR) is the house's spread
+        assert_that!(
+            processor.process(
+                Transaction::new(2, 1, TransactionType::Convert, Amount::new(50.0).ok())
+                    .in_currency(usd)
+                    .to_currency(eur)
+            ),
+            ok(eq(&TransactionOutcome {
+                postings: vec![
+                    Posting {
+                        client: 1,
+                        tx: 2,
+                        currency: Some(usd),
+                        available: Amount::new(-50.0).unwrap(),
+                        held: Amount::ZERO,
+                    },
+                    Posting {
+                        client: 1,
+                        tx: 2,
+                        currency: Some(eur),
+                        available: Amount::new(45.0).unwrap(),
+                        held: Amount::ZERO,
+                    },
+                ],
+                fees: vec![FeeLine {
+                    client: 1,
+                    tx: 2,
+                    currency: Some(eur),
+                    kind: FeeKind::FxSpread,
+                    amount: Amount::new(0.45).unwrap(),
+                }],
+                ledger: vec![
+                    ledger_posting(
+                        2,
+                        Some(usd),
+                        LedgerAccount::Available,
+                        LedgerAccount::FxExchange,
+                        50.0
+                    ),
+                    ledger_posting(
+                        2,
+                        Some(eur),
+                        LedgerAccount::FxExchange,
+                        LedgerAccount::Available,
+                        45.0
+                    ),
+                    ledger_posting(
+                        2,
+                        Some(eur),
+                        LedgerAccount::Available,
+                        LedgerAccount::FeeRevenue,
+                        0.45
+                    ),
+                ],
+            }))
+        );
+
+        let client = processor.client_manager.get_or_insert_client_mut(1);
+        assert_that!(
+            client.balance_mut(Some(usd)).available,
+            eq(Amount::new(50.0).unwrap())
+        );
+        assert_that!(
+            client.balance_mut(Some(eur)).available,
+            eq(Amount::new(44.55).unwrap())
+        );
+        assert_that!(
+            processor.house.revenue(Some(eur)),
+            eq(WideAmount::from(Amount::new(0.45).unwrap()))
+        );
+
+        // Conversions can't be disputed
+        assert_that!(
+            processor.process(Transaction::new(2, 1, TransactionType::Dispute, None)),
+            err(eq(&TransactionProcessError::InvalidDisputeNotFound(1, 2)))
+        );
+        let clients = processor.client_manager.clients.values();
+        assert_that!(processor.ledger.trial_balance(clients), ok(anything()));
+    }
+
+    #[gtest]
+    fn convert_requires_funds_and_a_rate() {
+        let mut processor = fx_processor();
+        let usd = Currency::new("USD").unwrap();
+        let eur = Currency::new("EUR").unwrap();
+
+        assert_that!(
+            processor.process(
+                Transaction::new(1, 1, TransactionType::Deposit, Amount::new(10.0).ok())
+                    .in_currency(usd)
+            ),
+            ok(anything())
+        );
+        assert_that!(
+            processor.process(
+                Transaction::new(2, 1, TransactionType::Convert, Amount::new(20.0).ok())
+                    .in_currency(usd)
+                    .to_currency(eur)
+            ),
+            err(eq(&TransactionProcessError::InsufficientFunds(1, 2)))
+        );
+        assert_that!(
+            processor.process(
+                Transaction::new(3, 1, TransactionType::Convert, Amount::new(5.0).ok())
+                    .in_currency(usd)
+                    .to_currency(Currency::new("GBP").unwrap())
+            ),
+            err(eq(&TransactionProcessError::FxRateNotFound(1, 3)))
+        );
+        assert_that!(
+            processor.process(
+                Transaction::new(4, 1, TransactionType::Convert, Amount::new(5.0).ok())
+                    .in_currency(usd)
+                    .to_currency(usd)
+            ),
+            err(eq(&TransactionProcessError::InvalidConversion(1, 4)))
+        );
+        assert_that!(
+            processor.process(
+                Transaction::new(5, 1, TransactionType::Convert, Amount::new(5.0).ok())
+                    .in_currency(usd)
+            ),
+            err(eq(&TransactionProcessError::InvalidConversion(1, 5)))
+        );
+
+        assert_that!(
+            processor
+                .client_manager
+                .get_or_insert_client_mut(1)
+                .balance_mut(Some(usd))
+                .available,
+            eq(Amount::new(10.0).unwrap())
+        );
+    }
+
+    #[gtest]
+    fn disputes_reload_spilled_transactions() {
+        let usd = Currency::new("USD").unwrap();
+        let mut transactions = Vec::new();
+        for tx in 1..=20 {
+            let deposit = Transaction::new(
+                tx,
+                (tx % 3) as ClientId,
+                TransactionType::Deposit,
+                Amount::new(f64::from(tx)).ok(),
+            );
+            transactions.push(if tx % 2 == 0 {
+                deposit.in_currency(usd)
+            } else {
+                deposit
+            });
+        }
+        for tx in [1, 4, 7, 20] {
+            transactions.push(Transaction::new(
+                tx,
+                (tx % 3) as ClientId,
+                TransactionType::Dispute,
+                None,
+            ));
+        }
+        transactions.push(Transaction::new(4, 1, TransactionType::Chargeback, None));
+        transactions.push(Transaction::new(7, 1, TransactionType::Resolve, None));
+
+        let mut unbounded = TransactionProcessor::<MultiClientManager>::default();
+        let mut spilling = TransactionProcessor::with_config(
+            MultiClientManager::default(),
+            EngineConfig {
+                history: HistoryConfig {
+                    memory_budget: Some(1),
+                    spill_dir: None,
+                },
+                ..Default::default()
+            },
+        );
+        for transaction in transactions {
+            assert_that!(
+                spilling.process(transaction.clone()),
+                eq(&unbounded.process(transaction))
+            );
+        }
+
+        for client in 0..3 {
+            expect_that!(
+                spilling
+                    .client_manager
+                    .get_or_insert_client_mut(client)
+                    .deref(),
+                eq(unbounded
+                    .client_manager
+                    .get_or_insert_client_mut(client)
+                    .deref())
+            );
+        }
+    }
 }
@@ -0,0 +1,82 @@
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard};
+
+use super::TransactionProcessError;
+use super::fees::FeeLine;
+use crate::{ClientId, ClientSnapshot, Transaction};
+
+// Hooks called as the engine processes transactions, e.g., to layer metrics, audit
+// logging or alerting on top of it. Every hook defaults to doing nothing.
+//
+// Hooks run on the thread processing the transaction (a worker thread for the stream
+// engine) before the next transaction is processed, so they should be cheap.
+pub trait EngineObserver: Send + Sync {
+    // `snapshot` is the balance the transaction was booked in, after applying it. For a
+    // conversion, that's the source currency's.
+    fn on_applied(&self, _transaction: &Transaction, _snapshot: &ClientSnapshot) {}
+
+    fn on_rejected(&self, _transaction: &Transaction, _error: &TransactionProcessError) {}
+
+    // Called after `on_applied` for every fee the transaction charged, in its outcome's
+    // order
+    fn on_fee(&self, _fee: &FeeLine) {}
+
+    // Called after `on_applied` for the transaction that locked the client
+    fn on_locked(&self, _client: ClientId) {}
+}
+
+// Shared by every processor of an engine, like `Subscribers`
+#[derive(Clone, Default)]
+pub(crate) struct Observers {
+    observers: Arc<RwLock<Vec<Arc<dyn EngineObserver>>>>,
+}
+
+impl std::fmt::Debug for Observers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Observers")
+            .field("len", &self.read().len())
+            .finish()
+    }
+}
+
+impl Observers {
+    pub(crate) fn register(&self, observer: Arc<dyn EngineObserver>) {
+        self.observers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(observer);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    pub(crate) fn applied(&self, transaction: &Transaction, snapshot: &ClientSnapshot) {
+        for observer in self.read().iter() {
+            observer.on_applied(transaction, snapshot);
+        }
+    }
+
+    pub(crate) fn rejected(&self, transaction: &Transaction, error: &TransactionProcessError) {
+        for observer in self.read().iter() {
+            observer.on_rejected(transaction, error);
+        }
+    }
+
+    pub(crate) fn fee(&self, fee: &FeeLine) {
+        for observer in self.read().iter() {
+            observer.on_fee(fee);
+        }
+    }
+
+    pub(crate) fn locked(&self, client: ClientId) {
+        for observer in self.read().iter() {
+            observer.on_locked(client);
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Vec<Arc<dyn EngineObserver>>> {
+        self.observers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
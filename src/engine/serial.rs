@@ -1,3 +1,6 @@
+use super::checkpoint::EngineState;
+use super::history::Disputable;
+use super::ledger::TrialBalanceError;
 use super::*;
 
 pub type Engine = SerialPaymentEngine;
@@ -8,27 +11,142 @@ pub struct SerialPaymentEngine {
     processor: TransactionProcessor<MultiClientManager>,
 }
 
+impl SerialPaymentEngine {
+    pub fn with_config(config: EngineConfig) -> Self {
+        Self {
+            processor: TransactionProcessor::with_config(MultiClientManager::default(), config),
+        }
+    }
+
+    // Picks up where the engine that captured `state` left off, e.g., before a restart.
+    // Fails if the restored transactions don't fit the memory budget and can't be spilled.
+    pub fn restore(config: EngineConfig, state: EngineState) -> io::Result<Self> {
+        let mut engine = Self::with_config(config);
+        let (clients, transactions, house, ledger) = state.restore();
+        engine.processor.client_manager.clients = clients
+            .into_iter()
+            .map(|client| (client.id, client))
+            .collect();
+        for transaction in transactions {
+            if let Some(disputable) = Disputable::of(&transaction) {
+                let history = &engine.processor.history;
+                history.insert(transaction.client_id, transaction.id, disputable)?;
+            }
+        }
+        engine.processor.house = house;
+        engine.processor.ledger = ledger;
+        Ok(engine)
+    }
+
+    // Everything `restore` needs, e.g., to checkpoint the engine. Fails if spilled
+    // transactions can't be read back.
+    pub fn state(&self) -> io::Result<EngineState> {
+        Ok(EngineState::capture(
+            self.processor.client_manager.clients.values(),
+            self.processor.history.transactions()?,
+            &self.processor.ledger,
+        ))
+    }
+
+    pub fn house_account(&self) -> &HouseAccount {
+        &self.processor.house
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.processor.ledger
+    }
+
+    // Checks that no money was created or destroyed, i.e., that the ledger and every
+    // client's balances sum to zero in each currency
+    pub fn trial_balance(&self) -> Result<(), TrialBalanceError> {
+        let clients = self.processor.client_manager.clients.values();
+        self.processor.ledger.trial_balance(clients)
+    }
+
+    // Same as `PaymentEngine::process`, but hands back the outcome (e.g., fee lines)
+    // and business logic errors instead of only logging them.
+    pub fn process_with_outcome(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<TransactionOutcome, TransactionProcessError> {
+        self.processor.process(transaction)
+    }
+
+    // Current balances of `client_id` without finalizing, e.g., to serve queries while
+    // transactions keep coming in. `None` if the engine has never seen the client.
+    pub fn client_snapshots(&self, client_id: ClientId) -> Option<Vec<ExtendedClientSnapshot>> {
+        let client = self.processor.client_manager.clients.get(&client_id)?;
+        Some(self.processor.snapshots(client))
+    }
+
+    // Same as `client_snapshots`, for every client in client id order
+    pub fn snapshots(&self) -> Vec<ExtendedClientSnapshot> {
+        let clients = &self.processor.client_manager.clients;
+        let mut ids = clients.keys().copied().collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.into_iter()
+            .flat_map(|id| self.processor.snapshots(&clients[&id]))
+            .collect()
+    }
+}
+
 impl PaymentEngine for SerialPaymentEngine {
     type ProcessError = TransactionProcessError;
     type SnapshotError = anyhow::Error;
 
     fn process(&mut self, transaction: Transaction) -> Result<(), Self::ProcessError> {
-        if let Err(err) = self.processor.process(transaction) {
-            // Silently fail + log if business logic error per PDF instructions
-            error!("{}", err);
-            if let TransactionProcessError::Unknown = err {
-                return Err(err);
+        match self.processor.process(transaction) {
+            Ok(outcome) => {
+                for fee in outcome.fees {
+                    debug!("[Client {}] Charged fee: {:?}", fee.client, fee);
+                }
+            }
+            Err(err) => {
+                // Silently fail + log if business logic error per PDF instructions
+                error!("{}", err);
+                if let TransactionProcessError::Unknown = err {
+                    return Err(err);
+                }
             }
         }
 
         Ok(())
     }
 
-    fn finalize(self) -> Vec<Result<ClientSnapshot, Self::SnapshotError>> {
-        let clients = self.processor.client_manager.clients;
-        let mut results = Vec::with_capacity(clients.len());
+    fn process_with_outcome(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<TransactionOutcome, TransactionProcessError> {
+        SerialPaymentEngine::process_with_outcome(self, transaction)
+    }
+
+    fn client_snapshots(
+        &mut self,
+        client_id: ClientId,
+    ) -> Result<Option<Vec<ExtendedClientSnapshot>>, Self::SnapshotError> {
+        Ok(SerialPaymentEngine::client_snapshots(self, client_id))
+    }
+
+    fn subscribe(&self, subscription: Subscription) -> BalanceChanges {
+        self.processor.subscribers.subscribe(subscription)
+    }
+
+    fn register_observer(&self, observer: Arc<dyn EngineObserver>) {
+        self.processor.observers.register(observer);
+    }
+
+    fn metrics(&self) -> Arc<EngineMetrics> {
+        Arc::clone(&self.processor.metrics)
+    }
+
+    fn finalize_extended(self) -> Vec<Result<ExtendedClientSnapshot, Self::SnapshotError>> {
+        let clients = &self.processor.client_manager.clients;
+        let mut results = Vec::with_capacity(clients.len() + 1);
+        if let Err(err) = self.processor.ledger.trial_balance(clients.values()) {
+            results.push(Err(err.into()));
+        }
         for client in clients.values() {
-            results.push(Ok(ClientSnapshot::from(client)));
+            results.extend(self.processor.snapshots(client).into_iter().map(Ok));
         }
         results
     }
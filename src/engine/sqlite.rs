@@ -0,0 +1,909 @@
+use std::path::Path;
+
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::de::IntoDeserializer;
+use serde::{Deserialize, Serialize};
+
+use super::history::Disputable;
+use super::*;
+use crate::parse::WideAmount;
+use crate::{Balance, ClientActivity};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS clients (
+        client INTEGER PRIMARY KEY,
+        locked INTEGER NOT NULL,
+        deposits INTEGER NOT NULL,
+        withdrawals INTEGER NOT NULL,
+        open_disputes INTEGER NOT NULL,
+        chargebacks INTEGER NOT NULL,
+        rejected INTEGER NOT NULL,
+        last_tx INTEGER
+    );
+    -- Amounts are `Amount` units, and '' is the currency-less balance
+    CREATE TABLE IF NOT EXISTS balances (
+        client INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        available INTEGER NOT NULL,
+        held INTEGER NOT NULL,
+        fees INTEGER NOT NULL,
+        PRIMARY KEY (client, currency)
+    );
+    -- Applied deposits and withdrawals, which can be disputed. `dispute` is one of
+    -- none, disputed, resolved or charged_back.
+    CREATE TABLE IF NOT EXISTS transactions (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        dispute TEXT NOT NULL DEFAULT 'none',
+        PRIMARY KEY (client, tx)
+    );
+    -- Every transaction processed, in order, with the error it was rejected with
+    CREATE TABLE IF NOT EXISTS journal (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        amount INTEGER,
+        currency TEXT,
+        to_currency TEXT,
+        error TEXT
+    );
+    CREATE INDEX IF NOT EXISTS journal_by_client ON journal (client, seq);
+    -- Balances of the ledger's accounts that aren't a client's, e.g., external_funding
+    CREATE TABLE IF NOT EXISTS ledger (
+        currency TEXT NOT NULL,
+        account TEXT NOT NULL,
+        balance INTEGER NOT NULL,
+        PRIMARY KEY (currency, account)
+    );
+";
+
+// One processed transaction, as answered by history queries
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct JournalEntry {
+    #[serde(rename = "type")]
+    pub action: TransactionType,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Option<Amount>,
+    pub currency: Option<Currency>,
+    pub to_currency: Option<Currency>,
+    pub applied: bool,
+    // Code of the `TransactionProcessError` the transaction was rejected with, e.g.,
+    // `insufficient_funds`
+    pub error: Option<String>,
+}
+
+// Keeps every client's balances, disputable transactions and their dispute states, lock
+// flag and activity in a SQLite database, so each run picks up where the previous one
+// left off (e.g., one input file a day) and history is answered from disk.
+//
+// Clients are loaded the first time a transaction needs them and stay cached for the
+// rest of the run. Everything a transaction changes is written in a single SQLite
+// transaction, so the database never reflects part of one.
+#[derive(Debug)]
+pub struct SqlitePaymentEngine {
+    processor: TransactionProcessor<SqliteClientManager>,
+}
+
+impl SqlitePaymentEngine {
+    // Creates the database if needed
+    pub fn open(path: impl AsRef<Path>, config: EngineConfig) -> anyhow::Result<Self> {
+        config.validate()?;
+        let history = TransactionHistory::new(&config.history);
+        let client_manager = SqliteClientManager::open(path, history.clone())?;
+        let house = client_manager.house_account()?;
+        let ledger = client_manager.ledger()?;
+        let mut processor = TransactionProcessor::with_config(client_manager, config);
+        processor.house = house;
+        processor.ledger = ledger;
+        processor.history = history;
+        Ok(Self { processor })
+    }
+
+    pub fn house_account(&self) -> &HouseAccount {
+        &self.processor.house
+    }
+
+    // Checks that no money was created or destroyed, i.e., that the ledger and every
+    // client's balances in the database sum to zero in each currency
+    pub fn trial_balance(&mut self) -> anyhow::Result<()> {
+        let client_manager = &mut self.processor.client_manager;
+        let ledger = client_manager.ledger()?;
+        for id in client_manager.client_ids()? {
+            client_manager.load(id)?;
+        }
+        ledger.trial_balance(client_manager.clients.values())?;
+        Ok(())
+    }
+
+    // Every transaction processed for `client_id`, across every run, in processing order
+    pub fn history(&self, client_id: ClientId) -> anyhow::Result<Vec<JournalEntry>> {
+        let connection = &self.processor.client_manager.connection;
+        let mut statement = connection.prepare_cached(
+            "SELECT type, client, tx, amount, currency, to_currency, error
+             FROM journal WHERE client = ?1 ORDER BY seq",
+        )?;
+        let rows = statement.query_map([client_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get::<_, Option<i64>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get::<_, Option<String>>(6)?,
+            ))
+        })?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let (action, client, tx, amount, currency, to_currency, error) = row?;
+            entries.push(JournalEntry {
+                action: transaction_type(&action)?,
+                client,
+                tx,
+                amount: amount.map(Amount::from),
+                currency: currency.as_deref().map(Currency::new).transpose()?,
+                to_currency: to_currency.as_deref().map(Currency::new).transpose()?,
+                applied: error.is_none(),
+                error,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+impl PaymentEngine for SqlitePaymentEngine {
+    type ProcessError = TransactionProcessError;
+    type SnapshotError = anyhow::Error;
+
+    fn process(&mut self, transaction: Transaction) -> Result<(), Self::ProcessError> {
+        match self.processor.process(transaction) {
+            Ok(outcome) => {
+                for fee in outcome.fees {
+                    debug!("[Client {}] Charged fee: {:?}", fee.client, fee);
+                }
+            }
+            Err(err) => {
+                // Silently fail + log if business logic error per PDF instructions
+                error!("{}", err);
+                if let TransactionProcessError::Unknown = err {
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_with_outcome(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<TransactionOutcome, TransactionProcessError> {
+        self.processor.process(transaction)
+    }
+
+    // `None` if the database has never seen the client
+    fn client_snapshots(
+        &mut self,
+        client_id: ClientId,
+    ) -> Result<Option<Vec<ExtendedClientSnapshot>>, Self::SnapshotError> {
+        if !self.processor.client_manager.load(client_id)? {
+            return Ok(None);
+        }
+        let client = &self.processor.client_manager.clients[&client_id];
+        Ok(Some(self.processor.snapshots(client)))
+    }
+
+    fn subscribe(&self, subscription: Subscription) -> BalanceChanges {
+        self.processor.subscribers.subscribe(subscription)
+    }
+
+    fn register_observer(&self, observer: Arc<dyn EngineObserver>) {
+        self.processor.observers.register(observer);
+    }
+
+    fn metrics(&self) -> Arc<EngineMetrics> {
+        Arc::clone(&self.processor.metrics)
+    }
+
+    // Every client in the database, not just the ones this run processed transactions for
+    fn finalize_extended(mut self) -> Vec<Result<ExtendedClientSnapshot, Self::SnapshotError>> {
+        let ids = match self.processor.client_manager.client_ids() {
+            Ok(ids) => ids,
+            Err(err) => return vec![Err(err.into())],
+        };
+
+        let mut results = Vec::with_capacity(ids.len() + 1);
+        if let Err(err) = self.trial_balance() {
+            results.push(Err(err));
+        }
+        for id in ids {
+            match self.client_snapshots(id) {
+                Ok(snapshots) => results.extend(snapshots.into_iter().flatten().map(Ok)),
+                Err(err) => results.push(Err(err)),
+            }
+        }
+        results
+    }
+}
+
+#[derive(Debug)]
+struct SqliteClientManager {
+    connection: Connection,
+    clients: HashMap<ClientId, Client>,
+    // The processor's, which a loaded client's disputable transactions go into
+    history: TransactionHistory,
+    // Set if a client couldn't be loaded, which fails the transaction that needed it
+    load_error: Option<anyhow::Error>,
+}
+
+impl SqliteClientManager {
+    fn open(path: impl AsRef<Path>, history: TransactionHistory) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        // With a write-ahead log, committing doesn't wait for the database file itself
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            connection,
+            clients: HashMap::new(),
+            history,
+            load_error: None,
+        })
+    }
+
+    fn house_account(&self) -> anyhow::Result<HouseAccount> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT currency, fees FROM balances WHERE fees != 0")?;
+        let mut fees = Vec::new();
+        for row in statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })? {
+            let (currency, units) = row?;
+            fees.push((currency_from_column(&currency)?, Amount::from(units)));
+        }
+        Ok(HouseAccount::from_balance_fees(fees))
+    }
+
+    // Databases created before the ledger existed start from opening balances
+    fn ledger(&self) -> anyhow::Result<Ledger> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT currency, account, balance FROM ledger")?;
+        let mut accounts = Vec::new();
+        for row in statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        })? {
+            let (currency, account, units) = row?;
+            accounts.push((
+                currency_from_column(&currency)?,
+                ledger_account(&account)?,
+                WideAmount::from(i128::from(units)),
+            ));
+        }
+        if !accounts.is_empty() {
+            return Ok(Ledger::from_accounts(accounts));
+        }
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT currency, available, held, fees FROM balances")?;
+        let mut balances = Vec::new();
+        for row in statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                Balance {
+                    available: Amount::from(row.get::<_, i64>(1)?),
+                    held: Amount::from(row.get::<_, i64>(2)?),
+                    fees: Amount::from(row.get::<_, i64>(3)?),
+                },
+            ))
+        })? {
+            let (currency, balance) = row?;
+            balances.push((currency_from_column(&currency)?, balance));
+        }
+        let ledger = Ledger::opening(balances);
+        let mut insert = self.connection.prepare_cached(
+            "INSERT INTO ledger (currency, account, balance) VALUES (?1, ?2, ?3)",
+        )?;
+        for (currency, account, balance) in ledger.accounts() {
+            insert.execute(params![
+                currency_column(currency.as_ref()),
+                account.as_str(),
+                i64::try_from(balance.units())?,
+            ])?;
+        }
+        Ok(ledger)
+    }
+
+    fn client_ids(&self) -> rusqlite::Result<Vec<ClientId>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT client FROM clients ORDER BY client")?;
+        statement.query_map([], |row| row.get(0))?.collect()
+    }
+
+    // Caches the client from the database unless it already is. False if the database
+    // has never seen it.
+    fn load(&mut self, client_id: ClientId) -> anyhow::Result<bool> {
+        if self.clients.contains_key(&client_id) {
+            return Ok(true);
+        }
+        let Some(client) = self.read_client(client_id)? else {
+            return Ok(false);
+        };
+        self.clients.insert(client_id, client);
+        Ok(true)
+    }
+
+    fn read_client(&self, client_id: ClientId) -> anyhow::Result<Option<Client>> {
+        let row = self
+            .connection
+            .prepare_cached(
+                "SELECT locked, deposits, withdrawals, open_disputes, chargebacks, rejected,
+                        last_tx
+                 FROM clients WHERE client = ?1",
+            )?
+            .query_row([client_id], |row| {
+                Ok((
+                    row.get(0)?,
+                    ClientActivity {
+                        deposits: row.get(1)?,
+                        withdrawals: row.get(2)?,
+                        open_disputes: row.get(3)?,
+                        chargebacks: row.get(4)?,
+                        rejected: row.get(5)?,
+                        last_tx: row.get(6)?,
+                    },
+                ))
+            })
+            .optional()?;
+        let Some((is_locked, activity)) = row else {
+            return Ok(None);
+        };
+
+        let mut client = Client::new(client_id);
+        client.is_locked = is_locked;
+        client.activity = activity;
+
+        let mut balances = self.connection.prepare_cached(
+            "SELECT currency, available, held, fees FROM balances WHERE client = ?1",
+        )?;
+        let rows = balances.query_map([client_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                Balance {
+                    available: Amount::from(row.get::<_, i64>(1)?),
+                    held: Amount::from(row.get::<_, i64>(2)?),
+                    fees: Amount::from(row.get::<_, i64>(3)?),
+                },
+            ))
+        })?;
+        for row in rows {
+            let (currency, balance) = row?;
+            client
+                .balances
+                .insert(currency_from_column(&currency)?, balance);
+        }
+
+        let mut transactions = self.connection.prepare_cached(
+            "SELECT tx, type, amount, currency, dispute FROM transactions WHERE client = ?1",
+        )?;
+        let rows = transactions.query_map([client_id], |row| {
+            Ok((
+                row.get(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+            ))
+        })?;
+        for row in rows {
+            let (id, action, amount, currency, dispute) = row?;
+            let amount = Amount::from(amount);
+            let disputable = Disputable {
+                amount: match transaction_type(&action)? {
+                    TransactionType::Withdrawal => -amount,
+                    _ => amount,
+                },
+                currency: currency_from_column(&currency)?,
+            };
+            self.history.insert(client_id, id, disputable)?;
+            if dispute == DisputeState::Disputed.as_str() {
+                client.disputes.insert(id);
+            }
+        }
+        Ok(Some(client))
+    }
+
+    fn write(
+        &mut self,
+        transaction: &Transaction,
+        result: Result<&TransactionOutcome, &TransactionProcessError>,
+    ) -> anyhow::Result<()> {
+        let client = &self.clients[&transaction.client_id];
+        let db = self.connection.transaction()?;
+
+        let activity = &client.activity;
+        db.prepare_cached(
+            "INSERT OR REPLACE INTO clients
+             (client, locked, deposits, withdrawals, open_disputes, chargebacks, rejected, last_tx)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?
+        .execute(params![
+            client.id,
+            client.is_locked,
+            activity.deposits,
+            activity.withdrawals,
+            activity.open_disputes,
+            activity.chargebacks,
+            activity.rejected,
+            activity.last_tx,
+        ])?;
+
+        if let Ok(outcome) = result {
+            // Rejected transactions don't change any balance
+            let mut balances = db.prepare_cached(
+                "INSERT OR REPLACE INTO balances (client, currency, available, held, fees)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for (currency, balance) in &client.balances {
+                balances.execute(params![
+                    client.id,
+                    currency_column(currency.as_ref()),
+                    balance.available.units(),
+                    balance.held.units(),
+                    balance.fees.units(),
+                ])?;
+            }
+            drop(balances);
+
+            let dispute = match transaction.action {
+                TransactionType::Deposit | TransactionType::Withdrawal => {
+                    // Booked in the resolved currency, which may not be the input's
+                    let currency = outcome.postings.first().and_then(|p| p.currency);
+                    db.prepare_cached(
+                        "INSERT INTO transactions (client, tx, type, amount, currency)
+                         VALUES (?1, ?2, ?3, ?4, ?5)
+                         ON CONFLICT (client, tx) DO UPDATE
+                         SET type = excluded.type, amount = excluded.amount,
+                             currency = excluded.currency",
+                    )?
+                    .execute(params![
+                        client.id,
+                        transaction.id,
+                        transaction.action.as_str(),
+                        transaction.amount.unwrap_or_default().units(),
+                        currency_column(currency.as_ref()),
+                    ])?;
+                    None
+                }
+                TransactionType::Dispute => Some(DisputeState::Disputed),
+                TransactionType::Resolve => Some(DisputeState::Resolved),
+                TransactionType::Chargeback => Some(DisputeState::ChargedBack),
+                TransactionType::Convert => None,
+            };
+            if let Some(dispute) = dispute {
+                db.prepare_cached(
+                    "UPDATE transactions SET dispute = ?3 WHERE client = ?1 AND tx = ?2",
+                )?
+                .execute(params![client.id, transaction.id, dispute.as_str()])?;
+            }
+
+            // The client's side of every posting is already in its balances
+            let mut ledger = db.prepare_cached(
+                "INSERT INTO ledger (currency, account, balance) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (currency, account) DO UPDATE
+                 SET balance = balance + excluded.balance",
+            )?;
+            for posting in &outcome.ledger {
+                let currency = currency_column(posting.currency.as_ref());
+                for (account, units) in [
+                    (posting.from, -posting.amount.units()),
+                    (posting.to, posting.amount.units()),
+                ] {
+                    if !account.is_client() {
+                        ledger.execute(params![currency, account.as_str(), units])?;
+                    }
+                }
+            }
+        }
+
+        db.prepare_cached(
+            "INSERT INTO journal (client, tx, type, amount, currency, to_currency, error)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?
+        .execute(params![
+            transaction.client_id,
+            transaction.id,
+            transaction.action.as_str(),
+            transaction.amount.map(|amount| amount.units()),
+            transaction.currency.as_ref().map(Currency::as_str),
+            transaction.to_currency.as_ref().map(Currency::as_str),
+            result.err().map(TransactionProcessError::code),
+        ])?;
+        db.commit()?;
+        Ok(())
+    }
+}
+
+impl ClientManager for SqliteClientManager {
+    fn get_or_insert_client_mut(&mut self, client_id: ClientId) -> &mut Client {
+        if let Err(err) = self.load(client_id) {
+            self.load_error = Some(err);
+        }
+        self.clients
+            .entry(client_id)
+            .or_insert_with(|| Client::new(client_id))
+    }
+
+    // A transaction that can't be stored is dropped from the cache too, so the client is
+    // reloaded as the database has it
+    fn persist(
+        &mut self,
+        transaction: &Transaction,
+        result: Result<&TransactionOutcome, &TransactionProcessError>,
+    ) -> Result<(), TransactionProcessError> {
+        let stored = match self.load_error.take() {
+            Some(err) => Err(err),
+            None => self.write(transaction, result),
+        };
+        stored.map_err(|err| {
+            error!(
+                "[Client {}] Can't store transaction {}: {err:#}",
+                transaction.client_id, transaction.id
+            );
+            self.clients.remove(&transaction.client_id);
+            TransactionProcessError::Unknown
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum DisputeState {
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl DisputeState {
+    const fn as_str(self) -> &'static str {
+        match self {
+            DisputeState::Disputed => "disputed",
+            DisputeState::Resolved => "resolved",
+            DisputeState::ChargedBack => "charged_back",
+        }
+    }
+}
+
+fn currency_column(currency: Option<&Currency>) -> &str {
+    currency.map_or("", Currency::as_str)
+}
+
+fn currency_from_column(currency: &str) -> anyhow::Result<Option<Currency>> {
+    Ok(match currency {
+        "" => None,
+        code => Some(Currency::new(code)?),
+    })
+}
+
+fn transaction_type(name: &str) -> anyhow::Result<TransactionType> {
+    TransactionType::deserialize(name.into_deserializer())
+        .map_err(|err: serde::de::value::Error| anyhow::anyhow!("Unknown transaction type: {err}"))
+}
+
+fn ledger_account(name: &str) -> anyhow::Result<LedgerAccount> {
+    LedgerAccount::deserialize(name.into_deserializer())
+        .map_err(|err: serde::de::value::Error| anyhow::anyhow!("Unknown ledger account: {err}"))
+}
+
+#[cfg(test)]
+mod sqlite_tests {
+    use googletest::prelude::*;
+
+    use super::*;
+    use crate::engine::fees::FeeSchedule;
+    use crate::parse::WideAmount;
+
+    fn temp_database(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "payment-engine-sqlite-{}-{name}.db",
+            std::process::id()
+        ));
+        remove_database(&path);
+        path
+    }
+
+    fn remove_database(path: &Path) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut file = path.as_os_str().to_owned();
+            file.push(suffix);
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    fn config() -> EngineConfig {
+        EngineConfig {
+            fee_schedule: FeeSchedule {
+                deposit_flat: Amount::new(0.5).unwrap(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn amount(value: f64) -> Option<Amount> {
+        Some(Amount::new(value).unwrap())
+    }
+
+    #[gtest]
+    pub fn picks_up_where_the_previous_run_left_off() {
+        let path = temp_database("reopen");
+        let usd = Currency::new("USD").unwrap();
+        let mut first_run = SqlitePaymentEngine::open(&path, config()).unwrap();
+        for transaction in [
+            Transaction::new(1, 1, TransactionType::Deposit, amount(10.0)),
+            Transaction::new(2, 1, TransactionType::Deposit, amount(4.0)).in_currency(usd),
+            Transaction::new(1, 1, TransactionType::Dispute, None),
+            Transaction::new(3, 2, TransactionType::Deposit, amount(3.0)),
+            Transaction::new(3, 2, TransactionType::Dispute, None),
+            Transaction::new(3, 2, TransactionType::Chargeback, None),
+        ] {
+            first_run.process(transaction).unwrap();
+        }
+        let expected = first_run.client_snapshots(1).unwrap();
+        drop(first_run);
+
+        let mut second_run = SqlitePaymentEngine::open(&path, config()).unwrap();
+        let reopened = second_run.client_snapshots(1).unwrap();
+        let revenue = second_run.house_account().revenue(None);
+        // Tx 1 is still disputed, and client 2 still locked
+        let resolve =
+            second_run.process_with_outcome(Transaction::new(1, 1, TransactionType::Resolve, None));
+        let locked = second_run.process_with_outcome(Transaction::new(
+            4,
+            2,
+            TransactionType::Deposit,
+            amount(1.0),
+        ));
+        let unknown = second_run.client_snapshots(3).unwrap();
+        drop(second_run);
+
+        let mut third_run = SqlitePaymentEngine::open(&path, config()).unwrap();
+        let snapshots = third_run.client_snapshots(1).unwrap().unwrap();
+        let trial_balance = third_run.trial_balance();
+        let loss = third_run
+            .processor
+            .ledger
+            .balance(None, LedgerAccount::ChargebackLoss);
+        let finalized = third_run
+            .finalize()
+            .into_iter()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        remove_database(&path);
+
+        expect_that!(reopened, eq(&expected));
+        expect_that!(revenue, eq(WideAmount::from(Amount::new(1.0).unwrap())));
+        expect_that!(resolve, ok(anything()));
+        expect_that!(
+            locked,
+            err(eq(&TransactionProcessError::ClientLocked(2, 4)))
+        );
+        expect_that!(unknown, none());
+        expect_that!(
+            snapshots
+                .iter()
+                .map(|s| (s.snapshot.available, s.snapshot.held))
+                .collect::<Vec<_>>(),
+            elements_are![
+                eq(&(Amount::new(9.5).unwrap(), Amount::ZERO)),
+                eq(&(Amount::new(3.5).unwrap(), Amount::ZERO)),
+            ]
+        );
+        expect_that!(
+            finalized.iter().map(|s| s.client).collect::<Vec<_>>(),
+            elements_are![eq(&1), eq(&1), eq(&2)]
+        );
+        expect_that!(trial_balance, ok(anything()));
+        expect_that!(loss, eq(WideAmount::from(Amount::new(3.0).unwrap())));
+    }
+
+    #[gtest]
+    pub fn opens_databases_without_a_ledger_with_opening_balances() {
+        let path = temp_database("opening");
+        let mut engine = SqlitePaymentEngine::open(&path, config()).unwrap();
+        for transaction in [
+            Transaction::new(1, 1, TransactionType::Deposit, amount(10.0)),
+            Transaction::new(1, 1, TransactionType::Dispute, None),
+        ] {
+            engine.process(transaction).unwrap();
+        }
+        // As if it was created before the ledger existed
+        let connection = &engine.processor.client_manager.connection;
+        connection.execute("DELETE FROM ledger", []).unwrap();
+        drop(engine);
+
+        let mut reopened = SqlitePaymentEngine::open(&path, config()).unwrap();
+        let opened = reopened.trial_balance();
+        let resolve =
+            reopened.process_with_outcome(Transaction::new(1, 1, TransactionType::Resolve, None));
+        let resolved = reopened.trial_balance();
+        let funding = reopened
+            .processor
+            .ledger
+            .balance(None, LedgerAccount::ExternalFunding);
+        remove_database(&path);
+
+        expect_that!(opened, ok(anything()));
+        expect_that!(resolve, ok(anything()));
+        expect_that!(resolved, ok(anything()));
+        expect_that!(funding, eq(WideAmount::from(Amount::new(-10.0).unwrap())));
+    }
+
+    #[gtest]
+    pub fn answers_history_from_disk_including_rejections() {
+        let path = temp_database("history");
+        let mut engine = SqlitePaymentEngine::open(&path, EngineConfig::default()).unwrap();
+        for transaction in [
+            Transaction::new(1, 7, TransactionType::Deposit, amount(2.0)),
+            Transaction::new(2, 7, TransactionType::Withdrawal, amount(5.0)),
+            Transaction::new(3, 8, TransactionType::Deposit, amount(1.0)),
+            Transaction::new(1, 7, TransactionType::Dispute, None),
+        ] {
+            engine.process(transaction).unwrap();
+        }
+        drop(engine);
+
+        let history = SqlitePaymentEngine::open(&path, EngineConfig::default())
+            .unwrap()
+            .history(7)
+            .unwrap();
+        remove_database(&path);
+
+        expect_that!(
+            history,
+            elements_are![
+                matches_pattern!(JournalEntry {
+                    action: eq(&TransactionType::Deposit),
+                    tx: eq(&1),
+                    amount: eq(&amount(2.0)),
+                    applied: eq(&true),
+                    ..
+                }),
+                matches_pattern!(JournalEntry {
+                    action: eq(&TransactionType::Withdrawal),
+                    applied: eq(&false),
+                    error: some(eq("insufficient_funds")),
+                    ..
+                }),
+                matches_pattern!(JournalEntry {
+                    action: eq(&TransactionType::Dispute),
+                    amount: none(),
+                    applied: eq(&true),
+                    ..
+                }),
+            ]
+        );
+    }
+
+    #[gtest]
+    pub fn rejected_transactions_open_no_balance() {
+        let path = temp_database("rejected-balance");
+        let usd = Currency::new("USD").unwrap();
+        let eur = Currency::new("EUR").unwrap();
+        let mut engine = SqlitePaymentEngine::open(&path, EngineConfig::default()).unwrap();
+        for transaction in [
+            Transaction::new(1, 1, TransactionType::Deposit, amount(10.0)).in_currency(usd),
+            Transaction::new(2, 1, TransactionType::Withdrawal, amount(5.0)).in_currency(eur),
+        ] {
+            engine.process(transaction).unwrap();
+        }
+        drop(engine);
+
+        let snapshots = SqlitePaymentEngine::open(&path, EngineConfig::default())
+            .unwrap()
+            .finalize()
+            .into_iter()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        remove_database(&path);
+
+        expect_that!(
+            snapshots,
+            elements_are![matches_pattern!(ClientSnapshot {
+                currency: eq(&Some(usd)),
+                available: eq(&Amount::new(10.0).unwrap()),
+                ..
+            })]
+        );
+    }
+
+    #[gtest]
+    pub fn takes_back_transactions_that_cant_be_stored() {
+        #[derive(Default)]
+        struct Locks(std::sync::Mutex<Vec<ClientId>>);
+
+        impl EngineObserver for Locks {
+            fn on_locked(&self, client: ClientId) {
+                self.0.lock().unwrap().push(client);
+            }
+        }
+
+        let path = temp_database("write-failure");
+        let mut engine = SqlitePaymentEngine::open(&path, config()).unwrap();
+        let locks = Arc::new(Locks::default());
+        engine.register_observer(locks.clone());
+        for transaction in [
+            Transaction::new(1, 1, TransactionType::Deposit, amount(10.0)),
+            Transaction::new(1, 1, TransactionType::Dispute, None),
+        ] {
+            engine.process(transaction).unwrap();
+        }
+        // Fails every write until the journal is back
+        let other = Connection::open(&path).unwrap();
+        other
+            .execute_batch("ALTER TABLE journal RENAME TO journal_away")
+            .unwrap();
+        let failed = engine.process_with_outcome(Transaction::new(
+            2,
+            1,
+            TransactionType::Deposit,
+            amount(5.0),
+        ));
+        let charged_back =
+            engine.process_with_outcome(Transaction::new(1, 1, TransactionType::Chargeback, None));
+        other
+            .execute_batch("ALTER TABLE journal_away RENAME TO journal")
+            .unwrap();
+
+        let revenue = engine.house_account().revenue(None);
+        let funding = engine
+            .processor
+            .ledger
+            .balance(None, LedgerAccount::ExternalFunding);
+        let dispute =
+            engine.process_with_outcome(Transaction::new(2, 1, TransactionType::Dispute, None));
+        let trial_balance = engine.trial_balance();
+        let in_memory = engine
+            .processor
+            .ledger
+            .trial_balance(engine.processor.client_manager.clients.values());
+        let snapshots = engine.client_snapshots(1).unwrap().unwrap();
+        let metrics = engine.metrics().render();
+        drop(engine);
+        remove_database(&path);
+
+        expect_that!(failed, err(eq(&TransactionProcessError::Unknown)));
+        expect_that!(charged_back, err(eq(&TransactionProcessError::Unknown)));
+        expect_that!(revenue, eq(WideAmount::from(Amount::new(0.5).unwrap())));
+        expect_that!(funding, eq(WideAmount::from(Amount::new(-10.0).unwrap())));
+        expect_that!(
+            dispute,
+            err(eq(&TransactionProcessError::InvalidDisputeNotFound(1, 2)))
+        );
+        expect_that!(trial_balance, ok(anything()));
+        expect_that!(in_memory, ok(anything()));
+        expect_that!(
+            snapshots[0].snapshot,
+            matches_pattern!(ClientSnapshot {
+                available: eq(&Amount::new(-0.5).unwrap()),
+                held: eq(&Amount::new(10.0).unwrap()),
+                locked: eq(&false),
+                ..
+            })
+        );
+        expect_that!(metrics, contains_substring("payment_engine_locks_total 0"));
+        expect_that!(*locks.0.lock().unwrap(), elements_are![]);
+    }
+}
@@ -1,6 +1,6 @@
 use crossbeam::channel::{Receiver, SendError, Sender};
 use log::info;
-use std::{collections::HashMap, thread::JoinHandle};
+use std::{collections::HashMap, thread::JoinHandle, time::Instant};
 
 use super::*;
 
@@ -12,52 +12,132 @@ pub type Engine = StreamPaymentEngine;
 // for this example problem, but I want to show that I understand how this can be done
 // if transaction processing was more expensive (e.g., database calls, more compute-heavy
 // calculations, etc.)
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct StreamPaymentEngine {
-    client_workers: HashMap<ClientId, JoinHandle<Result<ClientSnapshot, TransactionProcessError>>>,
-    senders: HashMap<ClientId, Sender<Transaction>>,
+    client_workers:
+        HashMap<ClientId, JoinHandle<Result<Vec<ExtendedClientSnapshot>, TransactionProcessError>>>,
+    senders: HashMap<ClientId, Sender<Work>>,
     num_enqueued_transactions: usize,
+    config: EngineConfig,
+    // Shared by every worker only with a memory budget, so the budget applies to the
+    // whole engine. Otherwise, each worker keeps its own and never waits on the others.
+    history: Option<TransactionHistory>,
+    subscribers: Subscribers,
+    observers: Observers,
+    metrics: Arc<EngineMetrics>,
+}
+
+// What a client's worker is asked to do, in the order it was asked
+#[derive(Debug)]
+enum Work {
+    // Sent with the time it was enqueued to measure worker latency, and where to send the
+    // result if the caller waits for it
+    Process(Transaction, Instant, Option<Sender<ProcessResult>>),
+    Snapshots(Sender<Vec<ExtendedClientSnapshot>>),
+}
+
+type ProcessResult = Result<TransactionOutcome, TransactionProcessError>;
+
+impl Default for StreamPaymentEngine {
+    fn default() -> Self {
+        Self::with_config(EngineConfig::default())
+    }
 }
 
 impl StreamPaymentEngine {
+    // Each worker books fees into its own house account, so the house's total revenue
+    // is the sum of the `fees` reported in the client snapshots.
+    pub fn with_config(config: EngineConfig) -> Self {
+        Self {
+            client_workers: HashMap::new(),
+            senders: HashMap::new(),
+            num_enqueued_transactions: 0,
+            history: config
+                .history
+                .memory_budget
+                .is_some()
+                .then(|| TransactionHistory::new(&config.history)),
+            config,
+            subscribers: Subscribers::default(),
+            observers: Observers::default(),
+            metrics: Arc::new(EngineMetrics::with_queue()),
+        }
+    }
+
     fn client_worker_thread(
-        client_id: ClientId,
-        receiver: Receiver<Transaction>,
-    ) -> Result<ClientSnapshot, TransactionProcessError> {
-        let client_manager = SingleClientManager::new(client_id);
-        let mut processor = TransactionProcessor::new(client_manager);
-        while let Ok(transaction) = receiver.recv() {
-            if let Err(err) = processor.process(transaction) {
-                // Silently fail + log if business logic error per PDF instructions
-                error!("{}", err);
-                if let TransactionProcessError::Unknown = err {
-                    return Err(err);
+        mut processor: TransactionProcessor<SingleClientManager>,
+        receiver: Receiver<Work>,
+    ) -> Result<Vec<ExtendedClientSnapshot>, TransactionProcessError> {
+        let client_id = processor.get_client_manager().client.id;
+        while let Ok(work) = receiver.recv() {
+            let (transaction, enqueued, reply) = match work {
+                Work::Process(transaction, enqueued, reply) => (transaction, enqueued, reply),
+                Work::Snapshots(reply) => {
+                    let client = &processor.get_client_manager().client;
+                    // Only fails if the caller stopped waiting
+                    let _ = reply.send(processor.snapshots(client));
+                    continue;
                 }
             };
+
+            let result = processor.process(transaction);
+            processor.metrics.record_dequeued(enqueued.elapsed());
+            let failed = matches!(result, Err(TransactionProcessError::Unknown));
+            match (result, reply) {
+                (result, Some(reply)) => {
+                    let _ = reply.send(result);
+                }
+                (Ok(outcome), None) => {
+                    for fee in outcome.fees {
+                        debug!("[Client {}] Charged fee: {:?}", fee.client, fee);
+                    }
+                }
+                // Silently fail + log if business logic error per PDF instructions
+                (Err(err), None) => error!("{}", err),
+            }
+            if failed {
+                return Err(TransactionProcessError::Unknown);
+            }
         }
 
-        Ok(processor.get_client_manager().generate_snapshot())
+        // Each worker posts to its own ledger, which only has to balance its client
+        let client = &processor.get_client_manager().client;
+        if let Err(err) = processor.ledger.trial_balance([client]) {
+            error!("[Client {client_id}] {err}");
+            return Err(TransactionProcessError::Unknown);
+        }
+        Ok(processor.snapshots(client))
     }
-}
-
-impl PaymentEngine for StreamPaymentEngine {
-    type ProcessError = SendError<Transaction>;
-    type SnapshotError = TransactionProcessError;
 
-    fn process(&mut self, transaction: Transaction) -> Result<(), Self::ProcessError> {
+    // Hands `transaction` to its client's worker, spawning the worker if it's the
+    // client's first transaction
+    fn enqueue(
+        &mut self,
+        transaction: Transaction,
+        reply: Option<Sender<ProcessResult>>,
+    ) -> Result<(), SendError<Transaction>> {
         self.num_enqueued_transactions += 1;
         let client_id = transaction.client_id;
         let sender = self.senders.entry(client_id).or_insert_with(|| {
+            let client_manager = SingleClientManager::new(client_id);
+            let mut processor =
+                TransactionProcessor::with_config(client_manager, self.config.clone());
+            if let Some(history) = &self.history {
+                processor.history = history.clone();
+            }
+            processor.subscribers = self.subscribers.clone();
+            processor.observers = self.observers.clone();
+            processor.metrics = Arc::clone(&self.metrics);
             // TODO (PERF): Would probably be faster to use Ringbuf SPSC bounded channel, but then
             // we need to handle backpressure appropriately... not going to do that in this exercise
-            let (sender, receiver) = crossbeam::channel::unbounded::<Transaction>();
+            let (sender, receiver) = crossbeam::channel::unbounded();
 
             info!("[Client {client_id}] spawning worker");
             self.client_workers.insert(
                 client_id,
                 // TODO (PERF + CORRECTNESS): threadpool, otherwise, we have N threads
                 // where N = # unique clients. Obviously, this won't scale.
-                std::thread::spawn(move || Self::client_worker_thread(client_id, receiver)),
+                std::thread::spawn(move || Self::client_worker_thread(processor, receiver)),
             );
             sender
         });
@@ -66,19 +146,83 @@ impl PaymentEngine for StreamPaymentEngine {
             "[Client {client_id}] Enqueueing transaction: {:?}",
             transaction
         );
-        sender.send(transaction)
+        self.metrics.record_enqueued();
+        sender
+            .send(Work::Process(transaction, Instant::now(), reply))
+            .map_err(|SendError(work)| match work {
+                Work::Process(transaction, ..) => SendError(transaction),
+                Work::Snapshots(_) => unreachable!("only transactions are enqueued"),
+            })
+    }
+}
+
+impl PaymentEngine for StreamPaymentEngine {
+    type ProcessError = SendError<Transaction>;
+    type SnapshotError = TransactionProcessError;
+
+    fn process(&mut self, transaction: Transaction) -> Result<(), Self::ProcessError> {
+        self.enqueue(transaction, None)
+    }
+
+    // Waits for the client's worker to get through everything enqueued before it
+    fn process_with_outcome(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<TransactionOutcome, TransactionProcessError> {
+        let (reply, result) = crossbeam::channel::bounded(1);
+        // The worker only goes away after an unknown error
+        self.enqueue(transaction, Some(reply))
+            .map_err(|_| TransactionProcessError::Unknown)?;
+        result
+            .recv()
+            .unwrap_or(Err(TransactionProcessError::Unknown))
+    }
+
+    // Asks the client's worker, so the balances include everything enqueued before
+    fn client_snapshots(
+        &mut self,
+        client_id: ClientId,
+    ) -> Result<Option<Vec<ExtendedClientSnapshot>>, Self::SnapshotError> {
+        let Some(sender) = self.senders.get(&client_id) else {
+            return Ok(None);
+        };
+        let (reply, snapshots) = crossbeam::channel::bounded(1);
+        sender
+            .send(Work::Snapshots(reply))
+            .map_err(|_| TransactionProcessError::Unknown)?;
+        snapshots
+            .recv()
+            .map(Some)
+            .map_err(|_| TransactionProcessError::Unknown)
+    }
+
+    // Changes arrive as workers get to the transactions, so changes to different
+    // clients may be interleaved differently than their transactions were enqueued
+    fn subscribe(&self, subscription: Subscription) -> BalanceChanges {
+        self.subscribers.subscribe(subscription)
+    }
+
+    fn register_observer(&self, observer: Arc<dyn EngineObserver>) {
+        self.observers.register(observer);
     }
 
-    fn finalize(self) -> Vec<Result<ClientSnapshot, Self::SnapshotError>> {
+    fn metrics(&self) -> Arc<EngineMetrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    fn finalize_extended(self) -> Vec<Result<ExtendedClientSnapshot, Self::SnapshotError>> {
         // notify workers to finish up...
         drop(self.senders);
 
         let mut results = Vec::with_capacity(self.client_workers.len());
         for handle in self.client_workers.into_values() {
-            let result = handle
+            match handle
                 .join()
-                .unwrap_or(Err(TransactionProcessError::Unknown));
-            results.push(result);
+                .unwrap_or(Err(TransactionProcessError::Unknown))
+            {
+                Ok(snapshots) => results.extend(snapshots.into_iter().map(Ok)),
+                Err(err) => results.push(Err(err)),
+            }
         }
         results
     }
@@ -95,10 +239,6 @@ impl SingleClientManager {
             client: Client::new(client_id),
         }
     }
-
-    pub fn generate_snapshot(&self) -> ClientSnapshot {
-        ClientSnapshot::from(&self.client)
-    }
 }
 
 impl ClientManager for SingleClientManager {
@@ -111,10 +251,6 @@ impl<C> TransactionProcessor<C>
 where
     C: ClientManager,
 {
-    fn new(client_manager: C) -> Self {
-        TransactionProcessor { client_manager }
-    }
-
     fn get_client_manager(&self) -> &C {
         &self.client_manager
     }
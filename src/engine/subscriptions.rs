@@ -0,0 +1,154 @@
+use std::sync::mpsc::{self, Receiver, RecvError, RecvTimeoutError, Sender, TryIter};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::parse::Amount;
+use crate::{ClientId, ClientSnapshot, TransactionId};
+
+// Whose balance changes a subscriber receives
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subscription {
+    Client(ClientId),
+    AllClients,
+}
+
+impl Subscription {
+    fn matches(self, client: ClientId) -> bool {
+        match self {
+            Subscription::Client(id) => id == client,
+            Subscription::AllClients => true,
+        }
+    }
+}
+
+// Sent every time a transaction changes one of a client's balances or its lock state.
+// A conversion changes two balances, so it's reported as two changes. `snapshot` is the
+// balance after the change, and the deltas are how much it moved.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BalanceChange {
+    pub tx: TransactionId,
+    pub available_delta: Amount,
+    pub held_delta: Amount,
+    #[serde(flatten)]
+    pub snapshot: ClientSnapshot,
+}
+
+impl BalanceChange {
+    // `None` if nothing observable changed. A balance the client didn't have before
+    // counts as having been zero.
+    pub(crate) fn between(
+        tx: TransactionId,
+        before: Option<&ClientSnapshot>,
+        after: ClientSnapshot,
+    ) -> Option<Self> {
+        let change = match before {
+            Some(before) if *before == after => return None,
+            Some(before) => BalanceChange {
+                tx,
+                available_delta: after.available - before.available,
+                held_delta: after.held - before.held,
+                snapshot: after,
+            },
+            None if after.available.is_zero() && after.held.is_zero() => return None,
+            None => BalanceChange {
+                tx,
+                available_delta: after.available,
+                held_delta: after.held,
+                snapshot: after,
+            },
+        };
+        Some(change)
+    }
+}
+
+// The receiving end of a subscription. Dropping it unsubscribes: the engine forgets the
+// subscription the next time it checks for subscribers, i.e., on the next transaction
+// or subscription, not only once a change would be sent to it.
+#[derive(Debug)]
+pub struct BalanceChanges {
+    receiver: Receiver<BalanceChange>,
+    // The engine only keeps a `Weak` to this, to tell when the receiver is gone
+    _alive: Arc<()>,
+}
+
+impl BalanceChanges {
+    pub fn recv(&self) -> Result<BalanceChange, RecvError> {
+        self.receiver.recv()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<BalanceChange, RecvTimeoutError> {
+        self.receiver.recv_timeout(timeout)
+    }
+
+    pub fn try_iter(&self) -> TryIter<'_, BalanceChange> {
+        self.receiver.try_iter()
+    }
+}
+
+// Blocks for every change until the engine is dropped
+impl Iterator for BalanceChanges {
+    type Item = BalanceChange;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    subscription: Subscription,
+    sender: Sender<BalanceChange>,
+    alive: Weak<()>,
+}
+
+// Shared by every processor of an engine (e.g., the stream engine's workers), so a
+// subscription made at any point sees changes from all of them
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Subscribers {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl Subscribers {
+    pub(crate) fn subscribe(&self, subscription: Subscription) -> BalanceChanges {
+        let (sender, receiver) = mpsc::channel();
+        let alive = Arc::new(());
+        self.live().push(Subscriber {
+            subscription,
+            sender,
+            alive: Arc::downgrade(&alive),
+        });
+        BalanceChanges {
+            receiver,
+            _alive: alive,
+        }
+    }
+
+    // Lets the processor skip diffing balances when nobody is listening
+    pub(crate) fn wants(&self, client: ClientId) -> bool {
+        self.live()
+            .iter()
+            .any(|subscriber| subscriber.subscription.matches(client))
+    }
+
+    pub(crate) fn publish(&self, change: &BalanceChange) {
+        self.lock().retain(|subscriber| {
+            !subscriber.subscription.matches(change.snapshot.client)
+                || subscriber.sender.send(change.clone()).is_ok()
+        });
+    }
+
+    // Without the subscribers whose `BalanceChanges` were dropped
+    fn live(&self) -> MutexGuard<'_, Vec<Subscriber>> {
+        let mut subscribers = self.lock();
+        subscribers.retain(|subscriber| subscriber.alive.strong_count() > 0);
+        subscribers
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Subscriber>> {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
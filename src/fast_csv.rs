@@ -0,0 +1,268 @@
+use std::io::Read;
+
+use csv::ByteRecord;
+
+use crate::currency::Currency;
+use crate::parse::Amount;
+use crate::{ClientId, Transaction, TransactionId, TransactionType};
+
+// **Motivation**: `csv::Reader::deserialize` allocates a record per row and goes
+// through serde + `f64` parsing for every amount, which dominates the runtime on
+// 100M+ row files. This reader reuses a single `ByteRecord` and parses the fields by
+// hand, straight from the bytes.
+//
+// The fast parsers only accept the canonical spelling of every field (e.g., `deposit`,
+// `12.5`). Anything else, including every invalid row, falls back to the serde path
+// for that row, so results (and error messages) are always identical to it.
+pub struct FastCsvReader<R> {
+    reader: csv::Reader<R>,
+    headers: ByteRecord,
+    record: ByteRecord,
+    columns: Columns,
+}
+
+// Position of each `Transaction` field in the row
+#[derive(Clone, Copy, Debug, Default)]
+struct Columns {
+    action: Option<usize>,
+    client_id: Option<usize>,
+    id: Option<usize>,
+    amount: Option<usize>,
+    currency: Option<usize>,
+    to_currency: Option<usize>,
+}
+
+impl<R: Read> FastCsvReader<R> {
+    // `reader` must be configured the same way as for the serde path (trimmed, with
+    // headers) for the fallback to behave identically.
+    pub fn new(mut reader: csv::Reader<R>) -> csv::Result<Self> {
+        let headers = reader.byte_headers()?.clone();
+        let position = |name: &[u8]| headers.iter().position(|header| header == name);
+        let columns = Columns {
+            action: position(b"type"),
+            client_id: position(b"client"),
+            id: position(b"tx"),
+            amount: position(b"amount"),
+            currency: position(b"currency"),
+            to_currency: position(b"to_currency"),
+        };
+
+        Ok(Self {
+            reader,
+            headers,
+            record: ByteRecord::new(),
+            columns,
+        })
+    }
+
+    pub fn read_transaction(&mut self) -> Option<csv::Result<Transaction>> {
+        match self.reader.read_byte_record(&mut self.record) {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(err) => return Some(Err(err)),
+        }
+
+        Some(match self.parse_record() {
+            Some(transaction) => Ok(transaction),
+            None => self.record.deserialize(Some(&self.headers)),
+        })
+    }
+
+    fn parse_record(&self) -> Option<Transaction> {
+        let field = |column: Option<usize>| self.record.get(column?);
+        // Missing optional columns are `None`, just like serde's missing fields
+        let optional = |column: Option<usize>| match field(column) {
+            None | Some(b"") => Some(None),
+            Some(bytes) => Some(Some(bytes)),
+        };
+
+        let mut transaction = Transaction::new(
+            parse_id::<TransactionId>(field(self.columns.id)?)?,
+            parse_id::<ClientId>(field(self.columns.client_id)?)?,
+            parse_action(field(self.columns.action)?)?,
+            match optional(self.columns.amount)? {
+                Some(bytes) => Some(parse_amount(bytes)?),
+                None => None,
+            },
+        );
+        if let Some(bytes) = optional(self.columns.currency)? {
+            transaction.currency = Some(parse_currency(bytes)?);
+        }
+        if let Some(bytes) = optional(self.columns.to_currency)? {
+            transaction.to_currency = Some(parse_currency(bytes)?);
+        }
+        Some(transaction)
+    }
+}
+
+impl<R: Read> Iterator for FastCsvReader<R> {
+    type Item = csv::Result<Transaction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_transaction()
+    }
+}
+
+fn parse_action(bytes: &[u8]) -> Option<TransactionType> {
+    Some(match bytes {
+        b"deposit" => TransactionType::Deposit,
+        b"withdrawal" => TransactionType::Withdrawal,
+        b"dispute" => TransactionType::Dispute,
+        b"resolve" => TransactionType::Resolve,
+        b"chargeback" => TransactionType::Chargeback,
+        b"convert" => TransactionType::Convert,
+        _ => return None,
+    })
+}
+
+// Plain ASCII digits only; signs etc. are left to serde
+fn parse_id<T: TryFrom<u64>>(bytes: &[u8]) -> Option<T> {
+    if bytes.is_empty() || bytes.len() > 19 {
+        return None;
+    }
+
+    let mut value: u64 = 0;
+    for &byte in bytes {
+        if !byte.is_ascii_digit() {
+            return None;
+        }
+        value = value * 10 + u64::from(byte - b'0');
+    }
+    T::try_from(value).ok()
+}
+
+// Below this many units, parsing the decimal exactly and going through `f64` (the
+// serde path) agree to well within `Amount::new`'s tolerance, so the results are the
+// same. Larger amounts take the serde path to reproduce its `f64` rounding.
+const MAX_EXACT_UNITS: u64 = 1 << 36;
+
+// `[-]digits[.digits]` with at most `Amount::MAX_DIGITS_AFTER_DECIMAL` decimals
+fn parse_amount(bytes: &[u8]) -> Option<Amount> {
+    let (negative, bytes) = match bytes {
+        [b'-', rest @ ..] => (true, rest),
+        rest => (false, rest),
+    };
+    let (whole, fraction) = match bytes.iter().position(|&byte| byte == b'.') {
+        Some(dot) => (&bytes[..dot], &bytes[dot + 1..]),
+        None => (bytes, &[][..]),
+    };
+    let decimals = Amount::MAX_DIGITS_AFTER_DECIMAL as usize;
+    if whole.is_empty() || whole.len() > 11 || fraction.len() > decimals {
+        return None;
+    }
+
+    let mut units: u64 = 0;
+    for &byte in whole.iter().chain(fraction) {
+        if !byte.is_ascii_digit() {
+            return None;
+        }
+        units = units * 10 + u64::from(byte - b'0');
+    }
+    units *= 10u64.pow((decimals - fraction.len()) as u32);
+    if units >= MAX_EXACT_UNITS {
+        return None;
+    }
+
+    let units = units as i64;
+    Some(Amount::from(if negative { -units } else { units }))
+}
+
+fn parse_currency(bytes: &[u8]) -> Option<Currency> {
+    Currency::new(std::str::from_utf8(bytes).ok()?).ok()
+}
+
+#[cfg(test)]
+mod fast_csv_tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    fn reader(input: &str) -> csv::Reader<&[u8]> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes())
+    }
+
+    // Errors are compared by message since `csv::Error` isn't `PartialEq`
+    fn fast(input: &str) -> Vec<std::result::Result<Transaction, String>> {
+        FastCsvReader::new(reader(input))
+            .unwrap()
+            .map(|row| row.map_err(|err| err.to_string()))
+            .collect()
+    }
+
+    fn serde(input: &str) -> Vec<std::result::Result<Transaction, String>> {
+        reader(input)
+            .deserialize()
+            .map(|row| row.map_err(|err: csv::Error| err.to_string()))
+            .collect()
+    }
+
+    #[gtest]
+    pub fn matches_serde_path_on_canonical_rows() {
+        let input = "type, client, tx, amount
+deposit, 1, 1, 1.0
+withdrawal, 2, 2, 0.1234
+dispute, 1, 1,
+resolve, 1, 1
+chargeback, 65535, 4294967295,
+deposit, 1, 3, 5
+deposit, 1, 4, -2.5
+";
+        let rows = fast(input);
+        expect_that!(rows, eq(&serde(input)));
+        expect_that!(
+            rows[1],
+            ok(eq(&Transaction::new(
+                2,
+                2,
+                TransactionType::Withdrawal,
+                Amount::new(0.1234).ok()
+            )))
+        );
+    }
+
+    #[gtest]
+    pub fn matches_serde_path_on_unusual_and_invalid_rows() {
+        let input = "type,client,tx,amount,currency,to_currency
+deposit,1,1,1.23456,,
+deposit,1,2,1.00000,,
+deposit,1,3,.5,,
+deposit,1,4,+1.5,,
+deposit,1,5,1e2,,
+deposit,1,6,922337203685477.5807,,
+deposit,1,7,99999999999999999999,,
+Deposit,1,8,1.0,,
+deposit,65536,9,1.0,,
+deposit,-1,10,1.0,,
+deposit,1,11,abc,,
+convert,1,12,1.0,USD,EUR
+convert,1,13,1.0,usd,EUR
+deposit,1
+";
+        expect_that!(fast(input), eq(&serde(input)));
+    }
+
+    #[gtest]
+    pub fn falls_back_when_required_columns_are_missing() {
+        let input = "type,client,amount\ndeposit,1,1.0\n";
+        let rows = fast(input);
+        expect_that!(rows, eq(&serde(input)));
+        expect_that!(rows, elements_are![err(anything())]);
+    }
+
+    #[gtest]
+    pub fn amounts_match_f64_path_up_to_exact_limit() {
+        // Walks the whole fast range, including the values right below the limit
+        let units = (0..MAX_EXACT_UNITS)
+            .step_by(999_983)
+            .chain(MAX_EXACT_UNITS - 1000..MAX_EXACT_UNITS);
+        for units in units {
+            let text = Amount::from(units as i64).to_string();
+            let f64_path = Amount::new(text.parse::<f64>().unwrap()).ok();
+            expect_that!(parse_amount(text.as_bytes()), eq(f64_path), "{text}");
+        }
+        expect_that!(parse_amount(b"6871947.6736"), none());
+    }
+}
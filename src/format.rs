@@ -0,0 +1,714 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Write};
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::ach::{AchFile, TraceIds};
+use crate::fast_csv::FastCsvReader;
+use crate::mapping::ColumnMapping;
+use crate::parse::binary::BinaryReader;
+use crate::{Transaction, TransactionType};
+
+pub type Transactions = Box<dyn Iterator<Item = anyhow::Result<Transaction>>>;
+
+// Both formats deserialize into the same `Transaction`, so amounts go through the exact
+// same `Amount` validation no matter where they came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    Csv,
+    // One JSON object per line (NDJSON)
+    JsonLines,
+    // Fixed-width records, see `parse::binary`
+    Binary,
+    // NACHA ACH batch files, see `ach`
+    Ach,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    JsonLines,
+    // A single JSON array, e.g., for tools that can't stream NDJSON
+    JsonArray,
+}
+
+impl InputFormat {
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        match extension(path.as_ref())?.as_str() {
+            "csv" => Some(InputFormat::Csv),
+            "jsonl" | "ndjson" => Some(InputFormat::JsonLines),
+            "petx" => Some(InputFormat::Binary),
+            "ach" => Some(InputFormat::Ach),
+            _ => None,
+        }
+    }
+
+    // Transactions in file order. Each item fails on its own so callers can decide
+    // whether a malformed row is fatal.
+    pub fn transactions<R: Read + 'static>(self, reader: R) -> Transactions {
+        self.mapped_transactions(reader, &ColumnMapping::default())
+    }
+
+    // Same as `transactions`, but with the partner's columns and values mapped onto ours
+    pub fn mapped_transactions<R: Read + 'static>(
+        self,
+        reader: R,
+        mapping: &ColumnMapping,
+    ) -> Transactions {
+        match (self, mapping.is_identity()) {
+            (InputFormat::Csv, true) => match FastCsvReader::new(csv_reader(reader, true)) {
+                Ok(reader) => Box::new(reader.map(|row| Ok(row?))),
+                Err(err) => Box::new(std::iter::once(Err(err.into()))),
+            },
+            (InputFormat::Csv, false) => {
+                let mut reader = csv_reader(reader, !mapping.is_headerless());
+                let headers = if mapping.is_headerless() {
+                    None
+                } else {
+                    match reader.headers() {
+                        Ok(headers) => Some(headers.clone()),
+                        Err(err) => return Box::new(std::iter::once(Err(err.into()))),
+                    }
+                };
+                let positions = match mapping.csv_positions(headers.as_ref()) {
+                    Ok(positions) => positions,
+                    Err(err) => return Box::new(std::iter::once(Err(err))),
+                };
+
+                let mapping = mapping.clone();
+                Box::new(
+                    reader
+                        .into_records()
+                        .map(move |record| Ok(mapping.csv_transaction(&positions, &record?)?)),
+                )
+            }
+            (InputFormat::JsonLines, true) => Box::new(
+                serde_json::Deserializer::from_reader(reader)
+                    .into_iter()
+                    .map(|row| Ok(row?)),
+            ),
+            (InputFormat::JsonLines, false) => {
+                let mapping = mapping.clone();
+                Box::new(
+                    serde_json::Deserializer::from_reader(reader)
+                        .into_iter()
+                        .map(move |row| mapping.json_transaction(row?)),
+                )
+            }
+            // Records have no columns to map
+            (InputFormat::Binary, _) => match BinaryReader::new(reader) {
+                Ok(reader) => Box::new(reader.map(|row| Ok(row?))),
+                Err(err) => Box::new(std::iter::once(Err(err.into()))),
+            },
+            (InputFormat::Ach, _) => ach_transactions(reader, &TraceIds::default()),
+        }
+    }
+}
+
+// ACH entries in file order, with transaction ids from `ids`, e.g., shared by every ACH
+// input of a run. The control totals are at the end, so the whole file is validated
+// before any of its transactions are handed out.
+pub fn ach_transactions<R: Read>(reader: R, ids: &TraceIds) -> Transactions {
+    match AchFile::parse(BufReader::new(reader)).and_then(|file| file.transactions(ids)) {
+        Ok(transactions) => Box::new(transactions.into_iter().map(Ok)),
+        Err(err) => Box::new(std::iter::once(Err(err.into()))),
+    }
+}
+
+pub(crate) fn csv_reader<R: Read>(reader: R, has_headers: bool) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .has_headers(has_headers)
+        .from_reader(reader)
+}
+
+impl OutputFormat {
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        match extension(path.as_ref())?.as_str() {
+            "csv" => Some(OutputFormat::Csv),
+            "jsonl" | "ndjson" => Some(OutputFormat::JsonLines),
+            "json" => Some(OutputFormat::JsonArray),
+            _ => None,
+        }
+    }
+}
+
+impl From<InputFormat> for OutputFormat {
+    // Output mirrors the input when nothing else is specified
+    fn from(format: InputFormat) -> Self {
+        match format {
+            InputFormat::Csv | InputFormat::Binary | InputFormat::Ach => OutputFormat::Csv,
+            InputFormat::JsonLines => OutputFormat::JsonLines,
+        }
+    }
+}
+
+// Extension of the data itself, so `tx.csv.gz` is still a CSV
+fn extension(path: &Path) -> Option<String> {
+    let last = path.extension()?.to_str()?.to_ascii_lowercase();
+    if Compression::from_extension(&last).is_some() {
+        return extension(Path::new(path.file_stem()?));
+    }
+    Some(last)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+    const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+    pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(Self::GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(Self::ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "gz" | "gzip" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    // Wraps `reader` in a streaming decoder if it starts with a gzip or zstd header and
+    // passes it through untouched otherwise. Only the magic bytes are read up front;
+    // nothing else is buffered beyond what the decoders need.
+    //
+    // Magic bytes win over the file name, so a mislabeled `.gz` that's actually plain
+    // text still works.
+    pub fn decompress<R: Read + 'static>(mut reader: R) -> std::io::Result<Box<dyn Read>> {
+        let mut magic = [0u8; 4];
+        let mut len = 0;
+        while len < magic.len() {
+            match reader.read(&mut magic[len..])? {
+                0 => break,
+                read => len += read,
+            }
+        }
+
+        let reader = Cursor::new(magic).take(len as u64).chain(reader);
+        Ok(match Self::from_magic(&magic[..len]) {
+            Some(Compression::Gzip) => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+            Some(Compression::Zstd) => Box::new(zstd::Decoder::new(reader)?),
+            None => Box::new(reader),
+        })
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "csv" => Ok(InputFormat::Csv),
+            "jsonl" | "ndjson" => Ok(InputFormat::JsonLines),
+            "binary" | "petx" => Ok(InputFormat::Binary),
+            "ach" | "nacha" => Ok(InputFormat::Ach),
+            _ => anyhow::bail!("Unknown input format `{s}`, expected csv, jsonl, binary or ach"),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "csv" => Ok(OutputFormat::Csv),
+            "jsonl" | "ndjson" => Ok(OutputFormat::JsonLines),
+            "json" => Ok(OutputFormat::JsonArray),
+            _ => anyhow::bail!("Unknown output format `{s}`, expected csv, jsonl or json"),
+        }
+    }
+}
+
+impl Display for InputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            InputFormat::Csv => "csv",
+            InputFormat::JsonLines => "jsonl",
+            InputFormat::Binary => "binary",
+            InputFormat::Ach => "ach",
+        })
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::JsonLines => "jsonl",
+            OutputFormat::JsonArray => "json",
+        })
+    }
+}
+
+// Where transactions are read from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InputSource {
+    Stdin,
+    Path(PathBuf),
+}
+
+impl InputSource {
+    // `-` is stdin and anything with glob metacharacters is expanded. Matches come back
+    // sorted, so date-named partitions (e.g., `tx-2024-01-*.csv`) run in date order.
+    pub fn expand(arg: &str) -> anyhow::Result<Vec<Self>> {
+        if arg == "-" {
+            return Ok(vec![InputSource::Stdin]);
+        }
+        if !arg.contains(['*', '?', '[']) {
+            return Ok(vec![InputSource::Path(arg.into())]);
+        }
+
+        let sources = glob::glob(arg)?
+            .map(|path| Ok(InputSource::Path(path?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if sources.is_empty() {
+            anyhow::bail!("No files match `{arg}`");
+        }
+        Ok(sources)
+    }
+
+    // `None` if it can't be told from the extension (e.g., stdin)
+    pub fn format(&self) -> Option<InputFormat> {
+        match self {
+            InputSource::Stdin => None,
+            InputSource::Path(path) => InputFormat::from_path(path),
+        }
+    }
+
+    // gzip and zstd input is decompressed on the fly
+    pub fn open(&self) -> std::io::Result<Box<dyn Read>> {
+        match self {
+            InputSource::Stdin => Compression::decompress(std::io::stdin().lock()),
+            InputSource::Path(path) => Compression::decompress(BufReader::new(File::open(path)?)),
+        }
+    }
+}
+
+impl Display for InputSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputSource::Stdin => f.write_str("stdin"),
+            InputSource::Path(path) => f.write_fmt(format_args!("{}", path.display())),
+        }
+    }
+}
+
+// K-way merge of inputs that are each in logical order (e.g., the same day exported by
+// several upstream shards), with new transactions ordered by id. Disputes, resolves and
+// chargebacks carry the id of the transaction they reference rather than their own
+// position, so they're ordered after both the rows before them in their input and the
+// transaction they reference, wherever that came from. Only the next row of every input
+// is compared, so rows within one input never get reordered. Ties go to new
+// transactions, then to the earlier input, and errors are yielded as soon as they're
+// read.
+pub fn merge_by_transaction_id(inputs: Vec<Transactions>) -> Transactions {
+    Box::new(MergeByTransactionId {
+        inputs: inputs
+            .into_iter()
+            .map(|input| (input.peekable(), 0))
+            .collect(),
+    })
+}
+
+struct MergeByTransactionId {
+    // Each input with the merge key of the last row taken from it
+    inputs: Vec<(Peekable<Transactions>, u32)>,
+}
+
+impl MergeByTransactionId {
+    // Position of `transaction` in the merged order, coming after `last`
+    fn key(transaction: &Transaction, last: u32) -> (u32, bool) {
+        let is_follow_up = matches!(
+            transaction.action,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+        );
+        (transaction.id.max(last), is_follow_up)
+    }
+}
+
+impl Iterator for MergeByTransactionId {
+    type Item = anyhow::Result<Transaction>;
+
+    // TODO (PERF): Linear scan is fine for a handful of files. Use a heap if we ever
+    // merge hundreds of inputs.
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut next: Option<(usize, (u32, bool))> = None;
+        for (index, (input, last)) in self.inputs.iter_mut().enumerate() {
+            match input.peek() {
+                Some(Err(_)) => return input.next(),
+                Some(Ok(transaction)) => {
+                    let key = Self::key(transaction, *last);
+                    if next.is_none_or(|(_, next_key)| key < next_key) {
+                        next = Some((index, key));
+                    }
+                }
+                None => {}
+            }
+        }
+
+        let (index, (id, _)) = next?;
+        let (input, last) = &mut self.inputs[index];
+        *last = id;
+        input.next()
+    }
+}
+
+// Writes records (e.g., `ClientSnapshot`s) one at a time in the chosen format.
+// `finish` must be called to close the JSON array and flush buffered output.
+pub enum RecordWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    JsonLines(W),
+    JsonArray { writer: W, is_empty: bool },
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(writer: W, format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Csv => RecordWriter::Csv(Box::new(csv::Writer::from_writer(writer))),
+            OutputFormat::JsonLines => RecordWriter::JsonLines(writer),
+            OutputFormat::JsonArray => RecordWriter::JsonArray {
+                writer,
+                is_empty: true,
+            },
+        }
+    }
+
+    pub fn write<T: Serialize>(&mut self, record: &T) -> anyhow::Result<()> {
+        match self {
+            RecordWriter::Csv(writer) => writer.serialize(record)?,
+            RecordWriter::JsonLines(writer) => {
+                serde_json::to_writer(&mut *writer, record)?;
+                writer.write_all(b"\n")?;
+            }
+            RecordWriter::JsonArray { writer, is_empty } => {
+                writer.write_all(if *is_empty { b"[\n" } else { b",\n" })?;
+                serde_json::to_writer(&mut *writer, record)?;
+                *is_empty = false;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> anyhow::Result<W> {
+        let mut writer = match self {
+            RecordWriter::Csv(writer) => writer.into_inner().map_err(|err| err.into_error())?,
+            RecordWriter::JsonLines(writer) => writer,
+            RecordWriter::JsonArray {
+                mut writer,
+                is_empty,
+            } => {
+                writer.write_all(if is_empty { b"[]\n" } else { b"\n]\n" })?;
+                writer
+            }
+        };
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+#[cfg(test)]
+mod format_tests {
+    use googletest::prelude::*;
+
+    use super::*;
+    use crate::TransactionType;
+    use crate::parse::Amount;
+
+    fn read(format: InputFormat, input: &'static str) -> Vec<anyhow::Result<Transaction>> {
+        format.transactions(input.as_bytes()).collect()
+    }
+
+    fn ids(transactions: Transactions) -> Vec<(u32, TransactionType)> {
+        transactions
+            .map(|transaction| {
+                let transaction = transaction.unwrap();
+                (transaction.id, transaction.action)
+            })
+            .collect()
+    }
+
+    fn write(format: OutputFormat, records: &[Transaction]) -> String {
+        let mut writer = RecordWriter::new(Vec::new(), format);
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    }
+
+    #[gtest]
+    pub fn json_lines_and_csv_read_the_same_transactions() {
+        let csv = read(
+            InputFormat::Csv,
+            "type,client,tx,amount\ndeposit,1,1,1.5\ndispute,1,1,\n",
+        );
+        let json = read(
+            InputFormat::JsonLines,
+            r#"{"type":"deposit","client":1,"tx":1,"amount":1.5}
+{"type":"dispute","client":1,"tx":1}
+"#,
+        );
+
+        let expected = [
+            Transaction::new(1, 1, TransactionType::Deposit, Amount::new(1.5).ok()),
+            Transaction::new(1, 1, TransactionType::Dispute, None),
+        ];
+        let csv = csv.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        let json = json.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        expect_that!(csv, eq(&expected));
+        expect_that!(json, eq(&expected));
+    }
+
+    #[gtest]
+    pub fn json_lines_reject_amounts_with_too_many_digits() {
+        let json = read(
+            InputFormat::JsonLines,
+            r#"{"type":"deposit","client":1,"tx":1,"amount":1.23456}"#,
+        );
+        expect_that!(json, elements_are![err(anything())]);
+    }
+
+    #[gtest]
+    pub fn writes_json_lines_and_arrays() {
+        let records = [
+            Transaction::new(1, 1, TransactionType::Deposit, Amount::new(1.5).ok()),
+            Transaction::new(2, 1, TransactionType::Resolve, None),
+        ];
+
+        expect_that!(
+            write(OutputFormat::JsonLines, &records),
+            eq(r#"{"tx":1,"client":1,"type":"deposit","amount":1.5}
+{"tx":2,"client":1,"type":"resolve","amount":null}
+"#)
+        );
+        expect_that!(
+            write(OutputFormat::JsonArray, &records),
+            eq(r#"[
+{"tx":1,"client":1,"type":"deposit","amount":1.5},
+{"tx":2,"client":1,"type":"resolve","amount":null}
+]
+"#)
+        );
+        expect_that!(write(OutputFormat::JsonArray, &[]), eq("[]\n"));
+    }
+
+    #[gtest]
+    pub fn writes_extended_snapshots_with_optional_columns_omitted() {
+        use crate::{ClientActivity, ClientSnapshot, ExtendedClientSnapshot};
+
+        let snapshot = ExtendedClientSnapshot {
+            snapshot: ClientSnapshot {
+                client: 1,
+                currency: None,
+                available: Amount::new(1.5).unwrap(),
+                held: Amount::ZERO,
+                total: Amount::new(1.5).unwrap(),
+                locked: false,
+                fees: None,
+            },
+            activity: ClientActivity {
+                deposits: 2,
+                withdrawals: 1,
+                open_disputes: 0,
+                chargebacks: 0,
+                rejected: 3,
+                last_tx: Some(9),
+            },
+        };
+        let write = |format| {
+            let mut writer = RecordWriter::new(Vec::new(), format);
+            writer.write(&snapshot).unwrap();
+            String::from_utf8(writer.finish().unwrap()).unwrap()
+        };
+
+        expect_that!(
+            write(OutputFormat::Csv),
+            eq("client,available,held,total,locked,deposits,withdrawals,open_disputes,chargebacks,rejected,last_tx
+1,1.5,0.0,1.5,false,2,1,0,0,3,9
+")
+        );
+        expect_that!(
+            write(OutputFormat::JsonLines),
+            eq(
+                r#"{"client":1,"available":1.5,"held":0.0,"total":1.5,"locked":false,"deposits":2,"withdrawals":1,"open_disputes":0,"chargebacks":0,"rejected":3,"last_tx":9}
+"#
+            )
+        );
+    }
+
+    #[gtest]
+    pub fn binary_reads_the_same_transactions_as_csv() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.5\ndispute,1,1,\n";
+        let mut writer = crate::parse::binary::BinaryWriter::new(Vec::new()).unwrap();
+        for transaction in InputFormat::Csv.transactions(csv.as_bytes()) {
+            writer.write(&transaction.unwrap()).unwrap();
+        }
+        let binary = writer.finish().unwrap();
+
+        let binary = InputFormat::Binary
+            .transactions(std::io::Cursor::new(binary))
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        let csv = read(InputFormat::Csv, csv)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        expect_that!(binary, eq(&csv));
+        expect_that!(
+            read(InputFormat::Binary, "type,client,tx,amount\n"),
+            elements_are![err(anything())]
+        );
+    }
+
+    #[gtest]
+    pub fn detects_format_from_extension() {
+        expect_that!(
+            InputFormat::from_path("tx.NDJSON"),
+            some(eq(InputFormat::JsonLines))
+        );
+        expect_that!(InputFormat::from_path("tx.csv"), some(eq(InputFormat::Csv)));
+        expect_that!(InputFormat::from_path("tx"), none());
+        expect_that!(
+            OutputFormat::from_path("out.json"),
+            some(eq(OutputFormat::JsonArray))
+        );
+    }
+
+    #[gtest]
+    pub fn merge_interleaves_inputs_by_transaction_id() {
+        let first = InputFormat::Csv.transactions(
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,4,1.0\ndispute,1,1,\n".as_bytes(),
+        );
+        let second = InputFormat::Csv
+            .transactions("type,client,tx,amount\ndeposit,2,2,1.0\ndeposit,2,3,1.0\n".as_bytes());
+
+        expect_that!(
+            ids(merge_by_transaction_id(vec![first, second])),
+            elements_are![
+                eq(&(1, TransactionType::Deposit)),
+                eq(&(2, TransactionType::Deposit)),
+                eq(&(3, TransactionType::Deposit)),
+                eq(&(4, TransactionType::Deposit)),
+                // Stays after tx 4, since rows within an input are never reordered
+                eq(&(1, TransactionType::Dispute)),
+            ]
+        );
+    }
+
+    #[gtest]
+    pub fn merge_orders_follow_ups_by_position_not_referenced_id() {
+        let first = InputFormat::Csv.transactions(
+            "type,client,tx,amount\ndeposit,1,3,1.0\ndeposit,1,4,1.0\ndeposit,1,7,1.0\ndeposit,1,9,1.0\n"
+                .as_bytes(),
+        );
+        let second = InputFormat::Csv.transactions(
+            "type,client,tx,amount\ndeposit,2,2,1.0\ndeposit,2,5,1.0\ndispute,1,3,\nchargeback,1,3,\ndispute,1,7,\ndeposit,2,8,1.0\n"
+                .as_bytes(),
+        );
+
+        expect_that!(
+            ids(merge_by_transaction_id(vec![first, second])),
+            elements_are![
+                eq(&(2, TransactionType::Deposit)),
+                eq(&(3, TransactionType::Deposit)),
+                eq(&(4, TransactionType::Deposit)),
+                eq(&(5, TransactionType::Deposit)),
+                // After tx 5, the row before them, not right after tx 3
+                eq(&(3, TransactionType::Dispute)),
+                eq(&(3, TransactionType::Chargeback)),
+                // Waits for the deposit it references from the other input
+                eq(&(7, TransactionType::Deposit)),
+                eq(&(7, TransactionType::Dispute)),
+                eq(&(8, TransactionType::Deposit)),
+                eq(&(9, TransactionType::Deposit)),
+            ]
+        );
+    }
+
+    #[gtest]
+    pub fn expand_globs_in_sorted_order() {
+        let dir = std::env::temp_dir().join(format!("payment-engine-glob-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["tx-02.csv", "tx-01.csv", "other.jsonl"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let sources = InputSource::expand(&format!("{}/tx-*.csv", dir.display())).unwrap();
+        let missing = InputSource::expand(&format!("{}/none-*.csv", dir.display()));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        expect_that!(
+            sources,
+            elements_are![
+                eq(&InputSource::Path(dir.join("tx-01.csv"))),
+                eq(&InputSource::Path(dir.join("tx-02.csv"))),
+            ]
+        );
+        expect_that!(missing, err(anything()));
+        expect_that!(
+            InputSource::expand("-"),
+            ok(elements_are![eq(&InputSource::Stdin)])
+        );
+    }
+
+    #[gtest]
+    pub fn decompresses_gzip_and_zstd_by_magic_bytes() {
+        use std::io::Write;
+
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.5\n";
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gzip.write_all(csv.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::encode_all(csv.as_bytes(), 0).unwrap();
+
+        let expected = Transaction::new(1, 1, TransactionType::Deposit, Amount::new(1.5).ok());
+        for input in [gzip, zstd, csv.as_bytes().to_vec()] {
+            let reader = Compression::decompress(Cursor::new(input)).unwrap();
+            let transactions = InputFormat::Csv
+                .transactions(reader)
+                .map(Result::unwrap)
+                .collect::<Vec<_>>();
+            expect_that!(transactions, elements_are![eq(&expected)]);
+        }
+    }
+
+    #[gtest]
+    pub fn passes_through_input_shorter_than_magic() {
+        let mut output = String::new();
+        Compression::decompress(Cursor::new(b"a"))
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        expect_that!(output, eq("a"));
+    }
+
+    #[gtest]
+    pub fn detects_format_through_compression_extension() {
+        expect_that!(
+            InputFormat::from_path("tx-2024-01-01.csv.gz"),
+            some(eq(InputFormat::Csv))
+        );
+        expect_that!(
+            InputFormat::from_path("tx.jsonl.zst"),
+            some(eq(InputFormat::JsonLines))
+        );
+        expect_that!(InputFormat::from_path("tx.gz"), none());
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::currency::Currency;
+use crate::parse::Rate;
+
+// Calendar date as `YYYYMMDD` so dates order correctly as plain integers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FxDate(u32);
+
+impl FxDate {
+    pub fn new(year: u32, month: u32, day: u32) -> Option<Self> {
+        (year <= 9999 && is_calendar_date(year, month, day))
+            .then_some(FxDate(year * 10_000 + month * 100 + day))
+    }
+}
+
+// Whether the year, month and day name a day that exists, e.g., not 2023-02-29
+pub(crate) fn is_calendar_date(year: u32, month: u32, day: u32) -> bool {
+    let is_leap_year =
+        year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+    let days = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year => 29,
+        2 => 28,
+        _ => return false,
+    };
+    (1..=days).contains(&day)
+}
+
+impl Display for FxDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (year, month, day) = (self.0 / 10_000, self.0 / 100 % 100, self.0 % 100);
+        f.write_fmt(format_args!("{year:04}-{month:02}-{day:02}"))
+    }
+}
+
+impl FromStr for FxDate {
+    type Err = anyhow::Error;
+
+    // ISO 8601 calendar date, e.g., 2024-03-31
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(3, '-').map(str::parse::<u32>);
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) => FxDate::new(year, month, day),
+            _ => None,
+        }
+        .ok_or_else(|| anyhow::anyhow!("Invalid date `{s}`, expected YYYY-MM-DD"))
+    }
+}
+
+impl<'de> Deserialize<'de> for FxDate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let date = String::deserialize(deserializer)?;
+        date.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// A row in the FX rate file: 1 unit of `from` buys `rate` units of `to`.
+// Transactions don't carry dates, so the date only picks the most recent rate of a
+// pair. Rows without one are used if the pair has no dated rate.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct FxRate {
+    pub from: Currency,
+    pub to: Currency,
+    pub rate: Rate,
+    #[serde(default)]
+    pub date: Option<FxDate>,
+}
+
+// Local table of FX rates, loaded once up front. Rates are directional: a
+// USD => EUR rate doesn't imply the inverse EUR => USD one.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FxRateTable {
+    // `None` sorts first, so undated rates are only used without dated ones
+    rates: BTreeMap<(Currency, Currency), BTreeMap<Option<FxDate>, Rate>>,
+}
+
+impl FxRateTable {
+    pub fn from_path(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_path(path)?;
+        Self::from_reader(reader)
+    }
+
+    pub fn from_reader<R: std::io::Read>(mut reader: csv::Reader<R>) -> anyhow::Result<Self> {
+        let mut table = Self::default();
+        for (index, row) in reader.deserialize::<FxRate>().enumerate() {
+            let rate = row?;
+            // A zero rate would convert to nothing, and a negative one would create money
+            if rate.rate <= Rate::ZERO {
+                anyhow::bail!(
+                    "Row {}: the {} => {} rate must be positive",
+                    index + 1,
+                    rate.from,
+                    rate.to
+                );
+            }
+            table.insert(rate);
+        }
+        Ok(table)
+    }
+
+    pub fn insert(&mut self, rate: FxRate) {
+        self.rates
+            .entry((rate.from, rate.to))
+            .or_default()
+            .insert(rate.date, rate.rate);
+    }
+
+    // Most recent rate for the pair
+    pub fn rate(&self, from: Currency, to: Currency) -> Option<Rate> {
+        let (_, &rate) = self.rates.get(&(from, to))?.last_key_value()?;
+        Some(rate)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod fx_rate_tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    fn table() -> FxRateTable {
+        let csv = "from,to,rate,date
+USD,EUR,0.9,
+USD,EUR,0.91,2024-01-01
+USD,EUR,0.92,2024-02-01
+EUR,USD,1.1,
+";
+        FxRateTable::from_reader(csv::Reader::from_reader(csv.as_bytes())).unwrap()
+    }
+
+    #[gtest]
+    pub fn rate_uses_most_recent_rate() {
+        let (usd, eur) = (Currency::new("USD").unwrap(), Currency::new("EUR").unwrap());
+        let table = table();
+
+        expect_that!(table.rate(usd, eur), some(eq(Rate::new(0.92).unwrap())));
+        // Undated rates are only used without dated ones
+        let csv = "from,to,rate,date\nUSD,EUR,0.91,2024-01-01\nUSD,EUR,0.9,\n";
+        let table = FxRateTable::from_reader(csv::Reader::from_reader(csv.as_bytes())).unwrap();
+        expect_that!(table.rate(usd, eur), some(eq(Rate::new(0.91).unwrap())));
+    }
+
+    #[gtest]
+    pub fn rejects_rates_that_are_not_positive() {
+        for rate in ["0", "-0.9"] {
+            let csv = format!("from,to,rate\nEUR,USD,1.1\nUSD,EUR,{rate}\n");
+            expect_that!(
+                FxRateTable::from_reader(csv::Reader::from_reader(csv.as_bytes()))
+                    .map_err(|err| err.to_string()),
+                err(eq("Row 2: the USD => EUR rate must be positive"))
+            );
+        }
+    }
+
+    #[gtest]
+    pub fn rates_are_directional() {
+        let (usd, eur, gbp) = (
+            Currency::new("USD").unwrap(),
+            Currency::new("EUR").unwrap(),
+            Currency::new("GBP").unwrap(),
+        );
+        let table = table();
+
+        expect_that!(table.rate(eur, usd), some(eq(Rate::new(1.1).unwrap())));
+        expect_that!(table.rate(usd, gbp), none());
+    }
+
+    #[gtest]
+    pub fn date_rejects_invalid_values() {
+        expect_that!("2024-13-01".parse::<FxDate>(), err(anything()));
+        expect_that!("2024-02-31".parse::<FxDate>(), err(anything()));
+        expect_that!("2026-04-31".parse::<FxDate>(), err(anything()));
+        expect_that!("2023-02-29".parse::<FxDate>(), err(anything()));
+        expect_that!("2024-02-29".parse::<FxDate>(), ok(anything()));
+        expect_that!("2024/01/01".parse::<FxDate>(), err(anything()));
+        expect_that!(
+            "2024-01-05".parse::<FxDate>().map(|d| d.to_string()),
+            ok(eq("2024-01-05"))
+        );
+    }
+}
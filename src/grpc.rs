@@ -0,0 +1,275 @@
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::engine::subscriptions::{BalanceChange, Subscription};
+use crate::engine::{PaymentEngine, TransactionProcessError};
+use crate::parse::Amount;
+use crate::{ClientId, ClientSnapshot, Transaction, TransactionType};
+
+// Generated from `proto/payment_engine.proto`
+pub mod proto {
+    tonic::include_proto!("payment_engine.v1");
+}
+
+use proto::payment_engine_server::{self, PaymentEngineServer};
+use proto::transaction_process_error::Kind;
+
+// Balance changes buffered per watcher on top of the engine's subscription channel
+const WATCH_CAPACITY: usize = 64;
+
+// How often a watcher's thread checks that the watcher is still there while its
+// client's balances don't change
+const WATCH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+// Serves any engine over gRPC (see `proto/payment_engine.proto`). Like `EngineServer`,
+// the engine sits behind a lock, so concurrent `Ingest` streams are applied one
+// transaction at a time. Each one waits for its outcome, so the stream engine's workers
+// don't get ahead of the RPC.
+pub struct GrpcService<E> {
+    engine: Mutex<E>,
+}
+
+impl<E: PaymentEngine> GrpcService<E> {
+    pub fn new(engine: E) -> Self {
+        Self {
+            engine: Mutex::new(engine),
+        }
+    }
+
+    pub fn into_server(self) -> PaymentEngineServer<Self> {
+        PaymentEngineServer::new(self)
+    }
+
+    fn engine(&self) -> MutexGuard<'_, E> {
+        self.engine.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[tonic::async_trait]
+impl<E> payment_engine_server::PaymentEngine for GrpcService<E>
+where
+    E: PaymentEngine + Send + 'static,
+    E::SnapshotError: Display,
+{
+    type WatchClientStream =
+        Pin<Box<dyn Stream<Item = Result<proto::BalanceChange, Status>> + Send>>;
+
+    async fn ingest(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> Result<Response<proto::IngestSummary>, Status> {
+        let mut transactions = request.into_inner();
+        let mut summary = proto::IngestSummary::default();
+        while let Some(transaction) = transactions.message().await? {
+            let result = self.engine().process_with_outcome(transaction.try_into()?);
+            match result {
+                Ok(_) => summary.applied += 1,
+                Err(err) => summary.rejected.push(err.try_into()?),
+            }
+        }
+        Ok(Response::new(summary))
+    }
+
+    async fn get_client(
+        &self,
+        request: Request<proto::GetClientRequest>,
+    ) -> Result<Response<proto::GetClientResponse>, Status> {
+        let client = client_id(request.into_inner().client)?;
+        let snapshots = self
+            .engine()
+            .client_snapshots(client)
+            .map_err(|err| Status::internal(err.to_string()))?;
+        let Some(snapshots) = snapshots else {
+            return Err(Status::not_found(format!("client {client} not found")));
+        };
+        Ok(Response::new(proto::GetClientResponse {
+            snapshots: snapshots.iter().map(|s| (&s.snapshot).into()).collect(),
+        }))
+    }
+
+    async fn watch_client(
+        &self,
+        request: Request<proto::WatchClientRequest>,
+    ) -> Result<Response<Self::WatchClientStream>, Status> {
+        let client = client_id(request.into_inner().client)?;
+
+        // Subscribing under the lock so no change falls between the current balances and
+        // the first change
+        let (current, changes) = {
+            let mut engine = self.engine();
+            let changes = engine.subscribe(Subscription::Client(client));
+            let current = engine
+                .client_snapshots(client)
+                .map_err(|err| Status::internal(err.to_string()))?;
+            (current.unwrap_or_default(), changes)
+        };
+
+        // Like the WebSocket feed, each watcher gets a thread forwarding the engine's
+        // changes. It ends, dropping the subscription, once tonic drops the stream
+        // (e.g., the watcher went away): right away if a change can't be sent, and
+        // within `WATCH_CHECK_INTERVAL` on a quiet client.
+        let (sender, receiver) = mpsc::channel(WATCH_CAPACITY);
+        std::thread::spawn(move || {
+            loop {
+                match changes.recv_timeout(WATCH_CHECK_INTERVAL) {
+                    Ok(change) => {
+                        if sender.blocking_send(Ok((&change).into())).is_err() {
+                            break;
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) if sender.is_closed() => break,
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+
+        let current = current
+            .iter()
+            .map(|s| {
+                Ok(proto::BalanceChange {
+                    snapshot: Some((&s.snapshot).into()),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        Ok(Response::new(Box::pin(
+            tokio_stream::iter(current).chain(ReceiverStream::new(receiver)),
+        )))
+    }
+}
+
+// Serves the engine on `address` until the process exits
+pub async fn serve<E>(address: SocketAddr, engine: E) -> anyhow::Result<()>
+where
+    E: PaymentEngine + Send + 'static,
+    E::SnapshotError: Display,
+{
+    log::info!("Serving the engine over gRPC on {address}");
+    tonic::transport::Server::builder()
+        .add_service(GrpcService::new(engine).into_server())
+        .serve(address)
+        .await?;
+    Ok(())
+}
+
+fn client_id(client: u32) -> Result<ClientId, Status> {
+    ClientId::try_from(client)
+        .map_err(|_| Status::invalid_argument(format!("{client} isn't a client id")))
+}
+
+impl TryFrom<proto::Transaction> for Transaction {
+    type Error = Status;
+
+    fn try_from(transaction: proto::Transaction) -> Result<Self, Self::Error> {
+        let tx = transaction.tx;
+        let invalid =
+            |err: &dyn Display| Status::invalid_argument(format!("transaction {tx}: {err}"));
+
+        let action = match proto::TransactionType::try_from(transaction.r#type) {
+            Ok(proto::TransactionType::Deposit) => TransactionType::Deposit,
+            Ok(proto::TransactionType::Withdrawal) => TransactionType::Withdrawal,
+            Ok(proto::TransactionType::Dispute) => TransactionType::Dispute,
+            Ok(proto::TransactionType::Resolve) => TransactionType::Resolve,
+            Ok(proto::TransactionType::Chargeback) => TransactionType::Chargeback,
+            Ok(proto::TransactionType::Convert) => TransactionType::Convert,
+            Ok(proto::TransactionType::Unspecified) | Err(_) => {
+                return Err(invalid(&"missing or unknown type"));
+            }
+        };
+        let amount = transaction
+            .amount
+            .map(|amount| amount.parse::<Amount>())
+            .transpose()
+            .map_err(|err| invalid(&err))?;
+        let currency = transaction
+            .currency
+            .map(|currency| currency.parse())
+            .transpose()
+            .map_err(|err| invalid(&err))?;
+        let to_currency = transaction
+            .to_currency
+            .map(|currency| currency.parse())
+            .transpose()
+            .map_err(|err| invalid(&err))?;
+
+        Ok(Transaction {
+            id: tx,
+            client_id: client_id(transaction.client)?,
+            action,
+            amount,
+            currency,
+            to_currency,
+        })
+    }
+}
+
+impl From<&ClientSnapshot> for proto::ClientSnapshot {
+    fn from(snapshot: &ClientSnapshot) -> Self {
+        Self {
+            client: snapshot.client.into(),
+            currency: snapshot.currency.map(|currency| currency.to_string()),
+            available: snapshot.available.to_string(),
+            held: snapshot.held.to_string(),
+            total: snapshot.total.to_string(),
+            locked: snapshot.locked,
+            fees: snapshot.fees.map(|fees| fees.to_string()),
+        }
+    }
+}
+
+impl From<&BalanceChange> for proto::BalanceChange {
+    fn from(change: &BalanceChange) -> Self {
+        Self {
+            tx: Some(change.tx),
+            available_delta: Some(change.available_delta.to_string()),
+            held_delta: Some(change.held_delta.to_string()),
+            snapshot: Some((&change.snapshot).into()),
+        }
+    }
+}
+
+// `Unknown` isn't the transaction's fault, so it fails the whole RPC instead
+impl TryFrom<TransactionProcessError> for proto::TransactionProcessError {
+    type Error = Status;
+
+    fn try_from(err: TransactionProcessError) -> Result<Self, Self::Error> {
+        use TransactionProcessError as E;
+
+        let (kind, client, tx) = match err {
+            E::ClientLocked(client, tx) => (Kind::ClientLocked, client, tx),
+            E::InsufficientFunds(client, tx) => (Kind::InsufficientFunds, client, tx),
+            E::InvalidDisputeNotFound(client, tx) => (Kind::InvalidDisputeNotFound, client, tx),
+            E::InvalidDisputeDuplicate(client, tx) => (Kind::InvalidDisputeDuplicate, client, tx),
+            E::InvalidResolveNotFound(client, tx) => (Kind::InvalidResolveNotFound, client, tx),
+            E::InvalidResolveNotDisputed(client, tx) => {
+                (Kind::InvalidResolveNotDisputed, client, tx)
+            }
+            E::InvalidChargeBackNotFound(client, tx) => {
+                (Kind::InvalidChargebackNotFound, client, tx)
+            }
+            E::InvalidChargeBackNotDisputed(client, tx) => {
+                (Kind::InvalidChargebackNotDisputed, client, tx)
+            }
+            E::UnsupportedCurrency(client, tx) => (Kind::UnsupportedCurrency, client, tx),
+            E::InvalidConversion(client, tx) => (Kind::InvalidConversion, client, tx),
+            E::FxRateNotFound(client, tx) => (Kind::FxRateNotFound, client, tx),
+            E::TooPrecise(client, tx) => (Kind::TooPrecise, client, tx),
+            E::Unknown => return Err(Status::internal(err.to_string())),
+        };
+        Ok(Self {
+            kind: kind.into(),
+            client: client.into(),
+            tx,
+        })
+    }
+}
@@ -1,10 +1,25 @@
+pub mod ach;
+pub mod camt;
+pub mod chunked;
+pub mod currency;
 pub mod engine;
+pub mod fast_csv;
+pub mod format;
+pub mod fx;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod mapping;
 pub mod parse;
+pub mod queue;
+#[cfg(not(feature = "stream"))]
+pub mod server;
 
 use std::collections::{BTreeMap, BTreeSet};
 
+use currency::Currency;
 use parse::Amount;
-use serde::{Deserialize, Serialize};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
 
 type ClientId = u16;
 type TransactionId = u32;
@@ -17,6 +32,22 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    // Moves `amount` from `currency` to `to_currency` at the configured FX rate
+    Convert,
+}
+
+impl TransactionType {
+    // Same as in the input, e.g., `deposit`
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Convert => "convert",
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
@@ -39,6 +70,13 @@ pub struct Transaction {
     // is Some(T) or None based on the type is unfortunate.
     // It *should* be enforced via the type system.
     pub amount: Option<Amount>,
+    // Optional column; transactions without one are booked in the engine's base currency.
+    // Disputes, resolves, and chargebacks always apply in the disputed transaction's currency.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    // Only used by `Convert`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_currency: Option<Currency>,
 }
 
 impl Transaction {
@@ -53,57 +91,208 @@ impl Transaction {
             client_id,
             action,
             amount,
+            currency: None,
+            to_currency: None,
         }
     }
+
+    pub const fn in_currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    pub const fn to_currency(mut self, currency: Currency) -> Self {
+        self.to_currency = Some(currency);
+        self
+    }
 }
 
+// One row per client-currency pair. `currency` is omitted for currency-less balances so
+// single-currency input keeps the original `client, available, held, total, locked` columns.
 #[derive(Clone, Debug, Serialize, PartialEq, Eq)]
 pub struct ClientSnapshot {
     pub client: ClientId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
     pub available: Amount,
     pub held: Amount,
     pub total: Amount,
     pub locked: bool,
+    // Only reported when a fee schedule is configured so the default output
+    // keeps the original `client, available, held, total, locked` columns.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fees: Option<Amount>,
 }
 
-impl From<&Client> for ClientSnapshot {
-    fn from(client: &Client) -> Self {
+impl ClientSnapshot {
+    fn new(
+        client: &Client,
+        currency: Option<Currency>,
+        balance: &Balance,
+        with_fees: bool,
+    ) -> Self {
         ClientSnapshot {
             client: client.id,
-            available: client.available,
-            held: client.held,
-            total: client.available + client.held,
+            currency,
+            available: balance.available,
+            held: balance.held,
+            total: balance.available + balance.held,
             locked: client.is_locked,
+            fees: with_fees.then_some(balance.fees),
+        }
+    }
+}
+
+// Per-client counters for ops, updated as every transaction for the client is processed.
+// They're per client, not per currency, so every row of the client repeats them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientActivity {
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub open_disputes: u64,
+    pub chargebacks: u64,
+    // Transactions the engine refused, e.g., for insufficient funds or a locked account
+    pub rejected: u64,
+    // Last transaction processed for the client, whether or not it was applied
+    pub last_tx: Option<TransactionId>,
+}
+
+impl ClientActivity {
+    fn record(
+        &mut self,
+        id: TransactionId,
+        action: TransactionType,
+        is_applied: bool,
+        open_disputes: usize,
+    ) {
+        self.last_tx = Some(id);
+        self.open_disputes = open_disputes as u64;
+        if !is_applied {
+            self.rejected += 1;
+            return;
+        }
+
+        match action {
+            TransactionType::Deposit => self.deposits += 1,
+            TransactionType::Withdrawal => self.withdrawals += 1,
+            TransactionType::Chargeback => self.chargebacks += 1,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Convert => {}
+        }
+    }
+}
+
+// `ClientSnapshot` with the client's activity counters as extra columns, for ops.
+// Serialized by hand since the csv crate can't serialize `#[serde(flatten)]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtendedClientSnapshot {
+    pub snapshot: ClientSnapshot,
+    pub activity: ClientActivity,
+}
+
+impl Serialize for ExtendedClientSnapshot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ClientSnapshot {
+            client,
+            currency,
+            available,
+            held,
+            total,
+            locked,
+            fees,
+        } = &self.snapshot;
+        let activity = &self.activity;
+
+        let mut state = serializer.serialize_struct("ExtendedClientSnapshot", 13)?;
+        state.serialize_field("client", client)?;
+        match currency {
+            Some(currency) => state.serialize_field("currency", currency)?,
+            None => state.skip_field("currency")?,
         }
+        state.serialize_field("available", available)?;
+        state.serialize_field("held", held)?;
+        state.serialize_field("total", total)?;
+        state.serialize_field("locked", locked)?;
+        match fees {
+            Some(fees) => state.serialize_field("fees", fees)?,
+            None => state.skip_field("fees")?,
+        }
+        state.serialize_field("deposits", &activity.deposits)?;
+        state.serialize_field("withdrawals", &activity.withdrawals)?;
+        state.serialize_field("open_disputes", &activity.open_disputes)?;
+        state.serialize_field("chargebacks", &activity.chargebacks)?;
+        state.serialize_field("rejected", &activity.rejected)?;
+        state.serialize_field("last_tx", &activity.last_tx)?;
+        state.end()
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Balance {
+    available: Amount,
+    held: Amount,
+    // Total fees charged in this currency (already deducted from `available`)
+    fees: Amount,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 struct Client {
     id: ClientId,
-    available: Amount,
-    held: Amount,
+    // `None` holds currency-less funds when no base currency is configured. `available`
+    // and `held` are the client's accounts in the engine's `Ledger`, and only change
+    // through its postings.
+    balances: BTreeMap<Option<Currency>, Balance>,
     is_locked: bool,
-    // Should be something like an LRU distributed
-    // cache in a real system. Cache miss => DB lookup.
-    //
-    // Again, would be nice if we could restrict this to only
-    // deposit/withdrawal variants within the type system.
-    //
-    // Using BTreeMap + BTreeSet for less memory overhead
-    basic_transactions: BTreeMap<TransactionId, Transaction>,
+    // Using BTreeSet for less memory overhead. The disputed transactions themselves are
+    // in the engine's `TransactionHistory`.
     disputes: BTreeSet<TransactionId>,
+    activity: ClientActivity,
 }
 
 impl Client {
     pub fn new(id: ClientId) -> Self {
         Self {
             id,
-            available: Amount::from(0),
-            held: Amount::from(0),
+            balances: BTreeMap::new(),
             is_locked: false,
-            basic_transactions: BTreeMap::new(),
             disputes: BTreeSet::new(),
+            activity: ClientActivity::default(),
+        }
+    }
+
+    // Zero if the client has no balance in `currency`, without opening one
+    fn balance(&self, currency: Option<Currency>) -> Balance {
+        self.balances.get(&currency).copied().unwrap_or_default()
+    }
+
+    fn balance_mut(&mut self, currency: Option<Currency>) -> &mut Balance {
+        self.balances.entry(currency).or_default()
+    }
+
+    // Clients that never held funds still get a (zeroed) row in `default_currency`
+    fn snapshots(
+        &self,
+        default_currency: Option<Currency>,
+        with_fees: bool,
+    ) -> Vec<ExtendedClientSnapshot> {
+        if self.balances.is_empty() {
+            return vec![self.snapshot(default_currency, &Balance::default(), with_fees)];
+        }
+
+        self.balances
+            .iter()
+            .map(|(&currency, balance)| self.snapshot(currency, balance, with_fees))
+            .collect()
+    }
+
+    fn snapshot(
+        &self,
+        currency: Option<Currency>,
+        balance: &Balance,
+        with_fees: bool,
+    ) -> ExtendedClientSnapshot {
+        ExtendedClientSnapshot {
+            snapshot: ClientSnapshot::new(self, currency, balance, with_fees),
+            activity: self.activity,
         }
     }
 }
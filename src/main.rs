@@ -1,23 +1,651 @@
 #![deny(clippy::all)]
 #![warn(clippy::pedantic)]
 
-use anyhow::anyhow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::bail;
+use clap::{Parser, Subcommand};
 use log::info;
 
-use payment_engine::engine::{Engine, PaymentEngine};
+use payment_engine::ach::TraceIds;
+use payment_engine::camt::Statements;
+use payment_engine::chunked::ChunkedReader;
+use payment_engine::currency::{Currency, CurrencyList};
+#[cfg(feature = "sqlite")]
+use payment_engine::engine::sqlite::SqlitePaymentEngine;
+use payment_engine::engine::{
+    Engine, EngineConfig, PaymentEngine, fees::FeeSchedule, history::HistoryConfig,
+};
+use payment_engine::format::{
+    InputFormat, InputSource, OutputFormat, RecordWriter, Transactions, ach_transactions,
+    merge_by_transaction_id,
+};
+use payment_engine::fx::FxRateTable;
+use payment_engine::mapping::ColumnMapping;
+use payment_engine::parse::binary::BinaryWriter;
+use payment_engine::parse::{Amount, Rate, RoundingMode, WideAmount};
+use payment_engine::queue::SegmentLog;
+use payment_engine::{ClientSnapshot, ExtendedClientSnapshot, Transaction};
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Processes CSV, JSON Lines, binary or NACHA ACH files of transactions and prints the final client balances",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Write the balances to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Add per-client activity columns to the balances: deposits, withdrawals,
+    /// `open_disputes`, chargebacks, rejected (transactions) and `last_tx` (id)
+    #[arg(long)]
+    extended: bool,
+
+    /// Output format: csv, jsonl or json (a single array)
+    /// [default: from the output's extension, else the input format]
+    #[arg(long, value_name = "FORMAT")]
+    output_format: Option<OutputFormat>,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+
+    /// Also write an ISO 20022 camt.053 statement per client (`<client>.xml`) to this
+    /// directory
+    #[arg(long, value_name = "DIR", requires = "statement_date")]
+    statements: Option<PathBuf>,
+
+    /// Booking date of the statements (YYYY-MM-DD)
+    #[arg(long, value_name = "DATE", requires = "statements")]
+    statement_date: Option<String>,
+
+    /// Write the engine's metrics (Prometheus text format) to this file once every
+    /// transaction is processed
+    #[arg(long, value_name = "PATH")]
+    metrics: Option<PathBuf>,
+
+    /// Keep the clients in this database file, created if needed. Transactions apply on
+    /// top of what earlier runs stored, and the balances of every stored client are printed
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "PATH", conflicts_with = "statements")]
+    database: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Converts transactions to the binary format (`.petx`) without processing them
+    Convert {
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// Write the binary transactions to this file instead of stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+
+    /// Appends transactions to a local segment-file queue (see `consume`)
+    Enqueue {
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// Queue directory, created if needed
+        #[arg(long, value_name = "DIR")]
+        queue: PathBuf,
+
+        /// Transactions per segment file
+        #[arg(long, default_value_t = 100_000, value_name = "COUNT")]
+        segment_size: u64,
+    },
+
+    /// Processes a local segment-file queue from where the last run left off, then
+    /// prints every client's balances. The engine is checkpointed before offsets are
+    /// committed, so a restart never applies a transaction twice
+    #[cfg(not(feature = "stream"))]
+    Consume(ConsumeArgs),
+
+    /// Prints every transaction processed for a client in a `--database`, across runs
+    #[cfg(feature = "sqlite")]
+    History {
+        /// Database file written with `--database`
+        #[arg(long, value_name = "PATH")]
+        database: PathBuf,
+
+        /// Client whose transactions to print
+        #[arg(long, value_name = "ID")]
+        client: u16,
+
+        /// Output format: csv, jsonl or json
+        #[arg(long, default_value = "csv", value_name = "FORMAT")]
+        output_format: OutputFormat,
+    },
+
+    /// Serves the engine over HTTP: `POST /transactions`, `GET /clients` and
+    /// `GET /clients/{id}`
+    #[cfg(not(feature = "stream"))]
+    Serve {
+        #[command(flatten)]
+        engine: EngineArgs,
+
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080", value_name = "ADDRESS")]
+        listen: String,
+    },
+
+    /// Serves the engine over gRPC (see `proto/payment_engine.proto`)
+    #[cfg(feature = "grpc")]
+    ServeGrpc {
+        #[command(flatten)]
+        engine: EngineArgs,
+
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:50051", value_name = "ADDRESS")]
+        listen: std::net::SocketAddr,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+struct EngineArgs {
+    /// Flat fee charged on every deposit
+    #[arg(long, default_value_t = Amount::ZERO, value_name = "AMOUNT", value_parser = non_negative::<Amount>)]
+    deposit_fee: Amount,
+
+    /// Flat fee charged on every withdrawal
+    #[arg(long, default_value_t = Amount::ZERO, value_name = "AMOUNT", value_parser = non_negative::<Amount>)]
+    withdrawal_fee: Amount,
+
+    /// Fraction of the withdrawn amount charged as a fee (e.g., 0.015 = 1.5%)
+    #[arg(long, default_value = "0", value_name = "RATE", value_parser = non_negative::<Rate>)]
+    withdrawal_fee_rate: Rate,
+
+    /// Flat fee charged on every chargeback
+    #[arg(long, default_value_t = Amount::ZERO, value_name = "AMOUNT", value_parser = non_negative::<Amount>)]
+    chargeback_fee: Amount,
+
+    /// Rounding for percentage fees: half-even, half-up, down or up
+    #[arg(long, default_value = "half-even", value_name = "MODE")]
+    fee_rounding: RoundingMode,
+
+    /// Comma separated ISO 4217 codes transactions may use [default: all of ISO 4217]
+    #[arg(long, value_name = "CODES")]
+    currencies: Option<CurrencyList>,
+
+    /// Currency for transactions without a `currency` column
+    #[arg(long, value_name = "CODE")]
+    base_currency: Option<Currency>,
+
+    /// CSV of FX rates (`from,to,rate[,date]`) used by `convert` transactions
+    #[arg(long, value_name = "PATH")]
+    fx_rates: Option<PathBuf>,
+
+    /// Fraction of every conversion kept by the house (e.g., 0.005 = 0.5%)
+    #[arg(long, default_value = "0", value_name = "RATE", value_parser = fx_spread)]
+    fx_spread: Rate,
+
+    /// Rounding for converted amounts and spreads: half-even, half-up, down or up
+    #[arg(long, default_value = "half-even", value_name = "MODE")]
+    fx_rounding: RoundingMode,
+
+    /// Memory for disputable transactions, in MiB. Least recently used ones beyond it are
+    /// spilled to disk [default: unlimited]
+    #[arg(long, value_name = "MIB")]
+    history_memory: Option<usize>,
+
+    /// Directory the transaction history spills to [default: the system's temp dir]
+    #[arg(long, value_name = "DIR", requires = "history_memory")]
+    spill_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Args)]
+struct InputArgs {
+    /// Input files of transactions, processed in the given order. `-` reads stdin, and
+    /// glob patterns (e.g., 'tx-2024-01-*.csv') expand to their sorted matches
+    #[arg(required = true, value_name = "INPUT")]
+    inputs: Vec<String>,
+
+    /// Interleave the inputs by transaction id instead of reading them one after another.
+    /// Every input must already be in logical order
+    #[arg(long)]
+    merge: bool,
+
+    /// Threads used to parse each uncompressed input file. Transactions still reach the
+    /// engine in file order
+    #[arg(long, default_value_t = 1, value_name = "THREADS")]
+    parse_threads: usize,
+
+    /// Input format: csv, jsonl, binary or ach [default: from each input's extension, else csv]
+    #[arg(long, value_name = "FORMAT")]
+    input_format: Option<InputFormat>,
+
+    /// Comma separated `field=column` pairs mapping our fields (`tx`, `client`, `type`,
+    /// `amount`, `currency`, `to_currency`) to the input's column names or 0-based indexes
+    #[arg(long, value_name = "MAPPING")]
+    columns: Option<String>,
+
+    /// Comma separated `alias=type` pairs, e.g., 'credit=deposit,debit=withdrawal'
+    #[arg(long, value_name = "ALIASES")]
+    type_aliases: Option<String>,
+
+    /// CSV input has no header row. Columns are read by `--columns` indexes, else in
+    /// `type,client,tx,amount,currency,to_currency` order
+    #[arg(long)]
+    no_header: bool,
+
+    /// Transaction id of the first ACH entry. Every new trace number, across all ACH
+    /// inputs, gets the next one, so start past the ids of the other inputs
+    #[arg(long, default_value_t = 1, value_name = "TX")]
+    ach_first_id: u32,
+}
+
+#[cfg(not(feature = "stream"))]
+#[derive(Debug, clap::Args)]
+struct ConsumeArgs {
+    #[command(flatten)]
+    engine: EngineArgs,
+
+    /// Queue directory written by `enqueue`
+    #[arg(long, value_name = "DIR")]
+    queue: PathBuf,
+
+    /// Consumer group the offsets are committed for
+    #[arg(long, default_value = "payment-engine", value_name = "NAME")]
+    group: String,
+
+    /// Engine checkpoint [default: `<queue>/<group>.checkpoint.json`]
+    #[arg(long, value_name = "PATH")]
+    checkpoint: Option<PathBuf>,
+
+    /// Checkpoint and commit after this many transactions (and once caught up)
+    #[arg(long, default_value_t = 10_000, value_name = "COUNT")]
+    checkpoint_interval: usize,
+
+    /// Write the balances to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Output format: csv, jsonl or json [default: from the output's extension, else csv]
+    #[arg(long, value_name = "FORMAT")]
+    output_format: Option<OutputFormat>,
+
+    /// Add per-client activity columns to the balances
+    #[arg(long)]
+    extended: bool,
+}
+
+impl InputArgs {
+    fn input_sources(&self) -> anyhow::Result<Vec<InputSource>> {
+        let mut sources = Vec::new();
+        for input in &self.inputs {
+            sources.extend(InputSource::expand(input)?);
+        }
+        if sources.iter().filter(|&s| *s == InputSource::Stdin).count() > 1 {
+            bail!("`-` (stdin) can only be given once");
+        }
+        Ok(sources)
+    }
+
+    fn input_format(&self, source: &InputSource) -> InputFormat {
+        self.input_format
+            .or_else(|| source.format())
+            .unwrap_or(InputFormat::Csv)
+    }
+
+    fn column_mapping(&self) -> anyhow::Result<ColumnMapping> {
+        let mut mapping = ColumnMapping::default().headerless(self.no_header);
+        if let Some(columns) = &self.columns {
+            mapping = mapping.with_columns(columns)?;
+        }
+        if let Some(aliases) = &self.type_aliases {
+            mapping = mapping.with_type_aliases(aliases)?;
+        }
+        Ok(mapping)
+    }
+
+    // Inputs are all opened up front so a missing file fails before anything is processed
+    fn transactions(&self, sources: &[InputSource]) -> anyhow::Result<Transactions> {
+        let mapping = self.column_mapping()?;
+        let trace_ids = TraceIds::starting_at(self.ach_first_id);
+        let mut inputs = Vec::with_capacity(sources.len());
+        for source in sources {
+            let format = self.input_format(source);
+            info!("Reading {format} input from {source}");
+            inputs.push(match source {
+                _ if format == InputFormat::Ach => ach_transactions(source.open()?, &trace_ids),
+                InputSource::Path(path) if self.parse_threads > 1 && mapping.is_identity() => {
+                    match ChunkedReader::open(path, format, self.parse_threads)? {
+                        Some(reader) => Box::new(reader),
+                        None => format.transactions(source.open()?),
+                    }
+                }
+                _ => format.mapped_transactions(source.open()?, &mapping),
+            });
+        }
+
+        Ok(if self.merge {
+            merge_by_transaction_id(inputs)
+        } else {
+            Box::new(inputs.into_iter().flatten())
+        })
+    }
+}
+
+impl EngineArgs {
+    fn engine_config(&self) -> anyhow::Result<EngineConfig> {
+        let fx_rates = match &self.fx_rates {
+            Some(path) => FxRateTable::from_path(path)?,
+            None => FxRateTable::default(),
+        };
+
+        let fee_schedule = FeeSchedule {
+            deposit_flat: self.deposit_fee,
+            withdrawal_flat: self.withdrawal_fee,
+            withdrawal_rate: self.withdrawal_fee_rate,
+            chargeback_flat: self.chargeback_fee,
+            rounding: self.fee_rounding,
+        };
+        let config = EngineConfig {
+            fee_schedule,
+            currencies: self.currencies.clone().unwrap_or_default(),
+            base_currency: self.base_currency,
+            fx_rates: Arc::new(fx_rates),
+            fx_spread: self.fx_spread,
+            fx_rounding: self.fx_rounding,
+            history: HistoryConfig {
+                memory_budget: self.history_memory.map(|mib| mib.saturating_mul(1 << 20)),
+                spill_dir: self.spill_dir.clone(),
+            },
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+// Fees and spreads below zero would pay the client out of the house, i.e., create money
+fn non_negative<T: FromStr<Err = anyhow::Error> + Default + PartialOrd>(
+    s: &str,
+) -> anyhow::Result<T> {
+    let value = s.parse()?;
+    if value < T::default() {
+        bail!("can't be negative");
+    }
+    Ok(value)
+}
+
+// A spread of 1 or more would take more than the converted amount
+fn fx_spread(s: &str) -> anyhow::Result<Rate> {
+    let spread = non_negative::<Rate>(s)?;
+    if spread >= Rate::ONE {
+        bail!("must be less than 1");
+    }
+    Ok(spread)
+}
+
+impl Args {
+    fn output_format(&self, sources: &[InputSource]) -> OutputFormat {
+        self.output_format
+            .or_else(|| self.output.as_ref().and_then(OutputFormat::from_path))
+            .or_else(|| Some(self.input.input_format(sources.first()?).into()))
+            .unwrap_or(OutputFormat::Csv)
+    }
+}
+
+// Re-encodes the inputs as binary transactions, e.g., to feed internal pipelines
+fn convert(input: &InputArgs, output: Option<&Path>) -> anyhow::Result<()> {
+    let sources = input.input_sources()?;
+    let output: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+
+    let mut writer = BinaryWriter::new(output)?;
+    let mut converted = 0usize;
+    for row in input.transactions(&sources)? {
+        writer.write(&row?)?;
+        converted += 1;
+    }
+    writer.finish()?;
+    info!("Converted {converted} transactions");
+    Ok(())
+}
+
+fn enqueue(input: &InputArgs, queue: &Path, segment_size: u64) -> anyhow::Result<()> {
+    let sources = input.input_sources()?;
+    let mut producer = SegmentLog::open(queue)?
+        .with_segment_size(segment_size)
+        .producer()?;
+    let mut last_offset = None;
+    for row in input.transactions(&sources)? {
+        last_offset = Some(producer.send(&row?)?);
+    }
+    producer.flush()?;
+    if let Some(offset) = last_offset {
+        info!("Enqueued up to offset {offset} in {}", queue.display());
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "stream"))]
+fn consume(args: &ConsumeArgs) -> anyhow::Result<()> {
+    use payment_engine::queue::{self, QueueConsumer};
+
+    let checkpoint = args
+        .checkpoint
+        .clone()
+        .unwrap_or_else(|| args.queue.join(format!("{}.checkpoint.json", args.group)));
+    let mut consumer = SegmentLog::open(&args.queue)?.consumer(&args.group)?;
+    let mut engine = queue::resume(args.engine.engine_config()?, &mut consumer, &checkpoint)?;
+    let processed = queue::consume(
+        &mut engine,
+        &mut consumer,
+        &checkpoint,
+        args.checkpoint_interval,
+    )?;
+    info!(
+        "Processed {processed} transactions, committed offset {}",
+        consumer.committed()
+    );
+
+    let output_format = args
+        .output_format
+        .or_else(|| args.output.as_ref().and_then(OutputFormat::from_path))
+        .unwrap_or(OutputFormat::Csv);
+    write_balances(
+        args.output.as_deref(),
+        output_format,
+        args.extended,
+        engine.snapshots(),
+    )
+}
+
+// Same as `PaymentEngine::process`, but records the applied transaction's entries
+#[cfg(not(feature = "stream"))]
+fn process_for_statements(
+    engine: &mut Engine,
+    statements: &mut Statements,
+    transaction: &Transaction,
+) -> anyhow::Result<()> {
+    use payment_engine::engine::TransactionProcessError;
+
+    match engine.process_with_outcome(transaction.clone()) {
+        Ok(outcome) => statements.record(transaction, &outcome),
+        Err(err @ TransactionProcessError::Unknown) => return Err(err.into()),
+        Err(err) => log::error!("{err}"),
+    }
+    Ok(())
+}
+
+// Workers only hand back their final snapshots, not the outcome of every transaction
+#[cfg(feature = "stream")]
+fn process_for_statements(
+    _engine: &mut Engine,
+    _statements: &mut Statements,
+    _transaction: &Transaction,
+) -> anyhow::Result<()> {
+    bail!("--statements isn't supported by the stream engine")
+}
+
+#[cfg(feature = "sqlite")]
+fn history(database: &Path, client: u16, output_format: OutputFormat) -> anyhow::Result<()> {
+    let engine = SqlitePaymentEngine::open(database, EngineConfig::default())?;
+    let mut writer = RecordWriter::new(std::io::stdout().lock(), output_format);
+    for entry in engine.history(client)? {
+        writer.write(&entry)?;
+    }
+    writer.finish()?.flush()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "stream"))]
+fn serve(engine: &EngineArgs, listen: &str) -> anyhow::Result<()> {
+    let server = payment_engine::server::EngineServer::bind(
+        listen,
+        Engine::with_config(engine.engine_config()?),
+    )?;
+    server.run();
+    Ok(())
+}
+
+fn write_statements(
+    statements: &Statements,
+    dir: &Path,
+    snapshots: &[ClientSnapshot],
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let clients = snapshots.iter().map(|s| s.client).collect::<BTreeSet<_>>();
+    for &client in &clients {
+        let file = BufWriter::new(File::create(dir.join(format!("{client}.xml")))?);
+        statements.write(file, client, snapshots)?.flush()?;
+    }
+    info!("Wrote {} statements to {}", clients.len(), dir.display());
+    Ok(())
+}
+
+fn write_balances(
+    output: Option<&Path>,
+    output_format: OutputFormat,
+    with_activity: bool,
+    mut snapshots: Vec<ExtendedClientSnapshot>,
+) -> anyhow::Result<()> {
+    // Every CSV row needs the same columns, which can't hold if only some rows have a
+    // currency. Clients that never held funds have nothing to report per currency, though.
+    if snapshots.iter().any(|s| s.snapshot.currency.is_some()) {
+        snapshots.retain(|ExtendedClientSnapshot { snapshot: s, .. }| {
+            s.currency.is_some() || s.total != Amount::ZERO || s.held != Amount::ZERO
+        });
+        if output_format == OutputFormat::Csv
+            && snapshots.iter().any(|s| s.snapshot.currency.is_none())
+        {
+            bail!("Input mixes transactions with and without a currency; set --base-currency");
+        }
+    }
+
+    let output: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut writer = RecordWriter::new(output, output_format);
+    // Sums across all clients can exceed a single balance's range
+    let mut house_revenue = BTreeMap::<Option<Currency>, WideAmount>::new();
+    let mut system_totals = BTreeMap::<Option<Currency>, WideAmount>::new();
+    for extended in snapshots {
+        let snapshot = &extended.snapshot;
+        *house_revenue.entry(snapshot.currency).or_default() += snapshot.fees.unwrap_or_default();
+        *system_totals.entry(snapshot.currency).or_default() += snapshot.total;
+        if with_activity {
+            writer.write(&extended)?;
+        } else {
+            writer.write(snapshot)?;
+        }
+    }
+    writer.finish()?;
+    for (currency, total) in system_totals {
+        let revenue = house_revenue.remove(&currency).unwrap_or_default();
+        let currency = currency.as_ref().map_or("", Currency::as_str);
+        info!("Client funds: {total} {currency}");
+        info!("House revenue from fees: {revenue} {currency}");
+    }
+
+    Ok(())
+}
+
+// Writes the engine's final balances, plus its metrics and the statements if requested
+fn finish<E: PaymentEngine>(
+    args: &Args,
+    engine: E,
+    statements: Option<&Statements>,
+    output_format: OutputFormat,
+) -> anyhow::Result<()>
+where
+    E::SnapshotError: Into<anyhow::Error>,
+{
+    let metrics = engine.metrics();
+    let snapshots = engine
+        .finalize_extended()
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Into::into)?;
+    if let Some(path) = &args.metrics {
+        std::fs::write(path, metrics.render())?;
+    }
+    if let (Some(statements), Some(dir)) = (statements, &args.statements) {
+        let snapshots = snapshots
+            .iter()
+            .map(|s| s.snapshot.clone())
+            .collect::<Vec<_>>();
+        write_statements(statements, dir, &snapshots)?;
+    }
+
+    write_balances(
+        args.output.as_deref(),
+        output_format,
+        args.extended,
+        snapshots,
+    )
+}
 
 fn main() -> anyhow::Result<()> {
-    // Since the executable name is always the first argument, we must skip to
-    // the second one (which is the first "real" user-specified arg) to get the file name.
-    let input_file_path = std::env::args()
-        .nth(1)
-        .ok_or_else(|| anyhow!("No input file path specified"))?;
-    let mut reader = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .flexible(true)
-        .from_path(&input_file_path)?;
-
-    info!("Reading input from {input_file_path}");
+    let args = Args::parse();
+    match &args.command {
+        Some(Command::Convert { input, output }) => return convert(input, output.as_deref()),
+        Some(Command::Enqueue {
+            input,
+            queue,
+            segment_size,
+        }) => return enqueue(input, queue, *segment_size),
+        #[cfg(not(feature = "stream"))]
+        Some(Command::Consume(args)) => return consume(args),
+        #[cfg(feature = "sqlite")]
+        Some(Command::History {
+            database,
+            client,
+            output_format,
+        }) => return history(database, *client, *output_format),
+        #[cfg(not(feature = "stream"))]
+        Some(Command::Serve { engine, listen }) => return serve(engine, listen),
+        #[cfg(feature = "grpc")]
+        Some(Command::ServeGrpc { engine, listen }) => {
+            let engine = Engine::with_config(engine.engine_config()?);
+            return tokio::runtime::Runtime::new()?
+                .block_on(payment_engine::grpc::serve(*listen, engine));
+        }
+        None => {}
+    }
+
+    let sources = args.input.input_sources()?;
+    let output_format = args.output_format(&sources);
+    let transactions = args.input.transactions(&sources)?;
 
     // TODO (PERF + CORRECTNESS): Address StreamPaymentEngine's thread
     // issue (N threads where N = unique clients... need a threadpool)
@@ -25,23 +653,29 @@ fn main() -> anyhow::Result<()> {
     //
     // I included this anyway to show give you a good high-level idea of
     // how I think it may work. In practice, this would connect to a
-    // distributed queue + enqueue => worker nodes pull.
-    let mut engine = Engine::default();
-    for row in reader.deserialize() {
-        let transaction = row?;
-        engine.process(transaction)?;
+    // distributed queue + enqueue => worker nodes pull (see `consume` for
+    // a local stand-in).
+    let config = args.engine.engine_config()?;
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &args.database {
+        let mut engine = SqlitePaymentEngine::open(path, config)?;
+        for row in transactions {
+            engine.process(row?)?;
+        }
+        return finish(&args, engine, None, output_format);
     }
 
-    let worker_results = engine.finalize();
-    {
-        let stdout = std::io::stdout();
-        let stdio = stdout.lock();
-        let mut writer = csv::Writer::from_writer(stdio);
-        for result in worker_results {
-            let snapshot = result?;
-            writer.serialize(&snapshot)?;
+    let mut engine = Engine::with_config(config);
+    let mut statements = match &args.statement_date {
+        Some(date) => Some(Statements::new(date)?.with_default_currency(args.engine.base_currency)),
+        None => None,
+    };
+    for row in transactions {
+        let transaction = row?;
+        match &mut statements {
+            Some(statements) => process_for_statements(&mut engine, statements, &transaction)?,
+            None => engine.process(transaction)?,
         }
     }
-
-    Ok(())
+    finish(&args, engine, statements.as_ref(), output_format)
 }
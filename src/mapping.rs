@@ -0,0 +1,331 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use serde::de::IntoDeserializer;
+
+use crate::{Transaction, TransactionType};
+
+// `Transaction`'s serde names, in the column order of our own CSVs. Header-less input
+// without a mapping is assumed to use this order.
+pub const FIELDS: [&str; 6] = ["type", "client", "tx", "amount", "currency", "to_currency"];
+
+// Columns the row can't be deserialized without
+const REQUIRED_FIELDS: [&str; 3] = ["type", "client", "tx"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    Name(String),
+    // 0-based, e.g., for files without a header
+    Index(usize),
+}
+
+impl FromStr for Column {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Ok(s.parse()
+            .map_or_else(|_| Column::Name(s.to_owned()), Column::Index))
+    }
+}
+
+// Maps a partner's columns and values onto `Transaction`'s fields. The mapping is applied
+// to the raw row before deserialization, so `Amount` validation etc. is unchanged.
+//
+// Example: a file with `transaction_id,account,kind,value` headers and `credit`/`debit`
+// kinds maps with `tx=transaction_id,client=account,type=kind,amount=value` plus the
+// `credit=deposit,debit=withdrawal` type aliases.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ColumnMapping {
+    // Fields without an entry use the column with the field's own name, or its position
+    // in `FIELDS` for header-less input.
+    columns: BTreeMap<&'static str, Column>,
+    // Alias => `TransactionType` serde name
+    type_aliases: BTreeMap<String, String>,
+    headerless: bool,
+}
+
+impl ColumnMapping {
+    pub fn with_column(mut self, field: &str, column: Column) -> anyhow::Result<Self> {
+        let Some(&field) = FIELDS.iter().find(|&&name| name == field.trim()) else {
+            anyhow::bail!(
+                "Unknown transaction field `{field}`, expected one of {}",
+                FIELDS.join(", ")
+            );
+        };
+        self.columns.insert(field, column);
+        Ok(self)
+    }
+
+    // Comma separated `field=column` pairs, e.g., "tx=transaction_id,client=1"
+    pub fn with_columns(self, spec: &str) -> anyhow::Result<Self> {
+        pairs(spec)?
+            .into_iter()
+            .try_fold(self, |mapping, (field, column)| {
+                let Ok(column) = column.parse();
+                mapping.with_column(field, column)
+            })
+    }
+
+    // Comma separated `alias=type` pairs, e.g., "credit=deposit,debit=withdrawal"
+    pub fn with_type_aliases(mut self, spec: &str) -> anyhow::Result<Self> {
+        for (alias, action) in pairs(spec)? {
+            let action = action.trim();
+            TransactionType::deserialize(action.into_deserializer())
+                .map_err(|err: serde::de::value::Error| anyhow::anyhow!("{err}"))?;
+            self.type_aliases
+                .insert(alias.trim().to_owned(), action.to_owned());
+        }
+        Ok(self)
+    }
+
+    pub fn headerless(mut self, headerless: bool) -> Self {
+        self.headerless = headerless;
+        self
+    }
+
+    pub fn is_headerless(&self) -> bool {
+        self.headerless
+    }
+
+    // Nothing to remap => rows can be deserialized directly
+    pub fn is_identity(&self) -> bool {
+        self.columns.is_empty() && self.type_aliases.is_empty() && !self.headerless
+    }
+
+    fn alias<'a>(&'a self, action: &'a str) -> &'a str {
+        self.type_aliases.get(action).map_or(action, String::as_str)
+    }
+
+    // Position of every field present in the CSV, given the file's header (if it has one)
+    pub fn csv_positions(
+        &self,
+        headers: Option<&csv::StringRecord>,
+    ) -> anyhow::Result<Vec<(&'static str, usize)>> {
+        let mut positions = Vec::with_capacity(FIELDS.len());
+        for (default_index, field) in FIELDS.into_iter().enumerate() {
+            let position = match (self.columns.get(field), headers) {
+                (Some(Column::Index(index)), _) => Some(*index),
+                (Some(Column::Name(name)), Some(headers)) => {
+                    headers.iter().position(|header| header == name)
+                }
+                (Some(Column::Name(name)), None) => {
+                    anyhow::bail!("Column `{name}` can't be found in input without a header")
+                }
+                (None, Some(headers)) => headers.iter().position(|header| header == field),
+                (None, None) => Some(default_index),
+            };
+            match position {
+                Some(position) => positions.push((field, position)),
+                None if REQUIRED_FIELDS.contains(&field) => {
+                    anyhow::bail!("Input has no column for `{field}`")
+                }
+                None => {}
+            }
+        }
+        Ok(positions)
+    }
+
+    // Rebuilds a CSV row with `Transaction`'s own column names and deserializes it
+    pub fn csv_transaction(
+        &self,
+        positions: &[(&'static str, usize)],
+        record: &csv::StringRecord,
+    ) -> csv::Result<Transaction> {
+        let mut headers = csv::StringRecord::with_capacity(64, positions.len());
+        let mut row = csv::StringRecord::with_capacity(record.as_slice().len(), positions.len());
+        for &(field, position) in positions {
+            // Flexible rows may be missing trailing (optional) columns
+            let Some(value) = record.get(position) else {
+                continue;
+            };
+            headers.push_field(field);
+            row.push_field(if field == "type" {
+                self.alias(value)
+            } else {
+                value
+            });
+        }
+        row.deserialize(Some(&headers))
+    }
+
+    // JSON objects are mapped by key; there's no column order to index into. Like the CSV
+    // row, the object is rebuilt from the original, so fields can swap keys.
+    pub fn json_transaction(&self, value: serde_json::Value) -> anyhow::Result<Transaction> {
+        let serde_json::Value::Object(object) = value else {
+            return Ok(serde_json::from_value(value)?);
+        };
+        let mut mapped = serde_json::Map::with_capacity(FIELDS.len());
+        for field in FIELDS {
+            let key = match self.columns.get(field) {
+                Some(Column::Name(name)) => name.as_str(),
+                Some(Column::Index(_)) => {
+                    anyhow::bail!("JSON input can't map `{field}` by column index")
+                }
+                None => field,
+            };
+            if let Some(value) = object.get(key) {
+                mapped.insert(field.to_owned(), value.clone());
+            }
+        }
+        if let Some(serde_json::Value::String(action)) = mapped.get_mut("type") {
+            *action = self.alias(action).to_owned();
+        }
+        Ok(serde_json::from_value(serde_json::Value::Object(mapped))?)
+    }
+}
+
+fn pairs(spec: &str) -> anyhow::Result<Vec<(&str, &str)>> {
+    spec.split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            pair.split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Expected `name=value`, found `{pair}`"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod column_mapping_tests {
+    use googletest::prelude::*;
+
+    use super::*;
+    use crate::format::InputFormat;
+    use crate::parse::Amount;
+
+    fn read(
+        format: InputFormat,
+        mapping: &ColumnMapping,
+        input: &'static str,
+    ) -> Vec<anyhow::Result<Transaction>> {
+        format
+            .mapped_transactions(input.as_bytes(), mapping)
+            .collect()
+    }
+
+    fn partner_mapping() -> ColumnMapping {
+        ColumnMapping::default()
+            .with_columns("tx=transaction_id, client=account, type=kind, amount=value")
+            .unwrap()
+            .with_type_aliases("credit=deposit, debit=withdrawal")
+            .unwrap()
+    }
+
+    fn expected() -> Vec<Transaction> {
+        vec![
+            Transaction::new(7, 3, TransactionType::Deposit, Amount::new(2.5).ok()),
+            Transaction::new(8, 3, TransactionType::Withdrawal, Amount::new(1.0).ok()),
+            Transaction::new(7, 3, TransactionType::Dispute, None),
+        ]
+    }
+
+    #[gtest]
+    pub fn maps_csv_columns_by_name_and_aliases_types() {
+        let transactions = read(
+            InputFormat::Csv,
+            &partner_mapping(),
+            "account,kind,value,transaction_id,note
+3,credit,2.5,7,hello
+3,debit,1.0,8,
+3,dispute,,7,
+",
+        );
+        let transactions = transactions
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        expect_that!(transactions, eq(&expected()));
+    }
+
+    #[gtest]
+    pub fn maps_headerless_csv_by_index() {
+        let mapping = partner_mapping()
+            .with_columns("tx=0, client=1, type=2, amount=3")
+            .unwrap()
+            .headerless(true);
+        let transactions = read(
+            InputFormat::Csv,
+            &mapping,
+            "7,3,credit,2.5\n8,3,debit,1.0\n7,3,dispute\n",
+        );
+        let transactions = transactions
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        expect_that!(transactions, eq(&expected()));
+    }
+
+    #[gtest]
+    pub fn headerless_csv_defaults_to_our_column_order() {
+        let mapping = ColumnMapping::default().headerless(true);
+        let transactions = read(InputFormat::Csv, &mapping, "deposit,3,7,2.5\n");
+        expect_that!(transactions, elements_are![ok(eq(&expected()[0]))]);
+    }
+
+    #[gtest]
+    pub fn maps_json_lines_by_key() {
+        let transactions = read(
+            InputFormat::JsonLines,
+            &partner_mapping(),
+            r#"{"transaction_id":7,"account":3,"kind":"credit","value":2.5}
+{"transaction_id":8,"account":3,"kind":"debit","value":1.0}
+{"transaction_id":7,"account":3,"kind":"dispute"}
+"#,
+        );
+        let transactions = transactions
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        expect_that!(transactions, eq(&expected()));
+    }
+
+    #[gtest]
+    pub fn maps_swapped_columns_the_same_in_csv_and_json() {
+        let mapping = ColumnMapping::default()
+            .with_columns("client=tx, tx=client")
+            .unwrap();
+        let expected = Transaction::new(7, 3, TransactionType::Deposit, Amount::new(2.5).ok());
+
+        let csv = read(
+            InputFormat::Csv,
+            &mapping,
+            "type,client,tx,amount\ndeposit,7,3,2.5\n",
+        );
+        expect_that!(csv, elements_are![ok(eq(&expected))]);
+        let json = read(
+            InputFormat::JsonLines,
+            &mapping,
+            r#"{"type":"deposit","client":7,"tx":3,"amount":2.5}"#,
+        );
+        expect_that!(json, elements_are![ok(eq(&expected))]);
+    }
+
+    #[gtest]
+    pub fn mapped_amounts_are_still_validated() {
+        let transactions = read(
+            InputFormat::Csv,
+            &partner_mapping(),
+            "transaction_id,account,kind,value\n7,3,credit,1.23456\n",
+        );
+        expect_that!(transactions, elements_are![err(anything())]);
+    }
+
+    #[gtest]
+    pub fn rejects_missing_required_columns_and_unknown_specs() {
+        let transactions = read(
+            InputFormat::Csv,
+            &partner_mapping(),
+            "account,kind,value\n3,credit,1.0\n",
+        );
+        expect_that!(transactions, elements_are![err(anything())]);
+        expect_that!(
+            ColumnMapping::default().with_columns("id=tx"),
+            err(anything())
+        );
+        expect_that!(
+            ColumnMapping::default().with_type_aliases("credit=refund"),
+            err(anything())
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use std::io::{Read, Write};
+
+use crate::currency::Currency;
+use crate::parse::Amount;
+use crate::{Transaction, TransactionType};
+
+// Fixed-width binary encoding of `Transaction`s for our internal pipelines.
+//
+// File layout (all integers little-endian):
+//   header: magic `PETX` | version: u16 | record length: u16
+//   record: tag: u8 | client: u16 | tx: u32 | amount: i64 (fixed-point units)
+//           | currency: [u8; 3] | to_currency: [u8; 3]
+//
+// The low bits of the tag are the `TransactionType` and `HAS_AMOUNT` marks whether the
+// amount is present; it's zeroed otherwise. Amounts are `Amount`'s raw units, so they
+// round-trip exactly without going through text or `f64`. Currencies are their ASCII
+// codes, or zeroes for none.
+//
+// Version 1 records are the same without the currency fields. They're still read, as
+// transactions without currencies, but only version 2 is written.
+pub const MAGIC: [u8; 4] = *b"PETX";
+pub const VERSION: u16 = 2;
+pub const HEADER_LEN: usize = 8;
+pub const RECORD_LEN: usize = 21;
+pub const V1_RECORD_LEN: usize = 15;
+
+const NO_CURRENCY: [u8; 3] = [0; 3];
+
+const HAS_AMOUNT: u8 = 0x80;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BinaryFormatError {
+    #[error("not a binary transaction file (bad magic bytes)")]
+    BadMagic,
+    #[error("unsupported binary transaction format version {0} (record length {1})")]
+    UnsupportedVersion(u16, u16),
+    #[error("unknown transaction type tag {0:#04x}")]
+    UnknownTag(u8),
+    #[error("input ends in the middle of a record")]
+    TruncatedRecord,
+    #[error("invalid currency code {0:?}")]
+    InvalidCurrency([u8; 3]),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+fn tag(action: TransactionType) -> u8 {
+    match action {
+        TransactionType::Deposit => 1,
+        TransactionType::Withdrawal => 2,
+        TransactionType::Dispute => 3,
+        TransactionType::Resolve => 4,
+        TransactionType::Chargeback => 5,
+        TransactionType::Convert => 6,
+    }
+}
+
+fn action(tag: u8) -> Option<TransactionType> {
+    Some(match tag {
+        1 => TransactionType::Deposit,
+        2 => TransactionType::Withdrawal,
+        3 => TransactionType::Dispute,
+        4 => TransactionType::Resolve,
+        5 => TransactionType::Chargeback,
+        6 => TransactionType::Convert,
+        _ => return None,
+    })
+}
+
+pub fn header() -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+    header[6..].copy_from_slice(&(RECORD_LEN as u16).to_le_bytes());
+    header
+}
+
+// Record length of the version in `header`, checking its magic bytes
+pub fn record_len(header: &[u8; HEADER_LEN]) -> Result<usize, BinaryFormatError> {
+    if header[..4] != MAGIC {
+        return Err(BinaryFormatError::BadMagic);
+    }
+
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    let record_len = u16::from_le_bytes([header[6], header[7]]);
+    match (version, usize::from(record_len)) {
+        (1, V1_RECORD_LEN) | (VERSION, RECORD_LEN) => Ok(usize::from(record_len)),
+        _ => Err(BinaryFormatError::UnsupportedVersion(version, record_len)),
+    }
+}
+
+pub fn encode(transaction: &Transaction) -> [u8; RECORD_LEN] {
+    let mut record = [0; RECORD_LEN];
+    record[0] = tag(transaction.action);
+    record[1..3].copy_from_slice(&transaction.client_id.to_le_bytes());
+    record[3..7].copy_from_slice(&transaction.id.to_le_bytes());
+    if let Some(amount) = transaction.amount {
+        record[0] |= HAS_AMOUNT;
+        record[7..15].copy_from_slice(&amount.units().to_le_bytes());
+    }
+    for (field, currency) in [
+        (15..18, transaction.currency),
+        (18..21, transaction.to_currency),
+    ] {
+        if let Some(currency) = currency {
+            record[field].copy_from_slice(currency.as_str().as_bytes());
+        }
+    }
+    record
+}
+
+// `record` is a version 1 or 2 record, i.e., `V1_RECORD_LEN` or `RECORD_LEN` bytes
+pub fn decode(record: &[u8]) -> Result<Transaction, BinaryFormatError> {
+    let action = action(record[0] & !HAS_AMOUNT).ok_or(BinaryFormatError::UnknownTag(record[0]))?;
+    let client_id = u16::from_le_bytes([record[1], record[2]]);
+    let id = u32::from_le_bytes([record[3], record[4], record[5], record[6]]);
+    let amount = (record[0] & HAS_AMOUNT != 0).then(|| {
+        let mut units = [0; 8];
+        units.copy_from_slice(&record[7..15]);
+        Amount::from(i64::from_le_bytes(units))
+    });
+
+    let mut transaction = Transaction::new(id, client_id, action, amount);
+    if record.len() == RECORD_LEN {
+        transaction.currency = currency(&record[15..18])?;
+        transaction.to_currency = currency(&record[18..21])?;
+    }
+    Ok(transaction)
+}
+
+fn currency(field: &[u8]) -> Result<Option<Currency>, BinaryFormatError> {
+    let code = [field[0], field[1], field[2]];
+    if code == NO_CURRENCY {
+        return Ok(None);
+    }
+    std::str::from_utf8(&code)
+        .ok()
+        .and_then(|code| Currency::new(code).ok())
+        .map(Some)
+        .ok_or(BinaryFormatError::InvalidCurrency(code))
+}
+
+pub struct BinaryReader<R> {
+    reader: R,
+    record_len: usize,
+}
+
+impl<R: Read> BinaryReader<R> {
+    // Reads and checks the header
+    pub fn new(mut reader: R) -> Result<Self, BinaryFormatError> {
+        let mut header = [0; HEADER_LEN];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| BinaryFormatError::BadMagic)?;
+        let record_len = record_len(&header)?;
+        Ok(Self { reader, record_len })
+    }
+
+    pub fn read_transaction(&mut self) -> Option<Result<Transaction, BinaryFormatError>> {
+        let mut buffer = [0; RECORD_LEN];
+        let record = &mut buffer[..self.record_len];
+        let mut len = 0;
+        while len < record.len() {
+            match self.reader.read(&mut record[len..]) {
+                Ok(0) if len == 0 => return None,
+                Ok(0) => return Some(Err(BinaryFormatError::TruncatedRecord)),
+                Ok(read) => len += read,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+        Some(decode(record))
+    }
+}
+
+impl<R: Read> Iterator for BinaryReader<R> {
+    type Item = Result<Transaction, BinaryFormatError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_transaction()
+    }
+}
+
+pub struct BinaryWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> BinaryWriter<W> {
+    // Writes the header
+    pub fn new(mut writer: W) -> Result<Self, BinaryFormatError> {
+        writer.write_all(&header())?;
+        Ok(Self { writer })
+    }
+
+    pub fn write(&mut self, transaction: &Transaction) -> Result<(), BinaryFormatError> {
+        self.writer.write_all(&encode(transaction))?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, BinaryFormatError> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod binary_format_tests {
+    use googletest::prelude::*;
+
+    use super::*;
+    use crate::currency::Currency;
+
+    fn transactions() -> Vec<Transaction> {
+        let usd = Currency::new("USD").unwrap();
+        let eur = Currency::new("EUR").unwrap();
+        vec![
+            Transaction::new(1, 1, TransactionType::Deposit, Amount::new(1.5).ok()),
+            Transaction::new(2, u16::MAX, TransactionType::Withdrawal, Some(Amount::MAX)),
+            Transaction::new(u32::MAX, 1, TransactionType::Dispute, None),
+            Transaction::new(1, 1, TransactionType::Resolve, None),
+            Transaction::new(1, 1, TransactionType::Chargeback, None),
+            Transaction::new(3, 2, TransactionType::Deposit, Some(Amount::from(-1))),
+            Transaction::new(4, 2, TransactionType::Deposit, Amount::new(2.5).ok())
+                .in_currency(usd),
+            Transaction::new(5, 2, TransactionType::Convert, Amount::new(1.0).ok())
+                .in_currency(usd)
+                .to_currency(eur),
+        ]
+    }
+
+    fn write(transactions: &[Transaction]) -> Vec<u8> {
+        let mut writer = BinaryWriter::new(Vec::new()).unwrap();
+        for transaction in transactions {
+            writer.write(transaction).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[gtest]
+    pub fn round_trips_transactions() {
+        let bytes = write(&transactions());
+        expect_that!(
+            bytes.len(),
+            eq(HEADER_LEN + RECORD_LEN * transactions().len())
+        );
+
+        let read = BinaryReader::new(bytes.as_slice())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        expect_that!(read, eq(&transactions()));
+    }
+
+    #[gtest]
+    pub fn rejects_foreign_and_future_headers() {
+        expect_that!(
+            BinaryReader::new(b"type,client,tx,amount\n".as_slice()).err(),
+            some(matches_pattern!(BinaryFormatError::BadMagic))
+        );
+
+        let mut future = header();
+        future[4] = 3;
+        expect_that!(
+            BinaryReader::new(future.as_slice()).err(),
+            some(matches_pattern!(BinaryFormatError::UnsupportedVersion(
+                eq(&3),
+                eq(&21)
+            )))
+        );
+    }
+
+    #[gtest]
+    pub fn rejects_truncated_records_and_unknown_tags() {
+        let mut bytes = write(&transactions()[..1]);
+        bytes.extend_from_slice(&[0x7f; RECORD_LEN]);
+        bytes.extend_from_slice(&[1, 2, 3]);
+
+        let read = BinaryReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<Vec<_>>();
+        expect_that!(
+            read,
+            elements_are![
+                ok(anything()),
+                err(matches_pattern!(BinaryFormatError::UnknownTag(eq(&0x7f)))),
+                err(matches_pattern!(BinaryFormatError::TruncatedRecord)),
+            ]
+        );
+    }
+
+    #[gtest]
+    pub fn reads_version_1_records_without_currencies() {
+        let mut bytes = header().to_vec();
+        bytes[4..].copy_from_slice(&[1, 0, V1_RECORD_LEN as u8, 0]);
+        for transaction in &transactions()[..6] {
+            bytes.extend_from_slice(&encode(transaction)[..V1_RECORD_LEN]);
+        }
+
+        let read = BinaryReader::new(bytes.as_slice())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        expect_that!(read, eq(&transactions()[..6]));
+    }
+
+    #[gtest]
+    pub fn rejects_invalid_currency_codes() {
+        let mut bytes = write(&transactions()[6..7]);
+        bytes[HEADER_LEN + 15..HEADER_LEN + 18].copy_from_slice(b"us$");
+
+        let read = BinaryReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<Vec<_>>();
+        expect_that!(
+            read,
+            elements_are![err(matches_pattern!(BinaryFormatError::InvalidCurrency(
+                eq(b"us$")
+            )))]
+        );
+    }
+}
@@ -0,0 +1,970 @@
+pub mod binary;
+
+use std::fmt::Display;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// **Motivation**: it's important that our transaction arithmetic is correct.
+// Since floating points can't properly represent all possible numbers (IEEE754),
+// and since our input precision is limited to <= 4 digits after the decimal,
+// it's better to parse the amounts and convert them to u64s while processing
+// the transactions. In a real system, this `Amount` would be parsed immediately
+// up front and/or handled by clients, and then the rest of the system would use
+// this value interally.
+//
+// The number of digits after the decimal is a const generic so other assets can use
+// a different scale (e.g., `FixedAmount<8>` for crypto, `FixedAmount<0>` for JPY).
+// `Amount` is the 4 digit scale the engine uses for balances.
+
+// INVARIANT 1: Amount * MAX_AMOUNT_DECIMAL_SHIFT <= i64::MAX.
+// INVARIANT 2: Amount has <= DECIMALS (4 for `Amount`) digits after the decimal.
+//
+// These invariants are enforced via Amount::new. In a real system, these invariants
+// should probably only be checked at the creation of this data (e.g., if it's user input).
+// Internal services can uphold this invariant, which should allow us to eliminate
+// the compute overhead entirely.
+
+#[derive(Clone, Copy, Debug, thiserror::Error, PartialEq)]
+pub enum AmountParseError {
+    Overflow(f64),
+    TooPrecise(f64),
+    // Only returned when parsing text that isn't a decimal number at all
+    Malformed,
+}
+
+impl AmountParseError {
+    pub fn to_deserializer_error<E>(&self, max_digits_after_decimal: u32) -> E
+    where
+        E: serde::de::Error,
+    {
+        let (amount, msg) = match *self {
+            AmountParseError::Overflow(amount) => (
+                amount,
+                "amount that will not overflow u64 after shift".to_owned(),
+            ),
+            AmountParseError::TooPrecise(amount) => (
+                amount,
+                format!("only {max_digits_after_decimal} digits after decimal"),
+            ),
+            AmountParseError::Malformed => return E::custom("malformed decimal amount"),
+        };
+        E::invalid_value(serde::de::Unexpected::Float(amount), &msg.as_str())
+    }
+}
+
+impl Display for AmountParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AmountParseError::Overflow(amount) => f.write_fmt(format_args!("Overflow({amount})")),
+            AmountParseError::TooPrecise(amount) => {
+                f.write_fmt(format_args!("TooPrecise({amount})"))
+            }
+            AmountParseError::Malformed => f.write_str("Malformed"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd)]
+pub struct FixedAmount<const DECIMALS: u32>(i64);
+
+// The only scale balances and transactions are kept in. Other scales can be parsed and
+// `rescale`d into it, but the engine doesn't carry them.
+pub type Amount = FixedAmount<4>;
+
+impl<const DECIMALS: u32> FixedAmount<DECIMALS> {
+    pub const MAX: Self = FixedAmount(i64::MAX);
+    pub const ZERO: Self = FixedAmount(0);
+    pub const MAX_DIGITS_AFTER_DECIMAL: u32 = DECIMALS;
+
+    // Fails to compile for scales where even 1.0 wouldn't fit in an i64
+    const UNITS_PER_WHOLE: i64 = {
+        assert!(
+            DECIMALS <= 18,
+            "at most 18 digits after the decimal fit in i64"
+        );
+        10i64.pow(DECIMALS)
+    };
+    const MAX_AMOUNT_DECIMAL_SHIFT: f64 = Self::UNITS_PER_WHOLE as f64;
+    // `i64::MAX as f64` rounds up to 2^63, so `MAX_F64` itself is already out of range,
+    // while `MIN_F64` (-2^63) is the smallest amount that still fits
+    const MAX_F64: f64 = (i64::MAX as f64) / Self::MAX_AMOUNT_DECIMAL_SHIFT;
+    const MIN_F64: f64 = (i64::MIN as f64) / Self::MAX_AMOUNT_DECIMAL_SHIFT;
+
+    pub fn new(amount: f64) -> Result<Self, AmountParseError> {
+        if amount >= Self::MAX_F64 || amount < Self::MIN_F64 {
+            return Err(AmountParseError::Overflow(amount));
+        }
+
+        // The shift itself can introduce error in the last couple of bits, which is
+        // larger than a fixed tolerance for big amounts at high scales (e.g., 8 digits).
+        // It can also round an amount just below the bound up to 2^63.
+        let amount_shifted = amount * Self::MAX_AMOUNT_DECIMAL_SHIFT;
+        let amount_rounded = amount_shifted.round();
+        if amount_rounded >= i64::MAX as f64 {
+            return Err(AmountParseError::Overflow(amount));
+        }
+        let tolerance = f64::max(0.0001, amount_shifted.abs() * f64::EPSILON * 4.0);
+        if (amount_rounded - amount_shifted).abs() > tolerance {
+            return Err(AmountParseError::TooPrecise(amount));
+        }
+
+        Ok(FixedAmount(amount_rounded as i64))
+    }
+
+    // Exact decimal parsing (no f64 round trip), e.g., "-12.3456". Trailing zeros past
+    // the scale are fine; any other extra digit is `TooPrecise`.
+    pub fn parse_decimal(text: &str) -> Result<Self, AmountParseError> {
+        let text = text.trim();
+        let approx = || text.parse::<f64>().unwrap_or(f64::NAN);
+        let (negative, digits) = match text.as_bytes() {
+            [b'-', rest @ ..] => (true, rest),
+            [b'+', rest @ ..] => (false, rest),
+            rest => (false, rest),
+        };
+
+        let (whole, fraction) = match digits.iter().position(|&b| b == b'.') {
+            Some(dot) => (&digits[..dot], &digits[dot + 1..]),
+            None => (digits, &[][..]),
+        };
+        if (whole.is_empty() && fraction.is_empty())
+            || !whole.iter().chain(fraction).all(u8::is_ascii_digit)
+        {
+            return Err(AmountParseError::Malformed);
+        }
+
+        let (kept, dropped) = fraction.split_at(fraction.len().min(DECIMALS as usize));
+        if dropped.iter().any(|&b| b != b'0') {
+            return Err(AmountParseError::TooPrecise(approx()));
+        }
+
+        let mut units: i64 = 0;
+        for &digit in whole
+            .iter()
+            .chain(kept)
+            .chain(std::iter::repeat_n(&b'0', DECIMALS as usize - kept.len()))
+        {
+            units = units
+                .checked_mul(10)
+                .and_then(|units| units.checked_add(i64::from(digit - b'0')))
+                .ok_or_else(|| AmountParseError::Overflow(approx()))?;
+        }
+
+        Ok(FixedAmount(if negative { -units } else { units }))
+    }
+
+    pub const fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    // Number of 10^-DECIMALS units, e.g., 12.3456 => 123456 for `Amount`
+    pub const fn units(&self) -> i64 {
+        self.0
+    }
+
+    // Converts to another scale, or `None` if digits would be lost or the result overflows
+    pub fn rescale<const TO: u32>(self) -> Option<FixedAmount<TO>> {
+        let units = if TO >= DECIMALS {
+            self.0.checked_mul(10i64.checked_pow(TO - DECIMALS)?)?
+        } else {
+            let divisor = 10i64.pow(DECIMALS - TO);
+            if self.0 % divisor != 0 {
+                return None;
+            }
+            self.0 / divisor
+        };
+        Some(FixedAmount(units))
+    }
+
+    // Rounds to `decimals` digits after the decimal per `rounding`, e.g., to a currency's
+    // minor units. Scales at or past `DECIMALS` are a no-op. `None` if rounding away from
+    // zero overflows.
+    pub fn round_to(self, decimals: u32, rounding: RoundingMode) -> Option<Self> {
+        let Some(digits) = DECIMALS.checked_sub(decimals) else {
+            return Some(self);
+        };
+        let divisor = 10i128.pow(digits);
+        let rounded = rounding.div(i128::from(self.0), divisor) * divisor;
+        i64::try_from(rounded).ok().map(FixedAmount)
+    }
+
+    // Multiplies by a fixed-point rate without ever going through f64. The intermediate
+    // product is computed in i128 so it can't overflow; only the final narrowing can.
+    pub fn checked_mul_rate(self, rate: Rate, rounding: RoundingMode) -> Option<Self> {
+        let product = i128::from(self.0) * i128::from(rate.0);
+        let scaled = rounding.div(product, i128::from(Rate::SCALE));
+        i64::try_from(scaled).ok().map(FixedAmount)
+    }
+}
+
+impl<const DECIMALS: u32> FromStr for FixedAmount<DECIMALS> {
+    type Err = anyhow::Error;
+
+    // e.g., amounts passed on the command line. Obeys the same invariants as the CSV.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::parse_decimal(s)?)
+    }
+}
+
+impl<const DECIMALS: u32> Display for FixedAmount<DECIMALS> {
+    // Exact fixed-point rendering with all DECIMALS digits, e.g., 1.5000
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_fixed_point(f, self.0 < 0, self.0.unsigned_abs().into(), DECIMALS)
+    }
+}
+
+fn write_fixed_point(
+    f: &mut std::fmt::Formatter<'_>,
+    negative: bool,
+    units: u128,
+    decimals: u32,
+) -> std::fmt::Result {
+    let sign = if negative { "-" } else { "" };
+    if decimals == 0 {
+        return f.write_fmt(format_args!("{sign}{units}"));
+    }
+
+    let shift = 10u128.pow(decimals);
+    f.write_fmt(format_args!(
+        "{sign}{}.{:0width$}",
+        units / shift,
+        units % shift,
+        width = decimals as usize
+    ))
+}
+
+impl<const DECIMALS: u32> From<i64> for FixedAmount<DECIMALS> {
+    fn from(amount: i64) -> Self {
+        FixedAmount(amount)
+    }
+}
+
+impl<const DECIMALS: u32> Add for FixedAmount<DECIMALS> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        // ignore overflow
+        FixedAmount(self.0 + rhs.0)
+    }
+}
+
+impl<const DECIMALS: u32> AddAssign for FixedAmount<DECIMALS> {
+    fn add_assign(&mut self, rhs: Self) {
+        self.0 = self.0 + rhs.0;
+    }
+}
+
+impl<const DECIMALS: u32> Sub for FixedAmount<DECIMALS> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        FixedAmount(self.0 - rhs.0)
+    }
+}
+
+impl<const DECIMALS: u32> SubAssign for FixedAmount<DECIMALS> {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl<const DECIMALS: u32> Neg for FixedAmount<DECIMALS> {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        FixedAmount(-self.0)
+    }
+}
+
+// Same scale as `FixedAmount`, but backed by an i128 for aggregates that can exceed
+// a single balance's range (e.g., system-wide totals across every client). Unlike
+// `FixedAmount`, arithmetic never silently wraps: overflowing i128 panics, and going
+// back to `FixedAmount` is fallible.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WideFixedAmount<const DECIMALS: u32>(i128);
+
+pub type WideAmount = WideFixedAmount<4>;
+
+#[derive(Clone, Copy, Debug, thiserror::Error, PartialEq, Eq)]
+#[error("{0} units don't fit in a 64-bit amount")]
+pub struct AmountOverflowError(pub i128);
+
+impl<const DECIMALS: u32> WideFixedAmount<DECIMALS> {
+    pub const ZERO: Self = WideFixedAmount(0);
+
+    pub const fn units(&self) -> i128 {
+        self.0
+    }
+
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(WideFixedAmount)
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(WideFixedAmount)
+    }
+}
+
+impl<const DECIMALS: u32> From<FixedAmount<DECIMALS>> for WideFixedAmount<DECIMALS> {
+    fn from(amount: FixedAmount<DECIMALS>) -> Self {
+        WideFixedAmount(amount.0.into())
+    }
+}
+
+impl<const DECIMALS: u32> From<i128> for WideFixedAmount<DECIMALS> {
+    fn from(amount: i128) -> Self {
+        WideFixedAmount(amount)
+    }
+}
+
+impl<const DECIMALS: u32> TryFrom<WideFixedAmount<DECIMALS>> for FixedAmount<DECIMALS> {
+    type Error = AmountOverflowError;
+
+    fn try_from(amount: WideFixedAmount<DECIMALS>) -> Result<Self, Self::Error> {
+        i64::try_from(amount.0)
+            .map(FixedAmount)
+            .map_err(|_| AmountOverflowError(amount.0))
+    }
+}
+
+impl<const DECIMALS: u32> Display for WideFixedAmount<DECIMALS> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_fixed_point(f, self.0 < 0, self.0.unsigned_abs(), DECIMALS)
+    }
+}
+
+impl<const DECIMALS: u32> Add for WideFixedAmount<DECIMALS> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        self.checked_add(rhs).expect("WideAmount overflowed i128")
+    }
+}
+
+impl<const DECIMALS: u32> AddAssign for WideFixedAmount<DECIMALS> {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl<const DECIMALS: u32> AddAssign<FixedAmount<DECIMALS>> for WideFixedAmount<DECIMALS> {
+    fn add_assign(&mut self, rhs: FixedAmount<DECIMALS>) {
+        *self = *self + rhs.into();
+    }
+}
+
+impl<const DECIMALS: u32> Sub for WideFixedAmount<DECIMALS> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.checked_sub(rhs).expect("WideAmount overflowed i128")
+    }
+}
+
+impl<const DECIMALS: u32> SubAssign for WideFixedAmount<DECIMALS> {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl<const DECIMALS: u32> std::iter::Sum<FixedAmount<DECIMALS>> for WideFixedAmount<DECIMALS> {
+    fn sum<I: Iterator<Item = FixedAmount<DECIMALS>>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, |total, amount| total + amount.into())
+    }
+}
+
+impl<const DECIMALS: u32> std::iter::Sum for WideFixedAmount<DECIMALS> {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, Add::add)
+    }
+}
+
+impl<const DECIMALS: u32> Serialize for WideFixedAmount<DECIMALS> {
+    // Wide values routinely exceed what f64 represents exactly, so always use the
+    // exact decimal text.
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+// Rounding applied whenever fixed-point math has to drop digits (e.g., percentage fees).
+// `HalfEven` (banker's rounding) is the default since it doesn't bias totals in
+// either direction over many transactions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RoundingMode {
+    #[default]
+    HalfEven,
+    HalfUp,
+    Down,
+    Up,
+}
+
+impl RoundingMode {
+    // Divides `value` by a positive `divisor`, rounding the quotient per `self`.
+    // `Down`/`Up` round toward/away from zero; the `Half*` modes round to nearest.
+    fn div(self, value: i128, divisor: i128) -> i128 {
+        let quotient = value / divisor;
+        let remainder = value % divisor;
+        if remainder == 0 {
+            return quotient;
+        }
+
+        let away_from_zero = quotient + value.signum();
+        let twice_remainder = remainder.abs() * 2;
+        let round_away = match self {
+            RoundingMode::Down => false,
+            RoundingMode::Up => true,
+            RoundingMode::HalfUp => twice_remainder >= divisor,
+            RoundingMode::HalfEven => {
+                twice_remainder > divisor || (twice_remainder == divisor && quotient % 2 != 0)
+            }
+        };
+
+        if round_away { away_from_zero } else { quotient }
+    }
+}
+
+impl FromStr for RoundingMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "half-even" | "halfeven" | "bankers" => Ok(RoundingMode::HalfEven),
+            "half-up" | "halfup" => Ok(RoundingMode::HalfUp),
+            "down" | "truncate" => Ok(RoundingMode::Down),
+            "up" => Ok(RoundingMode::Up),
+            other => Err(anyhow::anyhow!("Unknown rounding mode: {other}")),
+        }
+    }
+}
+
+// Fixed-point multiplier (e.g., fee percentages) with 8 digits after the decimal.
+// Same motivation as `Amount`: rates are parsed once and all math stays in integers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd)]
+pub struct Rate(i64);
+
+impl Rate {
+    pub const MAX_DIGITS_AFTER_DECIMAL: u32 = 8;
+    pub const ZERO: Self = Rate(0);
+    pub const ONE: Self = Rate(Self::SCALE);
+
+    const SCALE: i64 = 10i64.pow(Self::MAX_DIGITS_AFTER_DECIMAL);
+    const MAX_F64: f64 = (i64::MAX as f64) / (Self::SCALE as f64);
+
+    pub fn new(rate: f64) -> Result<Rate, AmountParseError> {
+        if rate.abs() > Self::MAX_F64 {
+            return Err(AmountParseError::Overflow(rate));
+        }
+
+        let rate_shifted = rate * Self::SCALE as f64;
+        let rate_rounded = rate_shifted.round();
+        if (rate_rounded - rate_shifted).abs() > 0.0001 {
+            return Err(AmountParseError::TooPrecise(rate));
+        }
+
+        Ok(Rate(rate_rounded as i64))
+    }
+
+    pub const fn from_scaled(rate: i64) -> Self {
+        Rate(rate)
+    }
+
+    pub const fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl FromStr for Rate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Rate::new(s.trim().parse::<f64>()?)?)
+    }
+}
+
+impl<const DECIMALS: u32> Serialize for FixedAmount<DECIMALS> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match DECIMALS {
+            // Whole units only (e.g., JPY) => no decimal point at all
+            0 => serializer.serialize_i64(self.0),
+            // Every 4 digit value prints exactly as a float (and always has, so the
+            // output stays the same).
+            // Example: 123456 => (123456.0000 / 10000.0) => 12.3456
+            1..=4 => {
+                let csv_float = (self.0 as f64).round();
+                let csv_float_shifted = csv_float / Self::MAX_AMOUNT_DECIMAL_SHIFT;
+                serializer.serialize_f64(csv_float_shifted)
+            }
+            // Floats would print small amounts in scientific notation (1e-8) and lose
+            // digits on large ones, so use the exact decimal text instead.
+            _ => serializer.collect_str(self),
+        }
+    }
+}
+
+impl<'de, const DECIMALS: u32> Deserialize<'de> for FixedAmount<DECIMALS> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct AmountVisitor<const DECIMALS: u32>;
+
+        impl<const DECIMALS: u32> serde::de::Visitor<'_> for AmountVisitor<DECIMALS> {
+            type Value = FixedAmount<DECIMALS>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_fmt(format_args!(
+                    "a number with at most {DECIMALS} digits after decimal"
+                ))
+            }
+
+            // Example: (12.3456 * 10000.0).round() => 123456.0000 => 123456
+            fn visit_f64<E>(self, amount: f64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                FixedAmount::new(amount).map_err(|err| err.to_deserializer_error(DECIMALS))
+            }
+
+            fn visit_i64<E>(self, amount: i64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                amount
+                    .checked_mul(FixedAmount::<DECIMALS>::UNITS_PER_WHOLE)
+                    .map(FixedAmount)
+                    .ok_or_else(|| {
+                        AmountParseError::Overflow(amount as f64).to_deserializer_error(DECIMALS)
+                    })
+            }
+
+            fn visit_u64<E>(self, amount: u64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                let amount = i64::try_from(amount).map_err(|_| {
+                    AmountParseError::Overflow(amount as f64).to_deserializer_error(DECIMALS)
+                })?;
+                self.visit_i64(amount)
+            }
+
+            // e.g., high scale amounts serialized as text
+            fn visit_str<E>(self, amount: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                FixedAmount::parse_decimal(amount)
+                    .map_err(|err| err.to_deserializer_error(DECIMALS))
+            }
+        }
+
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let csv_float = f64::deserialize(deserializer)?;
+        Rate::new(csv_float).map_err(|_| {
+            serde::de::Error::invalid_value(
+                serde::de::Unexpected::Float(csv_float),
+                &"rate with at most 8 digits after decimal",
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod amount_tests {
+    use googletest::prelude::*;
+
+    use crate::parse::{Amount, AmountParseError, FixedAmount, RoundingMode};
+
+    #[gtest]
+    pub fn new_amount_rejects_overflow_before_shift() {
+        let overflow_before_shift = i64::MAX as f64;
+        expect_that!(
+            Amount::new(overflow_before_shift),
+            err(eq(AmountParseError::Overflow(overflow_before_shift)))
+        );
+    }
+
+    #[gtest]
+    pub fn new_amount_rejects_overflow_after_shift() {
+        // Saturated to i64::MAX when the bound was computed from u64::MAX
+        let overflow_after_shift = i64::MAX as f64 / Amount::MAX_AMOUNT_DECIMAL_SHIFT;
+        expect_that!(
+            Amount::new(overflow_after_shift),
+            err(eq(AmountParseError::Overflow(overflow_after_shift)))
+        );
+        let underflow_after_shift = -1.0e15;
+        expect_that!(
+            Amount::new(underflow_after_shift),
+            err(eq(AmountParseError::Overflow(underflow_after_shift)))
+        );
+    }
+
+    #[gtest]
+    pub fn new_amount_rejects_if_still_float_after_shift() {
+        expect_that!(
+            Amount::new(123.45678),
+            err(eq(AmountParseError::TooPrecise(123.45678)))
+        );
+    }
+
+    #[gtest]
+    pub fn new_amount_accepts_zero() {
+        expect_that!(Amount::new(0.0), ok(eq(0.into())));
+    }
+
+    #[gtest]
+    pub fn new_amount_accepts_amounts_within_i64_after_shift() {
+        expect_that!(
+            Amount::new(9.0e14),
+            ok(eq(9_000_000_000_000_000_000.into()))
+        );
+        expect_that!(
+            Amount::new(i64::MIN as f64 / Amount::MAX_AMOUNT_DECIMAL_SHIFT),
+            ok(eq(i64::MIN.into()))
+        );
+    }
+
+    #[gtest]
+    pub fn new_amount_applies_decimal_shift() {
+        expect_that!(Amount::new(123.4567), ok(eq(1234567.into())));
+        expect_that!(Amount::new(562.844), ok(eq(5628440.into())));
+    }
+
+    #[gtest]
+    pub fn new_amount_checks_precision_per_scale() {
+        expect_that!(FixedAmount::<8>::new(0.12345678), ok(eq(12345678.into())));
+        expect_that!(
+            FixedAmount::<8>::new(0.123456789),
+            err(eq(AmountParseError::TooPrecise(0.123456789)))
+        );
+        expect_that!(FixedAmount::<0>::new(1500.0), ok(eq(1500.into())));
+        expect_that!(
+            FixedAmount::<0>::new(1500.5),
+            err(eq(AmountParseError::TooPrecise(1500.5)))
+        );
+    }
+
+    #[gtest]
+    pub fn new_amount_checks_overflow_per_scale() {
+        let overflow_after_shift = i64::MAX as f64 / 1e8;
+        expect_that!(
+            FixedAmount::<8>::new(overflow_after_shift),
+            err(eq(AmountParseError::Overflow(overflow_after_shift)))
+        );
+        expect_that!(
+            FixedAmount::<8>::new(-overflow_after_shift - 1.0),
+            err(eq(AmountParseError::Overflow(-overflow_after_shift - 1.0)))
+        );
+        expect_that!(
+            FixedAmount::<0>::new(overflow_after_shift.round()),
+            ok(anything())
+        );
+        expect_that!(
+            FixedAmount::<0>::new(i64::MAX as f64),
+            err(eq(AmountParseError::Overflow(i64::MAX as f64)))
+        );
+    }
+
+    #[gtest]
+    pub fn parse_decimal_is_exact() {
+        expect_that!(
+            FixedAmount::<8>::parse_decimal("92233720368.54775807"),
+            ok(eq(i64::MAX.into()))
+        );
+        expect_that!(
+            FixedAmount::<8>::parse_decimal("92233720368.54775808"),
+            err(matches_pattern!(AmountParseError::Overflow(_)))
+        );
+        expect_that!(Amount::parse_decimal("-1.50000"), ok(eq((-15000).into())));
+        expect_that!(
+            Amount::parse_decimal("1.00001"),
+            err(matches_pattern!(AmountParseError::TooPrecise(_)))
+        );
+        expect_that!(
+            Amount::parse_decimal("1.2.3"),
+            err(eq(AmountParseError::Malformed))
+        );
+        expect_that!(
+            Amount::parse_decimal("."),
+            err(eq(AmountParseError::Malformed))
+        );
+    }
+
+    #[gtest]
+    pub fn rescale_rejects_lost_digits() {
+        let amount = Amount::new(1.5).unwrap();
+        expect_that!(
+            amount.rescale::<8>(),
+            some(eq(FixedAmount::<8>::from(150_000_000)))
+        );
+        expect_that!(amount.rescale::<0>(), none());
+        expect_that!(
+            Amount::new(3.0).unwrap().rescale::<0>(),
+            some(eq(FixedAmount::<0>::from(3)))
+        );
+        expect_that!(Amount::MAX.rescale::<8>(), none());
+    }
+
+    #[gtest]
+    pub fn round_to_drops_digits_past_the_scale() {
+        let amount = Amount::new(1.2345).unwrap();
+        expect_that!(
+            amount.round_to(2, RoundingMode::HalfEven),
+            some(eq(Amount::new(1.23).unwrap()))
+        );
+        expect_that!(
+            amount.round_to(2, RoundingMode::Up),
+            some(eq(Amount::new(1.24).unwrap()))
+        );
+        expect_that!(
+            (-amount).round_to(0, RoundingMode::HalfUp),
+            some(eq(Amount::new(-1.0).unwrap()))
+        );
+        expect_that!(amount.round_to(4, RoundingMode::Up), some(eq(amount)));
+        expect_that!(Amount::MAX.round_to(0, RoundingMode::Up), none());
+    }
+
+    #[gtest]
+    pub fn display_uses_scale_digits() {
+        expect_that!(FixedAmount::<8>::from(1).to_string(), eq("0.00000001"));
+        expect_that!(FixedAmount::<0>::from(-1500).to_string(), eq("-1500"));
+        expect_that!(Amount::from(-15000).to_string(), eq("-1.5000"));
+    }
+}
+
+#[cfg(test)]
+mod wide_amount_tests {
+    use googletest::prelude::*;
+
+    use crate::parse::{Amount, AmountOverflowError, WideAmount};
+
+    #[gtest]
+    pub fn sum_exceeds_amount_range() {
+        let total: WideAmount = [Amount::MAX, Amount::MAX, Amount::from(2)]
+            .into_iter()
+            .sum();
+
+        expect_that!(total.units(), eq(i128::from(i64::MAX) * 2 + 2));
+        expect_that!(
+            Amount::try_from(total),
+            err(eq(AmountOverflowError(i128::from(i64::MAX) * 2 + 2)))
+        );
+        expect_that!(total.to_string(), eq("1844674407370955.1616"));
+    }
+
+    #[gtest]
+    pub fn round_trips_amounts_in_range() {
+        let amount = Amount::new(-12.3456).unwrap();
+        expect_that!(Amount::try_from(WideAmount::from(amount)), ok(eq(amount)));
+        expect_that!(
+            WideAmount::from(amount).to_string(),
+            eq(&amount.to_string())
+        );
+    }
+
+    #[gtest]
+    pub fn checked_add_detects_overflow() {
+        expect_that!(
+            WideAmount::from(i128::MAX).checked_add(WideAmount::from(1)),
+            none()
+        );
+    }
+}
+
+#[cfg(test)]
+mod rate_tests {
+    use googletest::prelude::*;
+
+    use crate::parse::{Amount, AmountParseError, Rate, RoundingMode};
+
+    #[gtest]
+    pub fn new_rate_rejects_if_still_float_after_shift() {
+        expect_that!(
+            Rate::new(0.123456789),
+            err(eq(AmountParseError::TooPrecise(0.123456789)))
+        );
+    }
+
+    #[gtest]
+    pub fn mul_rate_is_exact_when_no_digits_are_dropped() {
+        expect_that!(
+            Amount::new(200.0)
+                .unwrap()
+                .checked_mul_rate(Rate::new(0.015).unwrap(), RoundingMode::HalfEven),
+            some(eq(Amount::new(3.0).unwrap()))
+        );
+    }
+
+    #[gtest]
+    pub fn mul_rate_applies_rounding_mode() {
+        // 0.0005 * 0.5 = 0.00025 => halfway between 0.0002 and 0.0003
+        let amount = Amount::new(0.0005).unwrap();
+        let rate = Rate::new(0.5).unwrap();
+
+        expect_that!(
+            amount.checked_mul_rate(rate, RoundingMode::HalfEven),
+            some(eq(Amount::from(2)))
+        );
+        expect_that!(
+            amount.checked_mul_rate(rate, RoundingMode::HalfUp),
+            some(eq(Amount::from(3)))
+        );
+        expect_that!(
+            amount.checked_mul_rate(rate, RoundingMode::Down),
+            some(eq(Amount::from(2)))
+        );
+        expect_that!(
+            Amount::from(1).checked_mul_rate(Rate::new(0.1).unwrap(), RoundingMode::Up),
+            some(eq(Amount::from(1)))
+        );
+    }
+
+    #[gtest]
+    pub fn mul_rate_rounds_negative_amounts_symmetrically() {
+        expect_that!(
+            Amount::from(-5).checked_mul_rate(Rate::new(0.5).unwrap(), RoundingMode::HalfUp),
+            some(eq(Amount::from(-3)))
+        );
+        expect_that!(
+            Amount::from(-5).checked_mul_rate(Rate::new(0.5).unwrap(), RoundingMode::Down),
+            some(eq(Amount::from(-2)))
+        );
+    }
+
+    #[gtest]
+    pub fn mul_rate_detects_overflow() {
+        expect_that!(
+            Amount::MAX.checked_mul_rate(Rate::new(2.0).unwrap(), RoundingMode::HalfEven),
+            none()
+        );
+    }
+}
+
+#[cfg(test)]
+mod serde_tests {
+    use anyhow::Result;
+    use serde_test::{Token, assert_de_tokens_error, assert_tokens};
+
+    use crate::parse::*;
+    use crate::*;
+
+    #[test]
+    pub fn serialize_and_deserialize_amount_transactions() -> Result<()> {
+        assert_tokens(
+            &Transaction::new(
+                1,
+                2,
+                TransactionType::Withdrawal,
+                Some(Amount::new(123.4567)?),
+            ),
+            &[
+                Token::Struct {
+                    name: "Transaction",
+                    len: 4,
+                },
+                Token::Str("tx"),
+                Token::U32(1),
+                Token::Str("client"),
+                Token::U16(2),
+                Token::Str("type"),
+                Token::UnitVariant {
+                    name: "TransactionType",
+                    variant: "withdrawal",
+                },
+                Token::Str("amount"),
+                Token::Some,
+                Token::F64(123.4567),
+                Token::StructEnd,
+            ],
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn serialize_and_deserialize_non_amount_transactions() {
+        assert_tokens(
+            &Transaction::new(1, 2, TransactionType::Resolve, None),
+            &[
+                Token::Struct {
+                    name: "Transaction",
+                    len: 4,
+                },
+                Token::Str("tx"),
+                Token::U32(1),
+                Token::Str("client"),
+                Token::U16(2),
+                Token::Str("type"),
+                Token::UnitVariant {
+                    name: "TransactionType",
+                    variant: "resolve",
+                },
+                Token::Str("amount"),
+                Token::None,
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    pub fn can_not_serialize_invalid_amount() {
+        assert_de_tokens_error::<Transaction>(
+            &[
+                Token::Struct {
+                    name: "Transaction",
+                    len: 4,
+                },
+                Token::Str("tx"),
+                Token::U32(1),
+                Token::Str("client"),
+                Token::U16(2),
+                Token::Str("type"),
+                Token::UnitVariant {
+                    name: "TransactionType",
+                    variant: "withdrawal",
+                },
+                Token::Str("amount"),
+                Token::Some,
+                Token::F64(123.45678),
+                Token::StructEnd,
+            ],
+            &format!(
+                "invalid value: floating point `123.45678`, expected only {} digits after decimal",
+                Amount::MAX_DIGITS_AFTER_DECIMAL
+            ),
+        );
+    }
+
+    #[test]
+    pub fn serialize_amount_with_scale_digits() {
+        assert_tokens(&FixedAmount::<0>::from(1500), &[Token::I64(1500)]);
+        // Floats would turn this into 1e-8
+        assert_tokens(&FixedAmount::<8>::from(1), &[Token::Str("0.00000001")]);
+    }
+
+    #[test]
+    pub fn can_not_deserialize_amount_beyond_scale() {
+        assert_de_tokens_error::<FixedAmount<0>>(
+            &[Token::F64(1.5)],
+            "invalid value: floating point `1.5`, expected only 0 digits after decimal",
+        );
+        assert_de_tokens_error::<FixedAmount<8>>(
+            &[Token::Str("0.123456789")],
+            "invalid value: floating point `0.123456789`, expected only 8 digits after decimal",
+        );
+    }
+}
@@ -0,0 +1,525 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use log::warn;
+#[cfg(not(feature = "stream"))]
+use serde::{Deserialize, Serialize};
+
+use crate::Transaction;
+#[cfg(not(feature = "stream"))]
+use crate::engine::{Engine, EngineConfig, PaymentEngine, checkpoint::EngineState};
+
+// Position of a message in a queue, counted from 0 like Kafka's offsets
+pub type Offset = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub offset: Offset,
+    pub transaction: Transaction,
+}
+
+// Consumer of a queue of transactions, modelled on a Kafka consumer of a single
+// partition: messages are polled in offset order, and the committed offset is where a
+// consumer (group) picks up after a restart.
+pub trait QueueConsumer {
+    // Up to `max` messages from the current position. Empty once caught up.
+    fn poll(&mut self, max: usize) -> anyhow::Result<Vec<Message>>;
+
+    // Records that every message before `offset` has been consumed
+    fn commit(&mut self, offset: Offset) -> anyhow::Result<()>;
+
+    // 0 if nothing was committed yet
+    fn committed(&self) -> Offset;
+
+    // Moves the position the next poll starts from
+    fn seek(&mut self, offset: Offset) -> anyhow::Result<()>;
+}
+
+// Local stand-in for a Kafka topic partition: a directory of append-only segment files,
+// each holding up to `segment_size` transactions as JSON Lines and named after the
+// offset of its first message (`00000000000000000000.jsonl`, ...). Consumer groups
+// commit their offsets to `<group>.offset` files in the same directory.
+#[derive(Clone, Debug)]
+pub struct SegmentLog {
+    dir: PathBuf,
+    segment_size: u64,
+}
+
+impl SegmentLog {
+    const DEFAULT_SEGMENT_SIZE: u64 = 100_000;
+    const SEGMENT_EXTENSION: &str = "jsonl";
+
+    // Creates the directory if needed
+    pub fn open(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Can't create queue directory {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            segment_size: Self::DEFAULT_SEGMENT_SIZE,
+        })
+    }
+
+    // Only affects segments the producer starts from now on
+    pub fn with_segment_size(mut self, segment_size: u64) -> Self {
+        self.segment_size = segment_size.max(1);
+        self
+    }
+
+    pub fn producer(&self) -> anyhow::Result<SegmentProducer> {
+        SegmentProducer::open(self.clone())
+    }
+
+    pub fn consumer(&self, group: &str) -> anyhow::Result<SegmentConsumer> {
+        SegmentConsumer::open(self.clone(), group)
+    }
+
+    // Offsets of the segments' first messages, in order
+    fn segments(&self) -> anyhow::Result<Vec<Offset>> {
+        let mut segments = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|e| e == Self::SEGMENT_EXTENSION)
+                && let Some(base) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse().ok())
+            {
+                segments.push(base);
+            }
+        }
+        segments.sort_unstable();
+        Ok(segments)
+    }
+
+    fn segment_path(&self, base: Offset) -> PathBuf {
+        self.dir
+            .join(format!("{base:020}.{}", Self::SEGMENT_EXTENSION))
+    }
+}
+
+// Appends transactions to the end of a `SegmentLog`
+pub struct SegmentProducer {
+    log: SegmentLog,
+    // Offset of the current segment's first message, and of the next message
+    segment_base: Offset,
+    next_offset: Offset,
+    writer: Option<BufWriter<File>>,
+}
+
+impl SegmentProducer {
+    fn open(log: SegmentLog) -> anyhow::Result<Self> {
+        let (segment_base, next_offset) = match log.segments()?.last() {
+            Some(&base) => (base, base + Self::recover(&log.segment_path(base))?),
+            None => (0, 0),
+        };
+        Ok(Self {
+            log,
+            segment_base,
+            next_offset,
+            writer: None,
+        })
+    }
+
+    // Counts the segment's messages, dropping a partially written last line (e.g., the
+    // producer crashed mid-write) so the next message starts on a line of its own
+    fn recover(path: &Path) -> anyhow::Result<u64> {
+        let bytes = std::fs::read(path)?;
+        let complete = bytes.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        if complete < bytes.len() {
+            warn!(
+                "Dropping a partially written message from {}",
+                path.display()
+            );
+            OpenOptions::new()
+                .write(true)
+                .open(path)?
+                .set_len(complete as u64)?;
+        }
+        Ok(bytes[..complete].iter().filter(|&&b| b == b'\n').count() as u64)
+    }
+
+    pub fn send(&mut self, transaction: &Transaction) -> anyhow::Result<Offset> {
+        if self.next_offset - self.segment_base >= self.log.segment_size {
+            self.flush()?;
+            self.writer = None;
+            self.segment_base = self.next_offset;
+        }
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => self.writer.insert(BufWriter::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.log.segment_path(self.segment_base))?,
+            )),
+        };
+
+        serde_json::to_writer(&mut *writer, transaction)?;
+        writer.write_all(b"\n")?;
+        self.next_offset += 1;
+        Ok(self.next_offset - 1)
+    }
+
+    // Makes every message sent so far durable (and visible to consumers)
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+            writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+// Reads a `SegmentLog` as a consumer group, starting from the group's committed offset.
+// Messages the producer is still writing (a line without its newline) aren't handed
+// out until they're complete.
+pub struct SegmentConsumer {
+    log: SegmentLog,
+    offset_path: PathBuf,
+    committed: Offset,
+    position: Offset,
+    // Segment `position` is in, at the start of `position`'s line
+    reader: Option<BufReader<File>>,
+}
+
+impl SegmentConsumer {
+    fn open(log: SegmentLog, group: &str) -> anyhow::Result<Self> {
+        let offset_path = log.dir.join(format!("{group}.offset"));
+        let committed = match std::fs::read_to_string(&offset_path) {
+            Ok(offset) => offset.trim().parse().with_context(|| {
+                format!("Invalid committed offset in {}", offset_path.display())
+            })?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            log,
+            offset_path,
+            committed,
+            position: committed,
+            reader: None,
+        })
+    }
+
+    // `None` if no message has been written at `position` yet
+    fn open_segment(&self) -> anyhow::Result<Option<BufReader<File>>> {
+        let segments = self.log.segments()?;
+        let Some(&base) = segments.iter().rev().find(|&&base| base <= self.position) else {
+            return Ok(None);
+        };
+
+        let mut reader = BufReader::new(File::open(self.log.segment_path(base))?);
+        let mut line = Vec::new();
+        for _ in base..self.position {
+            line.clear();
+            if !read_complete_line(&mut reader, &mut line)? {
+                return Ok(None);
+            }
+        }
+        Ok(Some(reader))
+    }
+}
+
+impl QueueConsumer for SegmentConsumer {
+    fn poll(&mut self, max: usize) -> anyhow::Result<Vec<Message>> {
+        let mut messages = Vec::new();
+        let mut line = Vec::new();
+        while messages.len() < max {
+            let reader = match &mut self.reader {
+                Some(reader) => reader,
+                None => match self.open_segment()? {
+                    Some(reader) => self.reader.insert(reader),
+                    None => break,
+                },
+            };
+
+            line.clear();
+            if !read_complete_line(reader, &mut line)? {
+                // The producer rolls over to a segment starting at the next offset
+                if self.log.segments()?.contains(&self.position) {
+                    self.reader = None;
+                    continue;
+                }
+                break;
+            }
+
+            let transaction = serde_json::from_slice(&line)
+                .with_context(|| format!("Invalid message at offset {}", self.position))?;
+            messages.push(Message {
+                offset: self.position,
+                transaction,
+            });
+            self.position += 1;
+        }
+        Ok(messages)
+    }
+
+    fn commit(&mut self, offset: Offset) -> anyhow::Result<()> {
+        write_atomically(&self.offset_path, offset.to_string().as_bytes())?;
+        self.committed = offset;
+        Ok(())
+    }
+
+    fn committed(&self) -> Offset {
+        self.committed
+    }
+
+    fn seek(&mut self, offset: Offset) -> anyhow::Result<()> {
+        self.position = offset;
+        self.reader = None;
+        Ok(())
+    }
+}
+
+// Reads the next line into `line`, newline included. At the end of the file, or if
+// the line isn't complete yet, leaves the reader where it was and returns false.
+fn read_complete_line(reader: &mut BufReader<File>, line: &mut Vec<u8>) -> anyhow::Result<bool> {
+    let read = reader.read_until(b'\n', line)?;
+    if read > 0 && line.last() == Some(&b'\n') {
+        return Ok(true);
+    }
+    if read > 0 {
+        reader.seek(SeekFrom::Current(-(read as i64)))?;
+    }
+    Ok(false)
+}
+
+// Readers see either the old or the new contents, even if we crash mid-write
+fn write_atomically(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    {
+        let mut file = File::create(&temp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+// The engine's state after applying every message before `offset`, and none after it
+#[cfg(not(feature = "stream"))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub offset: Offset,
+    pub state: EngineState,
+}
+
+#[cfg(not(feature = "stream"))]
+impl Checkpoint {
+    // `None` if nothing was checkpointed yet
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Option<Self>> {
+        let path = path.as_ref();
+        match File::open(path) {
+            Ok(file) => Ok(Some(
+                serde_json::from_reader(BufReader::new(file))
+                    .with_context(|| format!("Invalid checkpoint {}", path.display()))?,
+            )),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        write_atomically(path.as_ref(), &serde_json::to_vec(self)?)
+    }
+}
+
+// Restores the engine from the checkpoint at `path`, if any, and moves `consumer` to the
+// first message the checkpoint doesn't reflect. That's the checkpoint's offset rather
+// than the committed one, which lags behind if we crashed between checkpointing and
+// committing, so no message is ever applied twice.
+#[cfg(not(feature = "stream"))]
+pub fn resume(
+    config: EngineConfig,
+    consumer: &mut impl QueueConsumer,
+    path: impl AsRef<Path>,
+) -> anyhow::Result<Engine> {
+    let Some(checkpoint) = Checkpoint::load(path)? else {
+        if consumer.committed() > 0 {
+            warn!(
+                "No checkpoint; reprocessing from offset 0 instead of the committed offset {}",
+                consumer.committed()
+            );
+        }
+        consumer.seek(0)?;
+        return Ok(Engine::with_config(config));
+    };
+
+    log::info!(
+        "Resuming from offset {} with {} clients",
+        checkpoint.offset,
+        checkpoint.state.clients()
+    );
+    if checkpoint.offset < consumer.committed() {
+        anyhow::bail!(
+            "Checkpoint at offset {} is older than the committed offset {}",
+            checkpoint.offset,
+            consumer.committed()
+        );
+    }
+    consumer.seek(checkpoint.offset)?;
+    Ok(Engine::restore(config, checkpoint.state)?)
+}
+
+// Applies messages until `consumer` is caught up, checkpointing the engine to `path`
+// every `interval` messages and once caught up. An offset is only committed after the
+// checkpoint reflecting it is on disk. Returns how many messages were consumed.
+#[cfg(not(feature = "stream"))]
+pub fn consume(
+    engine: &mut Engine,
+    consumer: &mut impl QueueConsumer,
+    path: impl AsRef<Path>,
+    interval: usize,
+) -> anyhow::Result<u64> {
+    const MAX_POLL: usize = 1024;
+
+    let path = path.as_ref();
+    let interval = interval.max(1);
+    let mut consumed = 0;
+    let mut uncheckpointed = 0;
+    let mut next_offset = None;
+    loop {
+        let messages = consumer.poll(MAX_POLL.min(interval - uncheckpointed))?;
+        if messages.is_empty() {
+            break;
+        }
+        for message in messages {
+            engine.process(message.transaction)?;
+            next_offset = Some(message.offset + 1);
+            consumed += 1;
+            uncheckpointed += 1;
+        }
+
+        if let Some(offset) = next_offset.filter(|_| uncheckpointed == interval) {
+            checkpoint(engine, consumer, path, offset)?;
+            uncheckpointed = 0;
+        }
+    }
+
+    if let Some(offset) = next_offset.filter(|_| uncheckpointed > 0) {
+        checkpoint(engine, consumer, path, offset)?;
+    }
+    Ok(consumed)
+}
+
+#[cfg(not(feature = "stream"))]
+fn checkpoint(
+    engine: &Engine,
+    consumer: &mut impl QueueConsumer,
+    path: &Path,
+    offset: Offset,
+) -> anyhow::Result<()> {
+    Checkpoint {
+        offset,
+        state: engine.state()?,
+    }
+    .save(path)?;
+    consumer.commit(offset)
+}
+
+#[cfg(test)]
+mod segment_log_tests {
+    use googletest::prelude::*;
+
+    use super::*;
+    use crate::TransactionType;
+    use crate::parse::Amount;
+
+    fn temp_log(name: &str) -> SegmentLog {
+        let dir = std::env::temp_dir().join(format!(
+            "payment-engine-queue-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        SegmentLog::open(dir).unwrap()
+    }
+
+    fn deposit(tx: u32) -> Transaction {
+        Transaction::new(
+            tx,
+            1,
+            TransactionType::Deposit,
+            Some(Amount::new(1.5).unwrap()),
+        )
+    }
+
+    fn offsets(messages: &[Message]) -> Vec<Offset> {
+        messages.iter().map(|m| m.offset).collect()
+    }
+
+    #[gtest]
+    pub fn consumes_across_segments_in_offset_order() {
+        let log = temp_log("segments").with_segment_size(2);
+        let mut producer = log.producer().unwrap();
+        for tx in 1..=5 {
+            producer.send(&deposit(tx)).unwrap();
+        }
+        producer.flush().unwrap();
+
+        let mut consumer = log.consumer("engine").unwrap();
+        let first = consumer.poll(3).unwrap();
+        let rest = consumer.poll(10).unwrap();
+        let segments = log.segments().unwrap();
+        std::fs::remove_dir_all(&log.dir).unwrap();
+
+        expect_that!(segments, elements_are![eq(&0), eq(&2), eq(&4)]);
+        expect_that!(offsets(&first), elements_are![eq(&0), eq(&1), eq(&2)]);
+        expect_that!(offsets(&rest), elements_are![eq(&3), eq(&4)]);
+        expect_that!(rest[1].transaction, eq(&deposit(5)));
+    }
+
+    #[gtest]
+    pub fn resumes_from_the_committed_offset() {
+        let log = temp_log("commit");
+        let mut producer = log.producer().unwrap();
+        for tx in 1..=3 {
+            producer.send(&deposit(tx)).unwrap();
+        }
+        producer.flush().unwrap();
+
+        let mut consumer = log.consumer("engine").unwrap();
+        consumer.poll(2).unwrap();
+        consumer.commit(2).unwrap();
+        let restarted = log.consumer("engine").unwrap().poll(10).unwrap();
+        let other_group = log.consumer("audit").unwrap().poll(10).unwrap();
+        std::fs::remove_dir_all(&log.dir).unwrap();
+
+        expect_that!(offsets(&restarted), elements_are![eq(&2)]);
+        expect_that!(offsets(&other_group), elements_are![eq(&0), eq(&1), eq(&2)]);
+    }
+
+    #[gtest]
+    pub fn waits_for_partially_written_messages() {
+        let log = temp_log("partial");
+        let mut producer = log.producer().unwrap();
+        producer.send(&deposit(1)).unwrap();
+        producer.flush().unwrap();
+        let segment = log.segment_path(0);
+        OpenOptions::new()
+            .append(true)
+            .open(&segment)
+            .unwrap()
+            .write_all(br#"{"tx":2,"client":1,"#)
+            .unwrap();
+
+        let mut consumer = log.consumer("engine").unwrap();
+        let before = consumer.poll(10).unwrap();
+        let mut restarted_producer = log.producer().unwrap();
+        let offset = restarted_producer.send(&deposit(3)).unwrap();
+        restarted_producer.flush().unwrap();
+        let after = consumer.poll(10).unwrap();
+        std::fs::remove_dir_all(&log.dir).unwrap();
+
+        expect_that!(offsets(&before), elements_are![eq(&0)]);
+        expect_that!(offset, eq(1));
+        expect_that!(offsets(&after), elements_are![eq(&1)]);
+        expect_that!(after[0].transaction, eq(&deposit(3)));
+    }
+}
@@ -0,0 +1,337 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use log::{error, info};
+use serde::Serialize;
+use serde_json::Value;
+use tiny_http::{Header, Method, Request, Response};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use crate::currency::Currency;
+use crate::engine::fees::FeeLine;
+use crate::engine::subscriptions::Subscription;
+use crate::engine::{Engine, PaymentEngine};
+use crate::{ClientId, ClientSnapshot, Transaction, TransactionId, TransactionType};
+
+// How long a balance change feed goes without sending before it pings the client, to
+// notice that it went away even if none of its clients' balances change
+const FEED_PING_INTERVAL: Duration = Duration::from_secs(10);
+
+// Serves the engine over HTTP (JSON in and out):
+//
+//   POST /transactions     one transaction object or an array of them, applied in order.
+//                          Responds with the outcome of each (an object or an array).
+//   GET  /clients          snapshots of every client, in client id order
+//   GET  /clients/{id}     the client's snapshot. Clients with balances in several
+//                          currencies need `?currency=XXX`.
+//   GET  /metrics          the engine's metrics in the Prometheus text format
+//   GET  /changes          WebSocket of every client's `BalanceChange`s
+//   GET  /clients/{id}/changes
+//                          WebSocket of the client's `BalanceChange`s
+//
+// The serial engine sits behind a lock, so transactions are applied one at a time in
+// the order their requests get the lock. Reads see every transaction applied before
+// them. A batch holds the lock throughout, so it's never interleaved with other writes.
+//
+// Rejected transactions (e.g., insufficient funds) are part of a successful response;
+// only malformed requests fail, and a malformed batch is rejected as a whole.
+pub struct EngineServer {
+    server: tiny_http::Server,
+    engine: Mutex<Engine>,
+}
+
+// Outcome of a single transaction, as reported to the client
+#[derive(Debug, Serialize)]
+struct Outcome {
+    tx: TransactionId,
+    client: ClientId,
+    #[serde(rename = "type")]
+    action: TransactionType,
+    applied: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fees: Vec<FeeLine>,
+    // `TransactionProcessError::code`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Reply {
+    fn json(value: &impl Serialize) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Reply {
+                status: 200,
+                content_type: "application/json",
+                body,
+            },
+            Err(err) => Reply::error(500, err),
+        }
+    }
+
+    fn error(status: u16, error: impl ToString) -> Self {
+        Reply {
+            status,
+            content_type: "application/json",
+            body: serde_json::json!({ "error": error.to_string() }).to_string(),
+        }
+    }
+}
+
+impl EngineServer {
+    pub fn bind(address: impl ToSocketAddrs, engine: Engine) -> anyhow::Result<Self> {
+        let server = tiny_http::Server::http(address).map_err(|err| anyhow!(err))?;
+        Ok(Self {
+            server,
+            engine: Mutex::new(engine),
+        })
+    }
+
+    // Bound address, e.g., to find the port after binding to port 0
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    // Handles requests until `shutdown` is called
+    pub fn run(&self) {
+        if let Some(address) = self.local_addr() {
+            info!("Serving the engine on http://{address}");
+        }
+        for request in self.server.incoming_requests() {
+            self.respond(request);
+        }
+    }
+
+    pub fn shutdown(&self) {
+        self.server.unblock();
+    }
+
+    fn respond(&self, mut request: Request) {
+        let reply = match websocket_accept_key(&request) {
+            Some(accept) => match subscription(request.url()) {
+                Ok(subscription) => return self.stream_changes(request, &accept, subscription),
+                Err(reply) => reply,
+            },
+            None => {
+                let mut body = Vec::new();
+                match request.as_reader().read_to_end(&mut body) {
+                    Ok(_) => self.handle(request.method(), request.url(), &body),
+                    Err(err) => Reply::error(400, err),
+                }
+            }
+        };
+
+        let content_type =
+            Header::from_bytes("Content-Type", reply.content_type).expect("static header is valid");
+        let response = Response::from_string(reply.body)
+            .with_status_code(reply.status)
+            .with_header(content_type);
+        if let Err(err) = request.respond(response) {
+            error!("Failed to send response: {err}");
+        }
+    }
+
+    fn handle(&self, method: &Method, url: &str, body: &[u8]) -> Reply {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        match (method, segments(path).as_slice()) {
+            (Method::Post, ["transactions"]) => self.post_transactions(body),
+            (Method::Get, ["clients"]) => {
+                let snapshots = self.engine().snapshots();
+                let snapshots = snapshots
+                    .into_iter()
+                    .map(|s| s.snapshot)
+                    .collect::<Vec<_>>();
+                Reply::json(&snapshots)
+            }
+            (Method::Get, ["clients", id]) => self.get_client(id, query),
+            (Method::Get, ["metrics"]) => Reply {
+                status: 200,
+                content_type: "text/plain; version=0.0.4",
+                body: self.engine().metrics().render(),
+            },
+            (Method::Get, ["changes"] | ["clients", _, "changes"]) => {
+                Reply::error(426, format!("{path} only accepts WebSocket connections"))
+            }
+            (
+                _,
+                ["transactions" | "clients" | "changes" | "metrics"]
+                | ["clients", _]
+                | ["clients", _, "changes"],
+            ) => Reply::error(405, format!("{method} isn't supported on {path}")),
+            _ => Reply::error(404, format!("{path} not found")),
+        }
+    }
+
+    fn post_transactions(&self, body: &[u8]) -> Reply {
+        let (transactions, is_batch) = match parse_transactions(body) {
+            Ok(parsed) => parsed,
+            Err(err) => return Reply::error(400, err),
+        };
+
+        let mut engine = self.engine();
+        let outcomes = transactions
+            .into_iter()
+            .map(|transaction| {
+                let (tx, client, action) =
+                    (transaction.id, transaction.client_id, transaction.action);
+                let (fees, error) = match engine.process_with_outcome(transaction) {
+                    Ok(outcome) => (outcome.fees, None),
+                    Err(err) => (Vec::new(), Some(err.code())),
+                };
+                Outcome {
+                    tx,
+                    client,
+                    action,
+                    applied: error.is_none(),
+                    fees,
+                    error,
+                }
+            })
+            .collect::<Vec<_>>();
+        drop(engine);
+
+        if is_batch {
+            Reply::json(&outcomes)
+        } else {
+            Reply::json(&outcomes[0])
+        }
+    }
+
+    fn get_client(&self, id: &str, query: &str) -> Reply {
+        let client = match client_id(id) {
+            Ok(client) => client,
+            Err(reply) => return reply,
+        };
+        let currency = match query_param(query, "currency").map(str::parse::<Currency>) {
+            None => None,
+            Some(Ok(currency)) => Some(currency),
+            Some(Err(err)) => return Reply::error(400, err),
+        };
+
+        let Some(snapshots) = self.engine().client_snapshots(client) else {
+            return Reply::error(404, format!("client {client} not found"));
+        };
+        let mut matching = snapshots
+            .into_iter()
+            .map(|s| s.snapshot)
+            .filter(|s| currency.is_none() || s.currency == currency)
+            .collect::<Vec<ClientSnapshot>>();
+        match matching.len() {
+            0 => Reply::error(
+                404,
+                format!("client {client} has no balance in that currency"),
+            ),
+            1 => Reply::json(&matching.remove(0)),
+            _ => Reply::error(
+                400,
+                format!("client {client} has balances in several currencies; pass ?currency="),
+            ),
+        }
+    }
+
+    // Upgrades to a WebSocket that gets every matching `BalanceChange` as a JSON text
+    // message; messages from the client are ignored. Each connection gets its own
+    // thread, which ends once a change or ping can't be sent (e.g., the client went
+    // away) or the server is dropped. Quiet feeds are pinged every `FEED_PING_INTERVAL`,
+    // so a closed connection is noticed within a couple of pings and its subscription
+    // dropped, rather than lingering until the client's balances next change.
+    fn stream_changes(&self, request: Request, accept: &str, subscription: Subscription) {
+        let changes = self.engine().subscribe(subscription);
+        let accept =
+            Header::from_bytes("Sec-WebSocket-Accept", accept).expect("accept key is base64");
+        let stream = request.upgrade("websocket", Response::empty(101).with_header(accept));
+        std::thread::spawn(move || {
+            let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+            loop {
+                let message = match changes.recv_timeout(FEED_PING_INTERVAL) {
+                    Ok(change) => match serde_json::to_string(&change) {
+                        Ok(json) => Message::text(json),
+                        Err(err) => {
+                            error!("Failed to serialize {change:?}: {err}");
+                            continue;
+                        }
+                    },
+                    Err(RecvTimeoutError::Timeout) => Message::Ping(Default::default()),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                if let Err(err) = socket.send(message) {
+                    info!("Closing balance change feed: {err}");
+                    break;
+                }
+            }
+        });
+    }
+
+    // Keeps serving if a request panicked while holding the lock
+    fn engine(&self) -> MutexGuard<'_, Engine> {
+        self.engine
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+// Every transaction is validated before any is applied
+fn parse_transactions(body: &[u8]) -> serde_json::Result<(Vec<Transaction>, bool)> {
+    Ok(match serde_json::from_slice(body)? {
+        Value::Array(values) => (
+            values
+                .into_iter()
+                .map(serde_json::from_value)
+                .collect::<Result<_, _>>()?,
+            true,
+        ),
+        value => (vec![serde_json::from_value(value)?], false),
+    })
+}
+
+fn segments(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+fn client_id(id: &str) -> Result<ClientId, Reply> {
+    id.parse()
+        .map_err(|_| Reply::error(400, format!("`{id}` isn't a client id")))
+}
+
+// `None` unless `request` asks to be upgraded to a WebSocket
+fn websocket_accept_key(request: &Request) -> Option<String> {
+    let header = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv(name))
+            .map(|header| header.value.as_str())
+    };
+    let is_upgrade = *request.method() == Method::Get
+        && header("Upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let key = header("Sec-WebSocket-Key").filter(|_| is_upgrade)?;
+    Some(derive_accept_key(key.as_bytes()))
+}
+
+// Which changes a WebSocket on `url` receives
+fn subscription(url: &str) -> Result<Subscription, Reply> {
+    let path = url.split_once('?').map_or(url, |(path, _)| path);
+    match segments(path).as_slice() {
+        ["changes"] => Ok(Subscription::AllClients),
+        ["clients", id, "changes"] => client_id(id).map(Subscription::Client),
+        _ => Err(Reply::error(404, format!("{path} not found"))),
+    }
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
@@ -0,0 +1,213 @@
+// Drives the gRPC service over a local socket
+#![cfg(feature = "grpc")]
+
+#[cfg(test)]
+mod grpc_tests {
+    use googletest::prelude::*;
+    use payment_engine::engine::Engine;
+    use payment_engine::grpc::GrpcService;
+    use payment_engine::grpc::proto::payment_engine_client::PaymentEngineClient;
+    use payment_engine::grpc::proto::transaction_process_error::Kind;
+    use payment_engine::grpc::proto::{
+        BalanceChange, ClientSnapshot, GetClientRequest, IngestSummary, Transaction,
+        TransactionProcessError, TransactionType, WatchClientRequest,
+    };
+    use tokio::net::TcpListener;
+    use tokio_stream::StreamExt;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::Code;
+    use tonic::transport::{Channel, Server};
+
+    async fn start() -> PaymentEngineClient<Channel> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(GrpcService::new(Engine::default()).into_server())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        PaymentEngineClient::connect(format!("http://{address}"))
+            .await
+            .unwrap()
+    }
+
+    fn transaction(
+        action: TransactionType,
+        client: u32,
+        tx: u32,
+        amount: Option<&str>,
+    ) -> Transaction {
+        Transaction {
+            tx,
+            client,
+            r#type: action.into(),
+            amount: amount.map(str::to_string),
+            currency: None,
+            to_currency: None,
+        }
+    }
+
+    fn snapshot(client: u32, available: &str, held: &str, total: &str) -> ClientSnapshot {
+        ClientSnapshot {
+            client,
+            currency: None,
+            available: available.to_string(),
+            held: held.to_string(),
+            total: total.to_string(),
+            locked: false,
+            fees: None,
+        }
+    }
+
+    fn change(
+        tx: Option<u32>,
+        deltas: Option<(&str, &str)>,
+        snapshot: ClientSnapshot,
+    ) -> BalanceChange {
+        BalanceChange {
+            tx,
+            available_delta: deltas.map(|(available, _)| available.to_string()),
+            held_delta: deltas.map(|(_, held)| held.to_string()),
+            snapshot: Some(snapshot),
+        }
+    }
+
+    #[gtest]
+    #[tokio::test]
+    async fn ingests_a_stream_and_reports_rejections() {
+        let mut client = start().await;
+
+        let summary = client
+            .ingest(tokio_stream::iter([
+                transaction(TransactionType::Deposit, 1, 1, Some("3.0")),
+                transaction(TransactionType::Withdrawal, 1, 2, Some("5.0")),
+                transaction(TransactionType::Dispute, 1, 1, None),
+                transaction(TransactionType::Deposit, 2, 3, Some("1.25")),
+            ]))
+            .await
+            .unwrap()
+            .into_inner();
+        expect_that!(
+            summary,
+            eq(&IngestSummary {
+                applied: 3,
+                rejected: vec![TransactionProcessError {
+                    kind: Kind::InsufficientFunds.into(),
+                    client: 1,
+                    tx: 2,
+                }],
+            })
+        );
+
+        let response = client
+            .get_client(GetClientRequest { client: 1 })
+            .await
+            .unwrap()
+            .into_inner();
+        expect_that!(
+            response.snapshots,
+            elements_are![eq(&snapshot(1, "0.0000", "3.0000", "3.0000"))]
+        );
+    }
+
+    #[gtest]
+    #[tokio::test]
+    async fn watches_balance_changes_of_one_client() {
+        let mut client = start().await;
+        client
+            .ingest(tokio_stream::iter([transaction(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some("3.0"),
+            )]))
+            .await
+            .unwrap();
+
+        let mut changes = client
+            .watch_client(WatchClientRequest { client: 1 })
+            .await
+            .unwrap()
+            .into_inner();
+        client
+            .ingest(tokio_stream::iter([
+                transaction(TransactionType::Deposit, 2, 2, Some("9.0")),
+                transaction(TransactionType::Withdrawal, 1, 3, Some("1.0")),
+                transaction(TransactionType::Withdrawal, 1, 4, Some("100.0")),
+                transaction(TransactionType::Dispute, 1, 1, None),
+                transaction(TransactionType::Chargeback, 1, 1, None),
+            ]))
+            .await
+            .unwrap();
+
+        let mut received = Vec::new();
+        for _ in 0..4 {
+            received.push(changes.next().await.unwrap().unwrap());
+        }
+        let locked = ClientSnapshot {
+            locked: true,
+            ..snapshot(1, "-1.0000", "0.0000", "-1.0000")
+        };
+        expect_that!(
+            received,
+            elements_are![
+                eq(&change(
+                    None,
+                    None,
+                    snapshot(1, "3.0000", "0.0000", "3.0000")
+                )),
+                eq(&change(
+                    Some(3),
+                    Some(("-1.0000", "0.0000")),
+                    snapshot(1, "2.0000", "0.0000", "2.0000")
+                )),
+                eq(&change(
+                    Some(1),
+                    Some(("-3.0000", "3.0000")),
+                    snapshot(1, "-1.0000", "3.0000", "2.0000")
+                )),
+                eq(&change(Some(1), Some(("0.0000", "-3.0000")), locked)),
+            ]
+        );
+    }
+
+    #[gtest]
+    #[tokio::test]
+    async fn rejects_malformed_requests() {
+        let mut client = start().await;
+
+        let status = client
+            .ingest(tokio_stream::iter([transaction(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some("lots"),
+            )]))
+            .await
+            .unwrap_err();
+        expect_that!(status.code(), eq(Code::InvalidArgument));
+
+        let status = client
+            .ingest(tokio_stream::iter([transaction(
+                TransactionType::Unspecified,
+                1,
+                1,
+                None,
+            )]))
+            .await
+            .unwrap_err();
+        expect_that!(status.code(), eq(Code::InvalidArgument));
+
+        let status = client
+            .get_client(GetClientRequest { client: 1 })
+            .await
+            .unwrap_err();
+        expect_that!(status.code(), eq(Code::NotFound));
+
+        let status = client
+            .get_client(GetClientRequest { client: 70_000 })
+            .await
+            .unwrap_err();
+        expect_that!(status.code(), eq(Code::InvalidArgument));
+    }
+}
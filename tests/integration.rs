@@ -3,10 +3,17 @@
 
 #[cfg(test)]
 mod integration_tests {
+    use std::sync::{Arc, Mutex};
+
     use googletest::prelude::*;
     use payment_engine::{
-        ClientSnapshot, Transaction,
-        engine::{Engine, PaymentEngine},
+        ClientSnapshot, Transaction, TransactionType,
+        currency::Currency,
+        engine::{
+            Engine, EngineConfig, PaymentEngine, TransactionProcessError,
+            fees::{FeeLine, FeeSchedule},
+            observer::EngineObserver,
+        },
         parse::Amount,
     };
 
@@ -19,24 +26,32 @@ mod integration_tests {
                 client_id: 1,
                 action: payment_engine::TransactionType::Deposit,
                 amount: Amount::new(3.0).ok(),
+                currency: None,
+                to_currency: None,
             },
             Transaction {
                 id: 2,
                 client_id: 1,
                 action: payment_engine::TransactionType::Withdrawal,
                 amount: Amount::new(1.5).ok(),
+                currency: None,
+                to_currency: None,
             },
             Transaction {
                 id: 3,
                 client_id: 1,
                 action: payment_engine::TransactionType::Deposit,
                 amount: Amount::new(4.5).ok(),
+                currency: None,
+                to_currency: None,
             },
             Transaction {
                 id: 4,
                 client_id: 2,
                 action: payment_engine::TransactionType::Deposit,
                 amount: Amount::new(9.0).ok(),
+                currency: None,
+                to_currency: None,
             },
             // this will not go through because wrong client id
             Transaction {
@@ -44,6 +59,8 @@ mod integration_tests {
                 client_id: 1,
                 action: payment_engine::TransactionType::Dispute,
                 amount: None,
+                currency: None,
+                to_currency: None,
             },
             // this will not go through because wrong client id
             Transaction {
@@ -51,36 +68,48 @@ mod integration_tests {
                 client_id: 1,
                 action: payment_engine::TransactionType::Chargeback,
                 amount: None,
+                currency: None,
+                to_currency: None,
             },
             Transaction {
                 id: 2,
                 client_id: 1,
                 action: payment_engine::TransactionType::Chargeback,
                 amount: None,
+                currency: None,
+                to_currency: None,
             },
             Transaction {
                 id: 2,
                 client_id: 1,
                 action: payment_engine::TransactionType::Dispute,
                 amount: None,
+                currency: None,
+                to_currency: None,
             },
             Transaction {
                 id: 2,
                 client_id: 1,
                 action: payment_engine::TransactionType::Chargeback,
                 amount: None,
+                currency: None,
+                to_currency: None,
             },
             Transaction {
                 id: 5,
                 client_id: 1,
                 action: payment_engine::TransactionType::Deposit,
                 amount: Amount::new(100.0).ok(),
+                currency: None,
+                to_currency: None,
             },
             Transaction {
                 id: 6,
                 client_id: 1,
                 action: payment_engine::TransactionType::Withdrawal,
                 amount: Amount::new(30.0).ok(),
+                currency: None,
+                to_currency: None,
             },
         ];
 
@@ -90,19 +119,169 @@ mod integration_tests {
             unordered_elements_are!(
                 ok(eq(&ClientSnapshot {
                     client: 1,
+                    currency: None,
                     available: Amount::new(7.5).unwrap(),
                     held: Amount::new(0.0).unwrap(),
                     total: Amount::new(7.5).unwrap(),
                     locked: true,
+                    fees: None,
                 })),
                 ok(eq(&ClientSnapshot {
                     client: 2,
+                    currency: None,
                     available: Amount::new(9.0).unwrap(),
                     held: Amount::new(0.0).unwrap(),
                     total: Amount::new(9.0).unwrap(),
                     locked: false,
+                    fees: None,
+                }))
+            )
+        );
+    }
+
+    #[gtest]
+    fn multi_currency_integration() {
+        let usd = Currency::new("USD").unwrap();
+        let eur = Currency::new("EUR").unwrap();
+        let mut engine = Engine::default();
+        let transactions = [
+            Transaction::new(1, 1, TransactionType::Deposit, Amount::new(10.0).ok())
+                .in_currency(usd),
+            Transaction::new(2, 1, TransactionType::Deposit, Amount::new(4.0).ok())
+                .in_currency(eur),
+            Transaction::new(3, 1, TransactionType::Withdrawal, Amount::new(1.5).ok())
+                .in_currency(eur),
+            Transaction::new(4, 2, TransactionType::Deposit, Amount::new(2.0).ok())
+                .in_currency(usd),
+            Transaction::new(1, 1, TransactionType::Dispute, None),
+        ];
+
+        assert_that!(transactions.map(|t| engine.process(t)), each(ok(())));
+        expect_that!(
+            engine.finalize(),
+            unordered_elements_are!(
+                ok(eq(&ClientSnapshot {
+                    client: 1,
+                    currency: Some(usd),
+                    available: Amount::new(0.0).unwrap(),
+                    held: Amount::new(10.0).unwrap(),
+                    total: Amount::new(10.0).unwrap(),
+                    locked: false,
+                    fees: None,
+                })),
+                ok(eq(&ClientSnapshot {
+                    client: 1,
+                    currency: Some(eur),
+                    available: Amount::new(2.5).unwrap(),
+                    held: Amount::new(0.0).unwrap(),
+                    total: Amount::new(2.5).unwrap(),
+                    locked: false,
+                    fees: None,
+                })),
+                ok(eq(&ClientSnapshot {
+                    client: 2,
+                    currency: Some(usd),
+                    available: Amount::new(2.0).unwrap(),
+                    held: Amount::new(0.0).unwrap(),
+                    total: Amount::new(2.0).unwrap(),
+                    locked: false,
+                    fees: None,
                 }))
             )
         );
     }
+
+    // Records every hook call as a line of text
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl EngineObserver for Recorder {
+        fn on_applied(&self, transaction: &Transaction, snapshot: &ClientSnapshot) {
+            self.0.lock().unwrap().push(format!(
+                "applied {} ({} available, {} held)",
+                transaction.id, snapshot.available, snapshot.held
+            ));
+        }
+
+        fn on_rejected(&self, transaction: &Transaction, error: &TransactionProcessError) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("rejected {}: {error:?}", transaction.id));
+        }
+
+        fn on_fee(&self, fee: &FeeLine) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("fee {} {:?} {}", fee.tx, fee.kind, fee.amount));
+        }
+
+        fn on_locked(&self, client: u16) {
+            self.0.lock().unwrap().push(format!("locked {client}"));
+        }
+    }
+
+    #[gtest]
+    fn observers_see_every_transaction() {
+        let mut engine = Engine::default();
+        let recorder = Arc::new(Recorder::default());
+        engine.register_observer(recorder.clone());
+        let transactions = [
+            Transaction::new(1, 1, TransactionType::Deposit, Amount::new(3.0).ok()),
+            Transaction::new(2, 1, TransactionType::Withdrawal, Amount::new(5.0).ok()),
+            Transaction::new(1, 1, TransactionType::Dispute, None),
+            Transaction::new(1, 1, TransactionType::Chargeback, None),
+            Transaction::new(3, 1, TransactionType::Deposit, Amount::new(1.0).ok()),
+        ];
+
+        assert_that!(transactions.map(|t| engine.process(t)), each(ok(())));
+        // Stream workers are done once finalized
+        let _ = engine.finalize();
+        expect_that!(
+            *recorder.0.lock().unwrap(),
+            elements_are![
+                eq("applied 1 (3.0000 available, 0.0000 held)"),
+                eq("rejected 2: InsufficientFunds(1, 2)"),
+                eq("applied 1 (0.0000 available, 3.0000 held)"),
+                eq("applied 1 (0.0000 available, 0.0000 held)"),
+                eq("locked 1"),
+                eq("rejected 3: ClientLocked(1, 3)"),
+            ]
+        );
+    }
+
+    #[gtest]
+    fn observers_see_fee_lines() {
+        let mut engine = Engine::with_config(EngineConfig {
+            fee_schedule: FeeSchedule {
+                deposit_flat: Amount::new(0.5).unwrap(),
+                chargeback_flat: Amount::new(2.0).unwrap(),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let recorder = Arc::new(Recorder::default());
+        engine.register_observer(recorder.clone());
+        let transactions = [
+            Transaction::new(1, 1, TransactionType::Deposit, Amount::new(3.0).ok()),
+            Transaction::new(1, 1, TransactionType::Dispute, None),
+            Transaction::new(1, 1, TransactionType::Chargeback, None),
+        ];
+
+        assert_that!(transactions.map(|t| engine.process(t)), each(ok(())));
+        let _ = engine.finalize();
+        expect_that!(
+            *recorder.0.lock().unwrap(),
+            elements_are![
+                eq("applied 1 (2.5000 available, 0.0000 held)"),
+                eq("fee 1 Deposit 0.5000"),
+                // The dispute holds the deposit, not what's left after its fee
+                eq("applied 1 (-0.5000 available, 3.0000 held)"),
+                eq("applied 1 (-2.5000 available, 0.0000 held)"),
+                eq("fee 1 Chargeback 2.0000"),
+                eq("locked 1"),
+            ]
+        );
+    }
 }
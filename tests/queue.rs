@@ -0,0 +1,139 @@
+// Consumes a local segment-file queue across simulated restarts
+#![cfg(not(feature = "stream"))]
+
+#[cfg(test)]
+mod queue_tests {
+    use std::path::PathBuf;
+
+    use googletest::prelude::*;
+    use payment_engine::engine::{Engine, EngineConfig, PaymentEngine, fees::FeeSchedule};
+    use payment_engine::parse::Amount;
+    use payment_engine::queue::{self, Checkpoint, QueueConsumer, SegmentLog};
+    use payment_engine::{ExtendedClientSnapshot, Transaction, TransactionType};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "payment-engine-queue-test-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn config() -> EngineConfig {
+        EngineConfig {
+            fee_schedule: FeeSchedule {
+                deposit_flat: Amount::new(0.25).unwrap(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    // Deposits, withdrawals and disputes whose effects double up if any is applied twice
+    fn transactions() -> Vec<Transaction> {
+        let mut transactions = Vec::new();
+        for tx in 1..=30 {
+            let client = (tx % 4) as u16;
+            let amount = Amount::new(f64::from(tx)).ok();
+            let action = if tx % 3 == 0 {
+                TransactionType::Withdrawal
+            } else {
+                TransactionType::Deposit
+            };
+            transactions.push(Transaction::new(tx, client, action, amount));
+            if tx % 5 == 0 {
+                transactions.push(Transaction::new(
+                    tx - 1,
+                    client,
+                    TransactionType::Dispute,
+                    None,
+                ));
+            }
+        }
+        transactions
+    }
+
+    fn expected_snapshots() -> Vec<ExtendedClientSnapshot> {
+        let mut engine = Engine::with_config(config());
+        for transaction in transactions() {
+            engine.process(transaction).unwrap();
+        }
+        engine.snapshots()
+    }
+
+    fn enqueue(log: &SegmentLog, transactions: &[Transaction]) {
+        let mut producer = log.producer().unwrap();
+        for transaction in transactions {
+            producer.send(transaction).unwrap();
+        }
+        producer.flush().unwrap();
+    }
+
+    #[gtest]
+    fn resumes_from_the_last_committed_offset() {
+        let dir = temp_dir("resume");
+        let log = SegmentLog::open(&dir).unwrap().with_segment_size(7);
+        let checkpoint = dir.join("engine.checkpoint.json");
+        let transactions = transactions();
+        let (first, second) = transactions.split_at(17);
+
+        enqueue(&log, first);
+        let mut consumer = log.consumer("engine").unwrap();
+        let mut engine = queue::resume(config(), &mut consumer, &checkpoint).unwrap();
+        let first_run = queue::consume(&mut engine, &mut consumer, &checkpoint, 5).unwrap();
+        let first_committed = consumer.committed();
+        drop((engine, consumer));
+
+        enqueue(&log, second);
+        let mut consumer = log.consumer("engine").unwrap();
+        let mut engine = queue::resume(config(), &mut consumer, &checkpoint).unwrap();
+        let second_run = queue::consume(&mut engine, &mut consumer, &checkpoint, 5).unwrap();
+        let snapshots = engine.snapshots();
+        let committed = consumer.committed();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        expect_that!(first_run, eq(17));
+        expect_that!(first_committed, eq(17));
+        expect_that!(second_run, eq(second.len() as u64));
+        expect_that!(committed, eq(transactions.len() as u64));
+        expect_that!(snapshots, eq(&expected_snapshots()));
+    }
+
+    #[gtest]
+    fn crashing_between_checkpoint_and_commit_applies_nothing_twice() {
+        let dir = temp_dir("crash");
+        let log = SegmentLog::open(&dir).unwrap();
+        let checkpoint = dir.join("engine.checkpoint.json");
+        enqueue(&log, &transactions());
+
+        // Checkpointed after 12 messages, but "crashed" before committing them, then
+        // applied 6 more that never made it into a checkpoint
+        let mut consumer = log.consumer("engine").unwrap();
+        let mut engine = Engine::with_config(config());
+        for message in consumer.poll(12).unwrap() {
+            engine.process(message.transaction).unwrap();
+        }
+        Checkpoint {
+            offset: 12,
+            state: engine.state().unwrap(),
+        }
+        .save(&checkpoint)
+        .unwrap();
+        for message in consumer.poll(6).unwrap() {
+            engine.process(message.transaction).unwrap();
+        }
+        drop((engine, consumer));
+
+        let mut consumer = log.consumer("engine").unwrap();
+        let committed_before_restart = consumer.committed();
+        let mut engine = queue::resume(config(), &mut consumer, &checkpoint).unwrap();
+        let consumed = queue::consume(&mut engine, &mut consumer, &checkpoint, 100).unwrap();
+        let snapshots = engine.snapshots();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        expect_that!(committed_before_restart, eq(0));
+        expect_that!(consumed, eq(transactions().len() as u64 - 12));
+        expect_that!(snapshots, eq(&expected_snapshots()));
+    }
+}
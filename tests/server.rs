@@ -0,0 +1,241 @@
+// Drives `serve` mode over a local socket
+#![cfg(not(feature = "stream"))]
+
+#[cfg(test)]
+mod server_tests {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::Arc;
+    use std::thread::JoinHandle;
+
+    use googletest::prelude::*;
+    use payment_engine::engine::{Engine, EngineConfig, fees::FeeSchedule};
+    use payment_engine::parse::Amount;
+    use payment_engine::server::EngineServer;
+    use serde_json::{Value, json};
+
+    struct TestServer {
+        server: Arc<EngineServer>,
+        thread: Option<JoinHandle<()>>,
+        address: SocketAddr,
+    }
+
+    impl TestServer {
+        fn start(engine: Engine) -> Self {
+            let server = Arc::new(EngineServer::bind("127.0.0.1:0", engine).unwrap());
+            let address = server.local_addr().unwrap();
+            let thread = std::thread::spawn({
+                let server = Arc::clone(&server);
+                move || server.run()
+            });
+            Self {
+                server,
+                thread: Some(thread),
+                address,
+            }
+        }
+
+        // Sends one HTTP/1.1 request and returns the response's status and body
+        fn send(&self, method: &str, path: &str, body: &str) -> (u16, String) {
+            let mut stream = TcpStream::connect(self.address).unwrap();
+            write!(
+                stream,
+                "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                 Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+            (status, body.to_string())
+        }
+
+        fn request(&self, method: &str, path: &str, body: &str) -> (u16, Value) {
+            let (status, body) = self.send(method, path, body);
+            (status, serde_json::from_str(&body).unwrap())
+        }
+
+        fn post(&self, body: &str) -> (u16, Value) {
+            self.request("POST", "/transactions", body)
+        }
+
+        fn get(&self, path: &str) -> (u16, Value) {
+            self.request("GET", path, "")
+        }
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            self.server.shutdown();
+            if let Some(thread) = self.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+
+    #[gtest]
+    fn applies_single_and_batched_transactions() {
+        let server = TestServer::start(Engine::default());
+
+        expect_that!(
+            server.post(r#"{"type":"deposit","client":1,"tx":1,"amount":3.0}"#),
+            eq(&(
+                200,
+                json!({"tx": 1, "client": 1, "type": "deposit", "applied": true})
+            ))
+        );
+        expect_that!(
+            server.post(
+                r#"[{"type":"withdrawal","client":1,"tx":2,"amount":5.0},
+                    {"type":"withdrawal","client":1,"tx":3,"amount":1.5},
+                    {"type":"deposit","client":2,"tx":4,"amount":2.0}]"#
+            ),
+            eq(&(
+                200,
+                json!([
+                    {"tx": 2, "client": 1, "type": "withdrawal", "applied": false,
+                     "error": "insufficient_funds"},
+                    {"tx": 3, "client": 1, "type": "withdrawal", "applied": true},
+                    {"tx": 4, "client": 2, "type": "deposit", "applied": true},
+                ])
+            ))
+        );
+
+        expect_that!(
+            server.get("/clients/1"),
+            eq(&(
+                200,
+                json!({"client": 1, "available": 1.5, "held": 0.0, "total": 1.5, "locked": false})
+            ))
+        );
+        expect_that!(
+            server.get("/clients"),
+            eq(&(
+                200,
+                json!([
+                    {"client": 1, "available": 1.5, "held": 0.0, "total": 1.5, "locked": false},
+                    {"client": 2, "available": 2.0, "held": 0.0, "total": 2.0, "locked": false},
+                ])
+            ))
+        );
+    }
+
+    #[gtest]
+    fn reports_fees_and_disputes() {
+        let engine = Engine::with_config(EngineConfig {
+            fee_schedule: FeeSchedule {
+                deposit_flat: Amount::new(0.5).unwrap(),
+                ..FeeSchedule::default()
+            },
+            ..EngineConfig::default()
+        });
+        let server = TestServer::start(engine);
+
+        let (status, outcome) = server.post(r#"{"type":"deposit","client":1,"tx":1,"amount":3.0}"#);
+        expect_that!(status, eq(200));
+        expect_that!(outcome["applied"], eq(&json!(true)));
+        expect_that!(outcome["fees"].as_array().map(Vec::len), some(eq(1)));
+
+        server.post(r#"{"type":"dispute","client":1,"tx":1}"#);
+        expect_that!(
+            server.get("/clients/1"),
+            eq(&(
+                200,
+                json!({"client": 1, "available": -0.5, "held": 3.0, "total": 2.5,
+                       "locked": false, "fees": 0.5})
+            ))
+        );
+    }
+
+    #[gtest]
+    fn rejects_malformed_batches_as_a_whole() {
+        let server = TestServer::start(Engine::default());
+
+        let (status, body) = server.post(
+            r#"[{"type":"deposit","client":1,"tx":1,"amount":3.0},
+                {"type":"teleport","client":1,"tx":2}]"#,
+        );
+        expect_that!(status, eq(400));
+        expect_that!(body["error"].as_str(), some(contains_substring("teleport")));
+
+        let (status, _) = server.post("not json");
+        expect_that!(status, eq(400));
+
+        // Nothing from the batch was applied
+        expect_that!(server.get("/clients/1").0, eq(404));
+        expect_that!(server.get("/clients"), eq(&(200, json!([]))));
+    }
+
+    #[gtest]
+    fn streams_balance_changes_over_websockets() {
+        let server = TestServer::start(Engine::default());
+        let (mut changes, _) = tungstenite::client(
+            format!("ws://{}/clients/1/changes", server.address),
+            TcpStream::connect(server.address).unwrap(),
+        )
+        .unwrap();
+
+        server.post(
+            r#"[{"type":"deposit","client":1,"tx":1,"amount":3.0},
+                {"type":"deposit","client":2,"tx":2,"amount":1.0},
+                {"type":"dispute","client":1,"tx":1}]"#,
+        );
+
+        let mut next = || {
+            let text = changes.read().unwrap().into_text().unwrap();
+            serde_json::from_str::<Value>(&text).unwrap()
+        };
+        expect_that!(
+            next(),
+            eq(
+                &json!({"tx": 1, "available_delta": 3.0, "held_delta": 0.0, "client": 1,
+                       "available": 3.0, "held": 0.0, "total": 3.0, "locked": false})
+            )
+        );
+        expect_that!(
+            next(),
+            eq(
+                &json!({"tx": 1, "available_delta": -3.0, "held_delta": 3.0, "client": 1,
+                       "available": 0.0, "held": 3.0, "total": 3.0, "locked": false})
+            )
+        );
+
+        // Plain requests can't subscribe
+        expect_that!(server.get("/changes").0, eq(426));
+        expect_that!(server.get("/clients/1/changes").0, eq(426));
+    }
+
+    #[gtest]
+    fn exposes_metrics() {
+        let server = TestServer::start(Engine::default());
+        server.post(
+            r#"[{"type":"deposit","client":1,"tx":1,"amount":3.0},
+                {"type":"withdrawal","client":1,"tx":2,"amount":5.0}]"#,
+        );
+
+        let (status, metrics) = server.send("GET", "/metrics", "");
+        expect_that!(status, eq(200));
+        for line in [
+            "payment_engine_transactions_total{type=\"deposit\"} 1\n",
+            "payment_engine_transactions_total{type=\"withdrawal\"} 1\n",
+            "payment_engine_rejections_total{error=\"insufficient_funds\"} 1\n",
+            "payment_engine_process_seconds_count 2\n",
+        ] {
+            expect_that!(metrics, contains_substring(line));
+        }
+    }
+
+    #[gtest]
+    fn rejects_unknown_routes_and_client_ids() {
+        let server = TestServer::start(Engine::default());
+
+        expect_that!(server.get("/clients/abc").0, eq(400));
+        expect_that!(server.get("/clients/70000").0, eq(400));
+        expect_that!(server.get("/accounts").0, eq(404));
+        expect_that!(server.request("DELETE", "/clients/1", "").0, eq(405));
+        expect_that!(server.get("/transactions").0, eq(405));
+    }
+}